@@ -0,0 +1,163 @@
+//! Storage throughput at different history sizes.
+//!
+//! Run with `cargo bench --bench storage`. Each size gets a fresh in-memory
+//! database, so results measure the query plan rather than disk speed.
+
+// The binary crate has no library target; compile the modules directly
+#[allow(dead_code, unused_imports)]
+#[path = "../src/config.rs"]
+mod config;
+#[allow(dead_code, unused_imports)]
+#[path = "../src/storage/mod.rs"]
+mod storage;
+#[allow(dead_code, unused_imports)]
+#[path = "../src/content_hash.rs"]
+mod content_hash;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use storage::models::{ClipboardContentType, ClipboardEntry, ClipboardSearchQuery};
+use storage::{ClipboardStorage, Storage};
+use tokio::runtime::Runtime;
+
+const HISTORY_SIZES: &[usize] = &[100, 1_000, 10_000];
+
+fn entry(i: usize) -> ClipboardEntry {
+    let source = ["macos", "nixos"][i % 2];
+    ClipboardEntry::new(
+        ClipboardContentType::Text,
+        format!("clipboard entry {} with some surrounding text", i),
+        source.to_string(),
+    )
+}
+
+/// A database already holding `size` entries
+fn populated(rt: &Runtime, size: usize) -> ClipboardStorage {
+    rt.block_on(async {
+        // Room to keep growing during the insert benchmark without eviction
+        let storage = ClipboardStorage::in_memory(usize::MAX / 2, 1).await.unwrap();
+        for i in 0..size {
+            storage.insert(&entry(i)).await.unwrap();
+        }
+        storage
+    })
+}
+
+fn bench_insert(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("insert");
+
+    for &size in HISTORY_SIZES {
+        let storage = populated(&rt, size);
+
+        // New content takes the dedup lookup and then the INSERT
+        let mut next = size;
+        group.bench_with_input(BenchmarkId::new("new", size), &size, |b, _| {
+            b.to_async(&rt).iter(|| {
+                next += 1;
+                let entry = entry(next);
+                let storage = &storage;
+                async move { storage.insert(&entry).await.unwrap() }
+            })
+        });
+
+        // Repeated content only bumps the timestamp of the existing row; the
+        // recent-checksum cache skips the dedup lookup
+        let existing = entry(0);
+        group.bench_with_input(BenchmarkId::new("duplicate", size), &size, |b, _| {
+            b.to_async(&rt)
+                .iter(|| async { storage.insert(&existing).await.unwrap() })
+        });
+
+        // Cycling through more entries than the cache holds (64) misses it
+        // every time, so this is the cost of the lookup the cache saves
+        let older: Vec<_> = (0..size).map(entry).collect();
+        let mut cycle = older.iter().cycle();
+        group.bench_with_input(BenchmarkId::new("duplicate_uncached", size), &size, |b, _| {
+            b.to_async(&rt).iter(|| {
+                let entry = cycle.next().unwrap();
+                let storage = &storage;
+                async move { storage.insert(entry).await.unwrap() }
+            })
+        });
+    }
+
+    group.finish();
+}
+
+fn bench_search(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("search");
+
+    for &size in HISTORY_SIZES {
+        let storage = populated(&rt, size);
+
+        let recent = ClipboardSearchQuery {
+            limit: 20,
+            ..Default::default()
+        };
+        group.bench_with_input(BenchmarkId::new("recent", size), &recent, |b, query| {
+            b.to_async(&rt)
+                .iter(|| async { storage.search(query).await.unwrap() })
+        });
+
+        let filtered = ClipboardSearchQuery {
+            content_type: Some(ClipboardContentType::Text),
+            source: Some("nixos".to_string()),
+            search_text: Some("entry 9".to_string()),
+            limit: 20,
+            ..Default::default()
+        };
+        group.bench_with_input(BenchmarkId::new("filtered", size), &filtered, |b, query| {
+            b.to_async(&rt)
+                .iter(|| async { storage.search(query).await.unwrap() })
+        });
+
+        group.bench_with_input(BenchmarkId::new("get_latest", size), &size, |b, _| {
+            b.to_async(&rt)
+                .iter(|| async { storage.get_latest().await.unwrap() })
+        });
+    }
+
+    group.finish();
+}
+
+fn bench_import(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("import");
+
+    for &count in &[100, 1_000] {
+        let storage = populated(&rt, 0);
+
+        // Fresh content every iteration, so nothing is skipped as a duplicate
+        let mut next = 0;
+        let mut batch = || {
+            next += count;
+            (next..next + count).map(entry).collect::<Vec<_>>()
+        };
+
+        group.bench_with_input(BenchmarkId::new("one_by_one", count), &count, |b, _| {
+            b.to_async(&rt).iter(|| {
+                let entries = batch();
+                let storage = &storage;
+                async move {
+                    for entry in &entries {
+                        storage.insert(entry).await.unwrap();
+                    }
+                }
+            })
+        });
+
+        group.bench_with_input(BenchmarkId::new("batch", count), &count, |b, _| {
+            b.to_async(&rt).iter(|| {
+                let entries = batch();
+                let storage = &storage;
+                async move { storage.insert_batch(&entries).await.unwrap() }
+            })
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_insert, bench_search, bench_import);
+criterion_main!(benches);
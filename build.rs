@@ -0,0 +1,35 @@
+// Embeds build metadata for `clippy version`
+use std::process::Command;
+
+fn main() {
+    // Builds without a git checkout (e.g. Nix) can pass the commit in
+    println!("cargo:rerun-if-env-changed=CLIPPY_GIT_COMMIT");
+    let commit = std::env::var("CLIPPY_GIT_COMMIT")
+        .ok()
+        .filter(|c| !c.is_empty())
+        .or_else(git_commit)
+        .unwrap_or_else(|| "unknown".to_string());
+    println!("cargo:rustc-env=CLIPPY_GIT_COMMIT={}", commit);
+
+    let target = std::env::var("TARGET").unwrap_or_else(|_| "unknown".to_string());
+    println!("cargo:rustc-env=CLIPPY_TARGET={}", target);
+}
+
+fn git_commit() -> Option<String> {
+    // Rebuild when HEAD moves: the file itself on a detached HEAD, the
+    // branch ref it points at otherwise
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    if let Ok(head) = std::fs::read_to_string(".git/HEAD") {
+        if let Some(reference) = head.trim().strip_prefix("ref: ") {
+            println!("cargo:rerun-if-changed=.git/{}", reference);
+        }
+    }
+
+    let output = Command::new("git")
+        .args(["rev-parse", "--short=12", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())?;
+    let commit = String::from_utf8(output.stdout).ok()?.trim().to_string();
+    (!commit.is_empty()).then_some(commit)
+}
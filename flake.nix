@@ -47,6 +47,9 @@
             lockFile = ./Cargo.lock;
           };
 
+          # No .git in the build sandbox; embedded in `clippy version`
+          CLIPPY_GIT_COMMIT = self.shortRev or self.dirtyShortRev or "unknown";
+
           nativeBuildInputs = with pkgs; [
             pkg-config
           ];
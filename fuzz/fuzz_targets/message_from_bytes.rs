@@ -0,0 +1,22 @@
+//! Feed arbitrary bytes to `Message::from_bytes`, the parser for everything
+//! a peer sends over TCP. It must return `Ok` or `Err`, never panic.
+//!
+//! Run with `cargo +nightly fuzz run message_from_bytes` from the repo root.
+
+#![no_main]
+
+// The binary crate has no library target; compile the module directly
+#[allow(dead_code)]
+#[path = "../../src/sync/protocol.rs"]
+mod protocol;
+
+use libfuzzer_sys::fuzz_target;
+use protocol::Message;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok((message, size)) = Message::from_bytes(data) {
+        assert!(size <= data.len());
+        // Received updates are decompressed next; that must not panic either
+        let _ = message.decompressed(1024 * 1024);
+    }
+});
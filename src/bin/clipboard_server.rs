@@ -1,38 +1,65 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
 use axum::{
-    extract::State,
-    http::StatusCode,
+    extract::{Path, Query, State},
+    http::{header, StatusCode},
     response::{IntoResponse, Response},
     routing::{get, post},
     Json, Router,
 };
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
 use std::sync::Arc;
-use tokio::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::{watch, Mutex, Semaphore};
 use tower_http::cors::CorsLayer;
 use tower_http::trace::TraceLayer;
 use tracing::info;
 
+// Shared with the main binary, which has no library crate to import from;
+// only advertising is used here
+#[cfg(feature = "discovery")]
+#[allow(dead_code)]
+#[path = "../discovery.rs"]
+mod discovery;
+
+#[path = "../content_hash.rs"]
+mod content_hash;
+
 // Configuration
 const DEFAULT_PORT: u16 = 8080;
 const DEFAULT_HOST: &str = "0.0.0.0";
 const MAX_CLIPBOARD_SIZE: usize = 10 * 1024 * 1024; // 10MB
 const MAX_HISTORY_ITEMS: usize = 100;
+const MAX_LONG_POLL_SECS: u64 = 30;
+const DEFAULT_MAX_SUBSCRIBERS: usize = 64; // Long-poll requests waiting at once
+const CONTENT_TYPES: &[&str] = &["text", "image", "html", "other"];
+/// Version written to the persisted history file. 1 was a bare JSON array
+/// of items, some without a `content_type`; 2 wraps them with this field.
+const STATE_VERSION: u32 = 2;
 
 // Data Models
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct ClipboardItem {
     id: u64,
     content: String, // Base64-encoded
-    hash: String,    // MD5 hash for deduplication
+    hash: String,    // SHA-256 of the decoded content, for deduplication
     timestamp: DateTime<Utc>,
     size: usize,
+    #[serde(default = "default_content_type")]
+    content_type: String, // "text", "image", "html" or "other", as declared by the sender
+}
+
+/// Type of items whose sender did not declare one
+fn default_content_type() -> String {
+    "text".to_string()
 }
 
 #[derive(Debug, Deserialize)]
 struct SubmitClipboardRequest {
     content: String, // Base64-encoded clipboard data
+    #[serde(default)]
+    content_type: Option<String>,
 }
 
 #[derive(Debug, Serialize)]
@@ -40,6 +67,7 @@ struct SubmitClipboardResponse {
     id: u64,
     hash: String,
     timestamp: DateTime<Utc>,
+    content_type: String,
 }
 
 #[derive(Debug, Serialize)]
@@ -49,6 +77,7 @@ struct LatestClipboardResponse {
     hash: String,
     timestamp: DateTime<Utc>,
     size: usize,
+    content_type: String,
 }
 
 #[derive(Debug, Serialize)]
@@ -57,11 +86,19 @@ struct HistoryResponse {
     total: usize,
 }
 
+#[derive(Debug, Deserialize)]
+struct WaitQuery {
+    since_id: u64,
+    timeout_secs: Option<u64>,
+}
+
 #[derive(Debug, Serialize)]
 struct HealthResponse {
     status: String,
     items_count: usize,
     uptime_seconds: u64,
+    instance_id: String, // New on every start, so clients can tell a restart happened
+    next_id: u64,
 }
 
 // Application State
@@ -69,6 +106,11 @@ struct HealthResponse {
 struct AppState {
     storage: Arc<Mutex<ClipboardStorage>>,
     start_time: DateTime<Utc>,
+    instance_id: String,
+    latest_id: Arc<watch::Sender<u64>>, // Wakes long-poll waiters on submit
+    waiters: Arc<Semaphore>,
+    allowed_types: Option<Arc<[String]>>, // None accepts every type
+    history_file: Option<Arc<PathBuf>>,   // History is kept in memory only when unset
 }
 
 struct ClipboardStorage {
@@ -76,6 +118,13 @@ struct ClipboardStorage {
     next_id: u64,
 }
 
+/// The persisted history file, in its current version
+#[derive(Serialize, Deserialize)]
+struct PersistedState {
+    version: u32,
+    items: Vec<ClipboardItem>,
+}
+
 impl ClipboardStorage {
     fn new() -> Self {
         Self {
@@ -84,8 +133,8 @@ impl ClipboardStorage {
         }
     }
 
-    fn add_item(&mut self, content: String) -> ClipboardItem {
-        let hash = format!("{:x}", md5::compute(&content));
+    fn add_item(&mut self, content: String, content_type: String) -> ClipboardItem {
+        let hash = hash_item_content(&content);
         let timestamp = Utc::now();
         let size = content.len();
 
@@ -95,6 +144,7 @@ impl ClipboardStorage {
             hash,
             timestamp,
             size,
+            content_type,
         };
 
         self.items.push(item.clone());
@@ -108,10 +158,94 @@ impl ClipboardStorage {
         item
     }
 
+    /// Rebuild storage from previously saved items, recomputing hashes made
+    /// by an older algorithm so duplicates of them are still recognized
+    fn from_items(mut items: Vec<ClipboardItem>) -> Self {
+        let mut migrated = 0;
+        for item in &mut items {
+            if !is_current_hash(&item.hash) {
+                item.hash = hash_item_content(&item.content);
+                migrated += 1;
+            }
+        }
+        if migrated > 0 {
+            info!("Recomputed {} stored hashes with the current algorithm", migrated);
+        }
+
+        let next_id = items.iter().map(|item| item.id).max().unwrap_or(0) + 1;
+        Self { items, next_id }
+    }
+
+    /// Serialize the history in the current persisted format
+    fn to_state_json(&self) -> Result<String> {
+        let state = PersistedState {
+            version: STATE_VERSION,
+            items: self.items.clone(),
+        };
+        Ok(serde_json::to_string(&state)?)
+    }
+
+    /// Load a persisted history file, migrating older versions forward and
+    /// refusing ones written by a newer server
+    fn from_state_json(json: &str) -> Result<Self> {
+        let value: serde_json::Value =
+            serde_json::from_str(json).context("history file is not valid JSON")?;
+
+        let items = match value {
+            // v1: items as returned by the history endpoint; missing types
+            // default to text when deserialized
+            serde_json::Value::Array(_) => {
+                info!("Migrating history file from version 1 to {}", STATE_VERSION);
+                serde_json::from_value(value).context("invalid version 1 history file")?
+            }
+            _ => {
+                let version = value.get("version").and_then(|v| v.as_u64());
+                match version {
+                    Some(v) if v == u64::from(STATE_VERSION) => {
+                        let state: PersistedState = serde_json::from_value(value)
+                            .with_context(|| format!("invalid version {} history file", v))?;
+                        state.items
+                    }
+                    Some(v) if v > u64::from(STATE_VERSION) => anyhow::bail!(
+                        "history file is version {}, but this server only reads up to {}; upgrade clipboard_server or move the file aside",
+                        v,
+                        STATE_VERSION
+                    ),
+                    _ => anyhow::bail!("history file has no recognized version"),
+                }
+            }
+        };
+
+        Ok(Self::from_items(items))
+    }
+
+    /// Load the history file at `path`, or start empty if there is none yet
+    fn load(path: &std::path::Path) -> Result<Self> {
+        match std::fs::read_to_string(path) {
+            Ok(json) => Self::from_state_json(&json)
+                .with_context(|| format!("Failed to load history file {}", path.display())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::new()),
+            Err(e) => Err(e).with_context(|| format!("Failed to read history file {}", path.display())),
+        }
+    }
+
+    /// Write the history to `path`, via a temporary file so a crash mid-write
+    /// leaves the previous version intact
+    async fn save(&self, path: &std::path::Path) -> Result<()> {
+        let tmp = path.with_extension("tmp");
+        tokio::fs::write(&tmp, self.to_state_json()?).await?;
+        tokio::fs::rename(&tmp, path).await?;
+        Ok(())
+    }
+
     fn get_latest(&self) -> Option<ClipboardItem> {
         self.items.last().cloned()
     }
 
+    fn get_by_id(&self, id: u64) -> Option<ClipboardItem> {
+        self.items.iter().find(|item| item.id == id).cloned()
+    }
+
     fn get_all(&self) -> Vec<ClipboardItem> {
         self.items.clone()
     }
@@ -121,11 +255,28 @@ impl ClipboardStorage {
     }
 }
 
+/// Whether `hash` came from `content_hash`. Hashes from older releases
+/// (MD5, 32 hex digits) are not and need recomputing.
+fn is_current_hash(hash: &str) -> bool {
+    hash.len() == 64 && hash.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+/// Hash of an item's decoded bytes, matching what the sync client computes
+fn hash_item_content(content: &str) -> String {
+    use base64::Engine;
+    match base64::engine::general_purpose::STANDARD.decode(content) {
+        Ok(bytes) => content_hash::content_hash(&bytes),
+        Err(_) => content_hash::content_hash(content.as_bytes()),
+    }
+}
+
 // Error handling
 enum AppError {
     ContentTooLarge,
     EmptyContent,
     InvalidBase64,
+    UnknownContentType(String),
+    DisallowedContentType(String, Arc<[String]>),
 }
 
 impl IntoResponse for AppError {
@@ -137,12 +288,41 @@ impl IntoResponse for AppError {
             ),
             AppError::EmptyContent => (StatusCode::BAD_REQUEST, "Content cannot be empty".to_string()),
             AppError::InvalidBase64 => (StatusCode::BAD_REQUEST, "Invalid base64 content".to_string()),
+            AppError::UnknownContentType(content_type) => (
+                StatusCode::BAD_REQUEST,
+                format!("Unknown content type: {}", content_type),
+            ),
+            AppError::DisallowedContentType(content_type, allowed) => (
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                format!(
+                    "Content type {} is not accepted by this server (allowed: {})",
+                    content_type,
+                    allowed.join(", ")
+                ),
+            ),
         };
 
         (status, Json(serde_json::json!({ "error": message }))).into_response()
     }
 }
 
+/// Parse a comma-separated `CLIPBOARD_SERVER_ALLOWED_TYPES`; empty allows all
+fn parse_allowed_types(value: &str) -> Result<Option<Arc<[String]>>> {
+    let types: Vec<String> = value
+        .split(',')
+        .map(|t| t.trim().to_lowercase())
+        .filter(|t| !t.is_empty())
+        .collect();
+    if let Some(unknown) = types.iter().find(|t| !CONTENT_TYPES.contains(&t.as_str())) {
+        anyhow::bail!(
+            "Unknown content type {:?} in CLIPBOARD_SERVER_ALLOWED_TYPES (expected {})",
+            unknown,
+            CONTENT_TYPES.join(", ")
+        );
+    }
+    Ok((!types.is_empty()).then(|| types.into()))
+}
+
 // API Handlers
 async fn health_check(State(state): State<AppState>) -> Json<HealthResponse> {
     let storage = state.storage.lock().await;
@@ -152,6 +332,8 @@ async fn health_check(State(state): State<AppState>) -> Json<HealthResponse> {
         status: "healthy".to_string(),
         items_count: storage.count(),
         uptime_seconds: uptime,
+        instance_id: state.instance_id.clone(),
+        next_id: storage.next_id,
     })
 }
 
@@ -174,20 +356,38 @@ async fn submit_clipboard(
         return Err(AppError::InvalidBase64);
     }
 
+    let content_type = payload.content_type.unwrap_or_else(default_content_type);
+    if !CONTENT_TYPES.contains(&content_type.as_str()) {
+        return Err(AppError::UnknownContentType(content_type));
+    }
+    if let Some(allowed) = &state.allowed_types {
+        if !allowed.contains(&content_type) {
+            return Err(AppError::DisallowedContentType(content_type, Arc::clone(allowed)));
+        }
+    }
+
     let mut storage = state.storage.lock().await;
-    let item = storage.add_item(payload.content);
+    let item = storage.add_item(payload.content, content_type);
+    state.latest_id.send_replace(item.id);
+    if let Some(path) = &state.history_file {
+        // The item is still served from memory if this fails
+        if let Err(e) = storage.save(path).await {
+            tracing::warn!("Failed to save history to {}: {:#}", path.display(), e);
+        }
+    }
 
     info!(
         "New clipboard item: id={}, size={}, hash={}",
         item.id,
         item.size,
-        &item.hash[..8]
+        content_hash::short_checksum(&item.hash)
     );
 
     Ok(Json(SubmitClipboardResponse {
         id: item.id,
         hash: item.hash,
         timestamp: item.timestamp,
+        content_type: item.content_type,
     }))
 }
 
@@ -201,11 +401,80 @@ async fn get_latest(State(state): State<AppState>) -> Result<Json<LatestClipboar
             hash: item.hash,
             timestamp: item.timestamp,
             size: item.size,
+            content_type: item.content_type,
         })),
         None => Err(StatusCode::NOT_FOUND),
     }
 }
 
+/// Hold the request until an item newer than `since_id` exists, then return
+/// the latest item like `/api/clipboard/latest`; 204 if none arrives in time
+async fn wait_for_clipboard(
+    State(state): State<AppState>,
+    Query(query): Query<WaitQuery>,
+) -> Result<Response, StatusCode> {
+    let _permit = state
+        .waiters
+        .clone()
+        .try_acquire_owned()
+        .map_err(|_| StatusCode::SERVICE_UNAVAILABLE)?;
+
+    let hold = query
+        .timeout_secs
+        .unwrap_or(MAX_LONG_POLL_SECS)
+        .min(MAX_LONG_POLL_SECS);
+    let mut latest_id = state.latest_id.subscribe();
+    let newer = latest_id.wait_for(|id| *id > query.since_id);
+    let timed_out = tokio::time::timeout(Duration::from_secs(hold), newer)
+        .await
+        .is_err();
+    if timed_out {
+        return Ok(StatusCode::NO_CONTENT.into_response());
+    }
+
+    get_latest(State(state)).await.map(IntoResponse::into_response)
+}
+
+/// Guess a MIME type for decoded clipboard bytes, if they are an image or text
+fn guess_content_type(bytes: &[u8]) -> Option<&'static str> {
+    if let Ok(format) = image::guess_format(bytes) {
+        return Some(format.to_mime_type());
+    }
+
+    std::str::from_utf8(bytes)
+        .is_ok()
+        .then_some("text/plain; charset=utf-8")
+}
+
+fn decode_item(item: &ClipboardItem) -> Result<Vec<u8>, StatusCode> {
+    use base64::Engine;
+    base64::engine::general_purpose::STANDARD
+        .decode(&item.content)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+async fn get_latest_raw(State(state): State<AppState>) -> Result<Response, StatusCode> {
+    let storage = state.storage.lock().await;
+    let item = storage.get_latest().ok_or(StatusCode::NOT_FOUND)?;
+    let bytes = decode_item(&item)?;
+    let content_type = guess_content_type(&bytes).unwrap_or("application/octet-stream");
+
+    Ok(([(header::CONTENT_TYPE, content_type)], bytes).into_response())
+}
+
+/// Serve a history item's decoded bytes so browsers can display it inline
+async fn get_item_raw(
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<Response, StatusCode> {
+    let storage = state.storage.lock().await;
+    let item = storage.get_by_id(id).ok_or(StatusCode::NOT_FOUND)?;
+    let bytes = decode_item(&item)?;
+    let content_type = guess_content_type(&bytes).ok_or(StatusCode::UNSUPPORTED_MEDIA_TYPE)?;
+
+    Ok(([(header::CONTENT_TYPE, content_type)], bytes).into_response())
+}
+
 async fn get_history(State(state): State<AppState>) -> Json<HistoryResponse> {
     let storage = state.storage.lock().await;
     let items = storage.get_all();
@@ -230,11 +499,39 @@ async fn main() -> Result<()> {
         .ok()
         .and_then(|p| p.parse().ok())
         .unwrap_or(DEFAULT_PORT);
+    let max_subscribers = match std::env::var("CLIPBOARD_SERVER_MAX_SUBSCRIBERS") {
+        Ok(value) => value
+            .parse()
+            .with_context(|| format!("Invalid CLIPBOARD_SERVER_MAX_SUBSCRIBERS: {:?}", value))?,
+        Err(_) => DEFAULT_MAX_SUBSCRIBERS,
+    };
+    let allowed_types = match std::env::var("CLIPBOARD_SERVER_ALLOWED_TYPES") {
+        Ok(value) => parse_allowed_types(&value)?,
+        Err(_) => None,
+    };
+    if let Some(allowed) = &allowed_types {
+        info!("Accepting only these content types: {}", allowed.join(", "));
+    }
+    let history_file = std::env::var_os("CLIPBOARD_SERVER_HISTORY_FILE").map(PathBuf::from);
+    let storage = match &history_file {
+        Some(path) => {
+            let storage = ClipboardStorage::load(path)?;
+            info!("💾 Keeping history in {} ({} items loaded)", path.display(), storage.count());
+            storage
+        }
+        None => ClipboardStorage::new(),
+    };
+    let latest_id = storage.get_latest().map_or(0, |item| item.id);
 
     // Initialize state
     let state = AppState {
-        storage: Arc::new(Mutex::new(ClipboardStorage::new())),
+        storage: Arc::new(Mutex::new(storage)),
         start_time: Utc::now(),
+        instance_id: uuid::Uuid::new_v4().to_string(),
+        latest_id: Arc::new(watch::channel(latest_id).0),
+        waiters: Arc::new(Semaphore::new(max_subscribers)),
+        allowed_types,
+        history_file: history_file.map(Arc::new),
     };
 
     // Build router
@@ -242,7 +539,10 @@ async fn main() -> Result<()> {
         .route("/health", get(health_check))
         .route("/api/clipboard", post(submit_clipboard))
         .route("/api/clipboard/latest", get(get_latest))
+        .route("/api/clipboard/latest/raw", get(get_latest_raw))
+        .route("/api/clipboard/wait", get(wait_for_clipboard))
         .route("/api/clipboard/history", get(get_history))
+        .route("/api/clipboard/:id/raw", get(get_item_raw))
         .layer(CorsLayer::permissive())
         .layer(TraceLayer::new_for_http())
         .with_state(state);
@@ -250,16 +550,42 @@ async fn main() -> Result<()> {
     // Start server
     let addr = format!("{}:{}", host, port);
     let listener = tokio::net::TcpListener::bind(&addr).await?;
+    // Port 0 asks the OS for a free port; report the one we actually got
+    let local_addr = listener.local_addr()?;
+
+    if let Ok(port_file) = std::env::var("CLIPBOARD_SERVER_PORT_FILE") {
+        std::fs::write(&port_file, format!("{}\n", local_addr.port()))
+            .with_context(|| format!("Failed to write port file {}", port_file))?;
+    }
+
+    // Announce on the LAN unless CLIPBOARD_SERVER_ADVERTISE=0
+    #[cfg(feature = "discovery")]
+    let _advertisement = match std::env::var("CLIPBOARD_SERVER_ADVERTISE").as_deref() {
+        Ok("0") | Ok("false") => None,
+        _ => match discovery::advertise(local_addr.port(), "http") {
+            Ok(advertisement) => {
+                info!("📡 Advertising {} over mDNS", discovery::SERVICE_TYPE);
+                Some(advertisement)
+            }
+            Err(e) => {
+                tracing::warn!("mDNS advertisement failed: {:#}", e);
+                None
+            }
+        },
+    };
 
     info!("🚀 Clipboard HTTP Server starting");
-    info!("📍 Listening on http://{}", addr);
+    info!("📍 Listening on http://{}", local_addr);
     info!("📊 Max clipboard size: {} bytes", MAX_CLIPBOARD_SIZE);
     info!("📚 Max history items: {}", MAX_HISTORY_ITEMS);
     info!("");
     info!("API Endpoints:");
     info!("  POST   /api/clipboard          - Submit new clipboard");
     info!("  GET    /api/clipboard/latest   - Get latest clipboard");
+    info!("  GET    /api/clipboard/latest/raw - Get latest clipboard as raw bytes");
+    info!("  GET    /api/clipboard/wait?since_id=N - Long-poll for an item newer than N");
     info!("  GET    /api/clipboard/history  - Get clipboard history");
+    info!("  GET    /api/clipboard/:id/raw  - Get a history item as raw bytes");
     info!("  GET    /health                 - Health check");
     info!("");
 
@@ -267,3 +593,169 @@ async fn main() -> Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+
+    #[test]
+    fn test_item_hash_matches_client_hash() {
+        // The sync client hashes the raw bytes it sent or received
+        let bytes = b"\x89PNG not really an image";
+        let mut storage = ClipboardStorage::new();
+        let item = storage.add_item(BASE64.encode(bytes), "image".to_string());
+
+        assert_eq!(item.hash, content_hash::content_hash(bytes));
+        assert!(is_current_hash(&item.hash));
+    }
+
+    #[test]
+    fn test_loaded_md5_hashes_are_recomputed() {
+        let content = BASE64.encode("hello");
+        let current = ClipboardStorage::new().add_item(content.clone(), default_content_type());
+        let legacy = ClipboardItem {
+            id: 7,
+            hash: "5d41402abc4b2a76b9719d911017c592".to_string(), // MD5 of "hello"
+            ..current.clone()
+        };
+
+        let storage = ClipboardStorage::from_items(vec![legacy]);
+        assert_eq!(storage.get_by_id(7).unwrap().hash, current.hash);
+        assert_eq!(storage.next_id, 8);
+    }
+
+    #[test]
+    fn test_items_saved_without_a_type_load_as_text() {
+        let item: ClipboardItem = serde_json::from_value(serde_json::json!({
+            "id": 1,
+            "content": BASE64.encode("hello"),
+            "hash": content_hash::content_hash(b"hello"),
+            "timestamp": "2025-10-28T00:00:00Z",
+            "size": 8,
+        }))
+        .unwrap();
+        assert_eq!(item.content_type, "text");
+
+        let json = serde_json::to_value(ClipboardStorage::from_items(vec![item]).get_all()).unwrap();
+        assert_eq!(json[0]["content_type"], "text");
+    }
+
+    #[test]
+    fn test_version_1_history_file_is_migrated() {
+        let v1 = serde_json::json!([{
+            "id": 3,
+            "content": BASE64.encode("hello"),
+            "hash": "5d41402abc4b2a76b9719d911017c592",
+            "timestamp": "2025-10-28T00:00:00Z",
+            "size": 8,
+        }])
+        .to_string();
+
+        let storage = ClipboardStorage::from_state_json(&v1).unwrap();
+        let item = storage.get_by_id(3).unwrap();
+        assert_eq!(item.content_type, "text");
+        assert_eq!(item.hash, content_hash::content_hash(b"hello"));
+        assert_eq!(storage.next_id, 4);
+
+        // Saved again it is current, and round-trips
+        let v2 = storage.to_state_json().unwrap();
+        assert_eq!(serde_json::from_str::<serde_json::Value>(&v2).unwrap()["version"], STATE_VERSION);
+        assert_eq!(ClipboardStorage::from_state_json(&v2).unwrap().count(), 1);
+
+        let future = serde_json::json!({ "version": STATE_VERSION + 1, "items": [] }).to_string();
+        let err = ClipboardStorage::from_state_json(&future).err().unwrap();
+        assert!(err.to_string().contains("upgrade clipboard_server"));
+    }
+
+    #[tokio::test]
+    async fn test_submitted_items_survive_a_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("history.json");
+        let state = AppState {
+            storage: Arc::new(Mutex::new(ClipboardStorage::load(&path).unwrap())),
+            start_time: Utc::now(),
+            instance_id: "test".to_string(),
+            latest_id: Arc::new(watch::channel(0).0),
+            waiters: Arc::new(Semaphore::new(1)),
+            allowed_types: None,
+            history_file: Some(Arc::new(path.clone())),
+        };
+        let submit = SubmitClipboardRequest {
+            content: BASE64.encode("kept"),
+            content_type: None,
+        };
+        let item = submit_clipboard(State(state), Json(submit)).await.ok().unwrap();
+
+        let reloaded = ClipboardStorage::load(&path).unwrap();
+        assert_eq!(reloaded.get_by_id(item.id).unwrap().hash, item.hash);
+        assert_eq!(reloaded.next_id, item.id + 1);
+    }
+
+    #[tokio::test]
+    async fn test_allowed_types_reject_other_uploads() {
+        let state = AppState {
+            storage: Arc::new(Mutex::new(ClipboardStorage::new())),
+            start_time: Utc::now(),
+            instance_id: "test".to_string(),
+            latest_id: Arc::new(watch::channel(0).0),
+            waiters: Arc::new(Semaphore::new(1)),
+            allowed_types: parse_allowed_types(" Text ,").unwrap(),
+            history_file: None,
+        };
+        let submit = |content_type: &str| SubmitClipboardRequest {
+            content: BASE64.encode("hello"),
+            content_type: Some(content_type.to_string()),
+        };
+
+        let accepted = submit_clipboard(State(state.clone()), Json(submit("text"))).await;
+        assert_eq!(accepted.ok().unwrap().content_type, "text");
+
+        let rejected = submit_clipboard(State(state.clone()), Json(submit("image"))).await;
+        let response = rejected.err().unwrap().into_response();
+        assert_eq!(response.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        assert_eq!(state.storage.lock().await.count(), 1);
+
+        assert!(parse_allowed_types("").unwrap().is_none());
+        assert!(parse_allowed_types("text,video").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_subscriber_cap_is_enforced_and_released() {
+        let state = AppState {
+            storage: Arc::new(Mutex::new(ClipboardStorage::new())),
+            start_time: Utc::now(),
+            instance_id: "test".to_string(),
+            latest_id: Arc::new(watch::channel(0).0),
+            waiters: Arc::new(Semaphore::new(1)),
+            allowed_types: None,
+            history_file: None,
+        };
+        let wait = |state: AppState| {
+            wait_for_clipboard(
+                State(state),
+                Query(WaitQuery {
+                    since_id: 0,
+                    timeout_secs: Some(5),
+                }),
+            )
+        };
+
+        let first = tokio::spawn(wait(state.clone()));
+        while state.waiters.available_permits() > 0 {
+            tokio::task::yield_now().await;
+        }
+        let rejected = wait(state.clone()).await;
+        assert_eq!(rejected.err(), Some(StatusCode::SERVICE_UNAVAILABLE));
+
+        // A new item wakes the waiter, which frees its slot
+        let item = state
+            .storage
+            .lock()
+            .await
+            .add_item(BASE64.encode("hi"), default_content_type());
+        state.latest_id.send_replace(item.id);
+        assert_eq!(first.await.unwrap().unwrap().status(), StatusCode::OK);
+        assert_eq!(state.waiters.available_permits(), 1);
+    }
+}
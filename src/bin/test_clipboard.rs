@@ -76,7 +76,7 @@ fn main() {
     use std::process::Command;
 
     match Command::new("xclip")
-        .args(&["-o", "-selection", "clipboard"])
+        .args(["-o", "-selection", "clipboard"])
         .output()
     {
         Ok(output) => {
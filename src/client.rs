@@ -1,61 +1,273 @@
-use crate::config::Config;
-use crate::sync::protocol::Message;
-use anyhow::Result;
+use crate::clipboard::{ClipboardContent, ClipboardManager};
+use crate::config::{Config, ServerEndpoint};
+use crate::content_hash::short_checksum;
+use crate::event_log::{Direction, SyncEvent, SyncEventLog};
+use crate::storage::{
+    models::{ClipboardContentType, ClipboardEntry},
+    Storage,
+};
+use crate::sync::{
+    large_apply::LargeApplyGuard,
+    outbound::LatestUpdate,
+    pause::PauseFlag,
+    protocol::{Compression, Message, WireFormat},
+    replace::ReplaceRules,
+    transport::{self, Stream},
+    RecentlyApplied,
+};
+use anyhow::{Context, Result};
+use std::collections::HashMap;
 use std::sync::Arc;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::TcpStream;
-use tokio::sync::mpsc;
-use tokio::time::{sleep, Duration};
-use tracing::{error, info, warn};
+use tokio::sync::{mpsc, watch};
+use tokio::time::{sleep, timeout, Duration, Instant};
+use tracing::{debug, error, info, warn};
+
+/// How long to wait for the server's `HelloAck`
+const HELLO_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long a server that never answered `Hello` is connected to without one
+/// before offering it again, in case it has been upgraded since
+const LEGACY_SERVER_RETRY: Duration = Duration::from_secs(600);
+
+/// What the `Hello` handshake settled for a connection
+#[derive(Debug, Clone, Copy, Default)]
+struct Negotiated {
+    compression: Compression,
+    format: WireFormat,
+}
+
+/// Why a server connection ended without an error
+enum Disconnect {
+    Closed,
+    PrimaryRecovered,
+    Shutdown,
+}
+
+/// Wait for `shutdown` to become true; false if it never can (sender gone)
+async fn shutdown_requested(shutdown: &mut watch::Receiver<bool>) -> bool {
+    shutdown.wait_for(|stop| *stop).await.is_ok()
+}
+
+/// Result of asking the local daemon to replay a history entry
+pub enum ReplayOutcome {
+    Replayed,
+    NotFound(String),
+}
+
+/// Ask the daemon's server on this machine to re-broadcast history entry `id`
+/// to its connected clients
+pub async fn request_replay(config: &Config, id: i64) -> Result<ReplayOutcome> {
+    let host = match config.server.host.as_str() {
+        "0.0.0.0" | "::" => "127.0.0.1",
+        host => host,
+    };
+    let addr = format!("{}:{}", host, config.server.port);
+
+    let unix_socket = config.server.unix_socket.as_deref();
+    let listening_on = match unix_socket {
+        Some(path) => path.display().to_string(),
+        None => addr.clone(),
+    };
+    let connect = match timeout(Duration::from_secs(2), transport::connect(unix_socket, &addr)).await {
+        Ok(result) => result,
+        Err(_) => Err(anyhow::anyhow!("timed out")),
+    };
+    let mut socket = connect.with_context(|| {
+        format!("no clippy daemon is listening on {}; start one with `clippy start`", listening_on)
+    })?;
+
+    if let Some(token) = &config.server.auth_token {
+        let auth = Message::Auth {
+            token: token.clone(),
+        };
+        socket.write_all(&auth.to_bytes()?).await?;
+        if let Message::AuthResponse { success: false, message } = read_message(&mut socket).await? {
+            anyhow::bail!("Authentication failed: {}", message);
+        }
+    }
+
+    let replay = Message::Replay {
+        id,
+        request_id: None,
+    };
+    socket.write_all(&replay.to_bytes()?).await?;
+    match read_message(&mut socket).await? {
+        Message::ClipboardAck { success: true, .. } => Ok(ReplayOutcome::Replayed),
+        Message::Error { message, .. } => Ok(ReplayOutcome::NotFound(message)),
+        _ => Err(anyhow::anyhow!("Unexpected response to replay request")),
+    }
+}
+
+/// Read one complete message, waiting for more data as needed
+async fn read_message(socket: &mut impl Stream) -> Result<Message> {
+    let mut pending = Vec::new();
+    let mut buffer = vec![0u8; 8192];
+
+    loop {
+        if let Ok((message, _)) = Message::from_bytes(&pending) {
+            return Ok(message);
+        }
+
+        let n = timeout(Duration::from_secs(5), socket.read(&mut buffer)).await??;
+        if n == 0 {
+            anyhow::bail!("Connection closed by server");
+        }
+        pending.extend_from_slice(&buffer[..n]);
+    }
+}
 
 pub struct ClipboardClient {
     config: Arc<Config>,
-    tx: mpsc::Sender<Message>,
-    rx: mpsc::Receiver<Message>,
+    outbound: LatestUpdate,
+    event_log: Option<SyncEventLog>,
+    storage: Option<Arc<dyn Storage>>, // Received updates are recorded here when set
+    applied: RecentlyApplied,
+    replace: ReplaceRules,
+    shutdown: watch::Receiver<bool>,
+    pause: PauseFlag,
+    legacy_servers: HashMap<String, Instant>, // Addresses that never answered `Hello`, and when
+    hello_timeout: Duration,
+    large_apply: LargeApplyGuard,
 }
 
 impl ClipboardClient {
     pub fn new(config: Config) -> Self {
-        let (tx, rx) = mpsc::channel(100);
+        let event_log = SyncEventLog::from_config(&config.sync);
+        let large_apply = LargeApplyGuard::from_config(&config.sync);
 
         Self {
             config: Arc::new(config),
-            tx,
-            rx,
+            outbound: LatestUpdate::default(),
+            event_log,
+            storage: None,
+            applied: RecentlyApplied::default(),
+            replace: ReplaceRules::default(),
+            // Sender dropped: never fires unless `with_shutdown` replaces it
+            shutdown: watch::channel(false).1,
+            pause: PauseFlag::default(),
+            legacy_servers: HashMap::new(),
+            hello_timeout: HELLO_TIMEOUT,
+            large_apply,
         }
     }
 
-    pub fn get_sender(&self) -> mpsc::Sender<Message> {
-        self.tx.clone()
+    /// Record received updates in local history so `clippy history` sees them
+    pub fn with_storage(mut self, storage: Arc<dyn Storage>) -> Self {
+        self.storage = Some(storage);
+        self
+    }
+
+    /// Rewrite received text with `sync.replace_rules` before applying it
+    pub fn with_replace_rules(mut self, replace: ReplaceRules) -> Self {
+        self.replace = replace;
+        self
+    }
+
+    /// Say `Bye` to the server and stop once `shutdown` becomes true
+    pub fn with_shutdown(mut self, shutdown: watch::Receiver<bool>) -> Self {
+        self.shutdown = shutdown;
+        self
+    }
+
+    /// Neither send nor apply clipboard updates while `pause` is set
+    pub fn with_pause(mut self, pause: PauseFlag) -> Self {
+        self.pause = pause;
+        self
+    }
+
+    /// Handle for the clipboard monitor to recognise content this client applied
+    pub fn recently_applied(&self) -> RecentlyApplied {
+        self.applied.clone()
+    }
+
+    /// Slot for the clipboard monitor's outbound updates; only the newest
+    /// unsent one is kept
+    pub fn get_sender(&self) -> LatestUpdate {
+        self.outbound.clone()
+    }
+
+    /// Drop pending updates until `ready` completes, so the monitor never
+    /// blocks on a client that isn't connecting yet
+    pub async fn discard_until(&mut self, ready: impl std::future::Future<Output = ()>) {
+        tokio::pin!(ready);
+        let mut shutdown = self.shutdown.clone();
+        loop {
+            tokio::select! {
+                _ = &mut ready => return,
+                true = shutdown_requested(&mut shutdown) => return,
+                _ = self.outbound.pop() => {}
+            }
+        }
     }
 
     pub async fn run(&mut self) -> Result<()> {
+        let mut endpoints = self.config.client.endpoints();
+        if self.config.client.unix_socket.is_some() {
+            // A local socket has nowhere to fail over to
+            endpoints.truncate(1);
+        }
+        let mut index = 0;
+
         loop {
-            match self.connect_and_run().await {
-                Ok(_) => {
-                    info!("Client connection closed gracefully");
+            if *self.shutdown.borrow() {
+                return Ok(());
+            }
+            let endpoint = &endpoints[index];
+            let primary = if index == 0 { None } else { Some(&endpoints[0]) };
+
+            match self.connect_and_run(endpoint, primary).await {
+                Ok(Disconnect::PrimaryRecovered) => {
+                    info!("Primary server {} is reachable again, switching back", endpoints[0].addr());
+                    index = 0;
+                    continue;
+                }
+                Ok(Disconnect::Closed) => {
+                    info!("Client connection to {} closed gracefully", endpoint.addr());
+                }
+                Ok(Disconnect::Shutdown) => {
+                    info!("Disconnected from {} for shutdown", endpoint.addr());
+                    return Ok(());
                 }
                 Err(e) => {
-                    error!("Client error: {}", e);
+                    error!("Client error ({}): {}", endpoint.addr(), e);
                 }
             }
 
+            // Fail over to the next server immediately; only back off once
+            // every server has been tried
+            index = (index + 1) % endpoints.len();
+            if index != 0 {
+                info!("Failing over to {}", endpoints[index].addr());
+                continue;
+            }
+
             info!(
                 "Reconnecting in {} ms...",
                 self.config.sync.retry_delay_ms
             );
-            sleep(Duration::from_millis(self.config.sync.retry_delay_ms)).await;
+            let mut shutdown = self.shutdown.clone();
+            tokio::select! {
+                _ = sleep(Duration::from_millis(self.config.sync.retry_delay_ms)) => {}
+                true = shutdown_requested(&mut shutdown) => return Ok(()),
+            }
         }
     }
 
-    async fn connect_and_run(&mut self) -> Result<()> {
-        let addr = format!(
-            "{}:{}",
-            self.config.client.server_host, self.config.client.server_port
-        );
+    async fn connect_and_run(
+        &mut self,
+        endpoint: &ServerEndpoint,
+        primary: Option<&ServerEndpoint>,
+    ) -> Result<Disconnect> {
+        let addr = endpoint.addr();
 
-        info!("Connecting to server at {}...", addr);
-        let mut socket = TcpStream::connect(&addr).await?;
+        let unix_socket = self.config.client.unix_socket.as_deref();
+        match unix_socket {
+            Some(path) => info!("Connecting to server at {}...", path.display()),
+            None => info!("Connecting to server at {}...", addr),
+        }
+        let mut socket = transport::connect(unix_socket, &addr).await?;
         info!("Connected to server");
 
         // Authenticate if token is provided
@@ -83,27 +295,78 @@ impl ClipboardClient {
             }
         }
 
-        let mut buffer = vec![0u8; 8192];
         let mut pending_data = Vec::new();
+        let Negotiated { compression, format } =
+            self.negotiate(&mut socket, &addr, &mut pending_data).await?;
+
+        let mut buffer = vec![0u8; 8192];
         let mut heartbeat_interval =
             tokio::time::interval(Duration::from_millis(self.config.sync.heartbeat_interval_ms));
+        let probe_period = Duration::from_millis(self.config.sync.retry_delay_ms);
+        let mut primary_probe =
+            tokio::time::interval_at(Instant::now() + probe_period, probe_period);
+        // Probes run in their own tasks so a slow connect never stalls the loop
+        let (recovered_tx, mut recovered_rx) = mpsc::channel::<()>(1);
+        let mut shutdown = self.shutdown.clone();
 
         loop {
             tokio::select! {
+                // Tell the server we're leaving rather than just dropping the socket
+                true = shutdown_requested(&mut shutdown) => {
+                    if let Err(e) = socket.write_all(&Message::Bye.to_bytes_as(format)?).await {
+                        warn!("Could not say goodbye to the server: {}", e);
+                    }
+                    let _ = socket.shutdown().await;
+                    return Ok(Disconnect::Shutdown);
+                }
+
                 // Send messages from the queue
-                Some(message) = self.rx.recv() => {
+                message = self.outbound.pop() => {
+                    if self.pause.is_paused() {
+                        if let Message::ClipboardUpdate { source, checksum, .. }
+                        | Message::ClipboardUpdateCompressed { source, checksum, .. } = &message
+                        {
+                            debug!("Sync paused: not sending clipboard update {}", checksum);
+                            self.record_event(
+                                SyncEvent::new(Direction::Outbound, source, checksum, "skipped")
+                                    .with_reason("paused"),
+                            );
+                            continue;
+                        }
+                    }
+                    let message = message.encoded_for(compression)?;
+
                     // Log what we're sending
                     match &message {
                         Message::ClipboardUpdate { content_type, checksum, source, .. } => {
                             info!(
                                 "📤 Sending clipboard update to server (type: {}, source: {}, checksum: {})",
-                                content_type, source, &checksum[..8]
+                                content_type, source, short_checksum(checksum)
+                            );
+                        }
+                        Message::ClipboardUpdateCompressed { content_type, checksum, source, original_len, content, .. } => {
+                            info!(
+                                "📤 Sending compressed clipboard update to server (type: {}, source: {}, checksum: {}, {} -> {} bytes)",
+                                content_type, source, short_checksum(checksum), original_len, content.len()
                             );
                         }
                         _ => {}
                     }
 
-                    if let Err(e) = socket.write_all(&message.to_bytes()?).await {
+                    let result = socket.write_all(&message.to_bytes_as(format)?).await;
+
+                    if let Message::ClipboardUpdate { source, checksum, .. }
+                    | Message::ClipboardUpdateCompressed { source, checksum, .. } = &message
+                    {
+                        let event = match &result {
+                            Ok(_) => SyncEvent::new(Direction::Outbound, source, checksum, "sent"),
+                            Err(e) => SyncEvent::new(Direction::Outbound, source, checksum, "failed")
+                                .with_reason(e.to_string()),
+                        };
+                        self.record_event(event);
+                    }
+
+                    if let Err(e) = result {
                         error!("❌ Error sending message: {}", e);
                         return Err(e.into());
                     }
@@ -114,7 +377,7 @@ impl ClipboardClient {
                     match result {
                         Ok(0) => {
                             info!("Server closed connection");
-                            return Ok(());
+                            return Ok(Disconnect::Closed);
                         }
                         Ok(n) => {
                             pending_data.extend_from_slice(&buffer[..n]);
@@ -143,35 +406,209 @@ impl ClipboardClient {
                 // Send heartbeat
                 _ = heartbeat_interval.tick() => {
                     let ping = Message::Ping;
-                    if let Err(e) = socket.write_all(&ping.to_bytes()?).await {
+                    if let Err(e) = socket.write_all(&ping.to_bytes_as(format)?).await {
                         error!("Error sending heartbeat: {}", e);
                         return Err(e.into());
                     }
                 }
+
+                // While on a backup server, check whether the primary is back
+                _ = primary_probe.tick(), if primary.is_some() => {
+                    if let Some(primary) = primary {
+                        let addr = primary.addr();
+                        let recovered_tx = recovered_tx.clone();
+                        tokio::spawn(async move {
+                            let probe = timeout(Duration::from_secs(2), TcpStream::connect(&addr)).await;
+                            if matches!(probe, Ok(Ok(_))) {
+                                let _ = recovered_tx.try_send(());
+                            }
+                        });
+                    }
+                }
+
+                Some(()) = recovered_rx.recv() => {
+                    return Ok(Disconnect::PrimaryRecovered);
+                }
+            }
+        }
+    }
+
+    /// Offer our compression algorithms and wire format, and wait for the
+    /// server's pick. Frames that arrive first (e.g. a broadcast) are left in `pending`. A
+    /// server predating the handshake can't parse `Hello` and stops reading
+    /// the connection, so it is remembered and connections for the next
+    /// `LEGACY_SERVER_RETRY` skip it.
+    async fn negotiate(
+        &mut self,
+        socket: &mut impl Stream,
+        addr: &str,
+        pending: &mut Vec<u8>,
+    ) -> Result<Negotiated> {
+        if let Some(since) = self.legacy_servers.get(addr) {
+            if since.elapsed() < LEGACY_SERVER_RETRY {
+                return Ok(Negotiated::default());
+            }
+            self.legacy_servers.remove(addr);
+        }
+        let hello = Message::Hello {
+            compression: Compression::advertised(),
+            formats: vec![WireFormat::from(self.config.client.wire_format).name().to_string()],
+        };
+        socket.write_all(&hello.to_bytes()?).await?;
+
+        let deadline = Instant::now() + self.hello_timeout;
+        let mut buffer = vec![0u8; 8192];
+        loop {
+            let mut offset = 0;
+            while let Ok((message, size)) = Message::from_bytes(&pending[offset..]) {
+                if let Message::HelloAck { compression, format } = message {
+                    pending.drain(offset..offset + size);
+                    let compression = Compression::negotiate(compression.as_slice());
+                    let format = WireFormat::negotiate(format.as_slice());
+                    info!(
+                        "Compression for this connection: {}, with {} frames",
+                        compression.name().unwrap_or("none"),
+                        format.name()
+                    );
+                    return Ok(Negotiated { compression, format });
+                }
+                offset += size;
+            }
+
+            match tokio::time::timeout_at(deadline, socket.read(&mut buffer)).await {
+                Ok(Ok(0)) => anyhow::bail!("Connection closed by server"),
+                Ok(Ok(n)) => pending.extend_from_slice(&buffer[..n]),
+                Ok(Err(e)) => return Err(e.into()),
+                Err(_) => {
+                    warn!("Server {} did not answer Hello; reconnecting without compression", addr);
+                    self.legacy_servers.insert(addr.to_string(), Instant::now());
+                    anyhow::bail!("No handshake reply from {}", addr);
+                }
             }
         }
     }
 
     async fn handle_message(&self, message: Message) -> Result<()> {
+        let message = match message.decompressed(self.config.storage.max_encoded_size_bytes()) {
+            Ok(message) => message,
+            Err(e) => {
+                error!("❌ Dropping compressed clipboard update: {}", e);
+                return Ok(());
+            }
+        };
+
         match message {
             Message::ClipboardUpdate {
                 content_type,
                 content,
-                timestamp: _,
+                timestamp,
                 source,
                 checksum,
+                metadata,
             } => {
                 info!(
                     "📥 Received clipboard update from {} (type: {}, checksum: {}, size: {} bytes)",
-                    source, content_type, &checksum[..8], content.len()
+                    source, content_type, short_checksum(&checksum), content.len()
                 );
 
+                if !self.config.sync.direction.receives() {
+                    debug!("Send-only mode: ignoring clipboard update {} from {}", checksum, source);
+                    return Ok(());
+                }
+
+                // A malformed update is skipped rather than tearing down the connection
+                let clipboard_content = match ClipboardContent::from_base64(
+                    &content_type,
+                    &content,
+                    metadata.as_deref(),
+                    self.config.storage.max_content_size_bytes(),
+                ) {
+                    Ok(clipboard_content) => clipboard_content,
+                    Err(e) => {
+                        warn!("⚠ Skipping malformed clipboard update {} from {}: {}", checksum, source, e);
+                        self.record_event(
+                            SyncEvent::new(Direction::Inbound, &source, &checksum, "skipped")
+                                .with_reason(e.to_string()),
+                        );
+                        return Ok(());
+                    }
+                };
+
+                if !clipboard_content.matches_checksum(&checksum) {
+                    warn!("⚠ Rejecting clipboard update from {}: checksum mismatch", source);
+                    self.record_event(
+                        SyncEvent::new(Direction::Inbound, &source, &checksum, "skipped")
+                            .with_reason("checksum mismatch"),
+                    );
+                    return Ok(());
+                }
+
+                let mut stored_id = None;
+                if let Some(storage) = &self.storage {
+                    // Verified above, so a repeat of an existing entry only
+                    // bumps its timestamp
+                    let content_type = ClipboardContentType::from(content_type.as_str());
+                    let entry = ClipboardEntry {
+                        id: None,
+                        size_bytes: ClipboardEntry::decoded_size(&content_type, &content),
+                        content_type,
+                        checksum: clipboard_content.checksum(),
+                        content,
+                        metadata,
+                        source: source.clone(),
+                        timestamp,
+                        created_at: timestamp,
+                        apply_count: 0,
+                    };
+                    match storage.insert(&entry).await {
+                        Ok(id) => stored_id = Some(id),
+                        Err(e) => error!("❌ Failed to store received clipboard update: {}", e),
+                    }
+                }
+
+                if self.pause.is_paused() {
+                    info!("⏸ Sync paused: not applying clipboard update from {}", source);
+                    self.record_event(
+                        SyncEvent::new(Direction::Inbound, &source, &checksum, "skipped")
+                            .with_reason("paused"),
+                    );
+                    return Ok(());
+                }
+
+                if !self.large_apply.allow(&clipboard_content).await {
+                    self.record_event(
+                        SyncEvent::new(Direction::Inbound, &source, &checksum, "skipped")
+                            .with_reason("too_large"),
+                    );
+                    return Ok(());
+                }
+
                 // Update local clipboard
                 info!("📋 Applying clipboard update to local clipboard...");
-                if let Err(e) = self.apply_clipboard_update(&content_type, &content).await {
-                    error!("❌ Error applying clipboard update: {}", e);
-                } else {
-                    info!("✓ Successfully applied clipboard update");
+                match self.apply_clipboard_update(clipboard_content).await {
+                    Err(e) => {
+                        error!("❌ Error applying clipboard update: {}", e);
+                        self.record_event(
+                            SyncEvent::new(Direction::Inbound, &source, &checksum, "failed")
+                                .with_reason(e.to_string()),
+                        );
+                    }
+                    Ok(false) => {
+                        info!("Local clipboard already holds this update");
+                        self.record_event(
+                            SyncEvent::new(Direction::Inbound, &source, &checksum, "skipped")
+                                .with_reason("unchanged"),
+                        );
+                    }
+                    Ok(true) => {
+                        info!("✓ Successfully applied clipboard update");
+                        self.record_event(SyncEvent::new(Direction::Inbound, &source, &checksum, "applied"));
+                        if let (Some(storage), Some(id)) = (&self.storage, stored_id) {
+                            if let Err(e) = storage.record_apply(id).await {
+                                warn!("⚠ Failed to count applied entry {}: {}", id, e);
+                            }
+                        }
+                    }
                 }
             }
 
@@ -179,15 +616,22 @@ impl ClipboardClient {
                 // Heartbeat response
             }
 
-            Message::ClipboardAck { checksum, success } => {
+            Message::ClipboardAck { checksum, success, .. } => {
                 if success {
-                    info!("✓ Server acknowledged clipboard sync: {}", &checksum[..8]);
+                    info!("✓ Server acknowledged clipboard sync: {}", short_checksum(&checksum));
                 } else {
-                    warn!("❌ Server failed to sync clipboard: {}", &checksum[..8]);
+                    warn!("❌ Server failed to sync clipboard: {}", short_checksum(&checksum));
                 }
             }
 
-            Message::Error { message } => {
+            Message::StatsResponse { total, by_type, by_source, new_entries, deduped, .. } => {
+                info!(
+                    "Server stats: {} entries (by type: {:?}, by source: {:?}), this session New: {}, Deduped: {}",
+                    total, by_type, by_source, new_entries, deduped
+                );
+            }
+
+            Message::Error { message, .. } => {
                 error!("Server error: {}", message);
             }
 
@@ -199,13 +643,253 @@ impl ClipboardClient {
         Ok(())
     }
 
-    async fn apply_clipboard_update(&self, content_type: &str, content: &str) -> Result<()> {
-        use crate::clipboard::{ClipboardContent, ClipboardManager};
+    fn record_event(&self, event: SyncEvent) {
+        if let Some(log) = &self.event_log {
+            log.record(event);
+        }
+    }
 
+    /// Write an update to the clipboard, returning whether it changed anything
+    async fn apply_clipboard_update(&self, content: ClipboardContent) -> Result<bool> {
+        let content = self.replace.on_receive(content);
         let mut clipboard = ClipboardManager::new()?;
-        let clipboard_content = ClipboardContent::from_base64(content_type, content)?;
-        clipboard.set_content(&clipboard_content)?;
+        // Nothing written means the monitor has no echo to suppress
+        let written = clipboard.set_content(&content)?;
+        if written {
+            self.applied.record_applied(&mut clipboard, &content);
+        }
 
-        Ok(())
+        Ok(written)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::SyncDirection;
+    use crate::storage::ClipboardStorage;
+
+    #[tokio::test]
+    async fn test_malformed_image_update_is_skipped() {
+        let client = ClipboardClient::new(Config::default());
+        let message = Message::ClipboardUpdate {
+            content_type: "image".to_string(),
+            content: "not base64!!".to_string(),
+            timestamp: chrono::Utc::now(),
+            source: "macos".to_string(),
+            checksum: "deadbeefcafe".to_string(),
+            metadata: None,
+        };
+
+        // An error here would end the connection loop
+        assert!(client.handle_message(message).await.is_ok());
+        assert!(client.handle_message(Message::Pong).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_no_updates_are_sent_while_paused() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut config = Config::default();
+        config.client.server_host = "127.0.0.1".to_string();
+        config.client.server_port = listener.local_addr().unwrap().port();
+
+        let pause = PauseFlag::default();
+        pause.set(true);
+        let mut client = ClipboardClient::new(config).with_pause(pause.clone());
+        let tx = client.get_sender();
+        let task = tokio::spawn(async move { client.run().await });
+        let (mut socket, mut pending) = accept_client(&listener).await;
+
+        let update = |text: &str| {
+            let checksum = ClipboardEntry::calculate_checksum(text);
+            Message::clipboard_update("text".into(), text.into(), "test".into(), checksum, None)
+        };
+        tx.push(update("copied while paused"));
+        // Wait for the client to take it before resuming
+        while !tx.is_empty() {
+            tokio::task::yield_now().await;
+        }
+        pause.set(false);
+        tx.push(update("copied after resume"));
+
+        // The first update must be the second one
+        let sent = next_update(&mut socket, &mut pending, Duration::from_secs(5)).await;
+        assert_eq!(sent.as_deref(), Some("copied after resume"));
+        task.abort();
+    }
+
+    #[tokio::test]
+    async fn test_received_updates_are_ignored_when_send_only() {
+        for (direction, receives) in [
+            (SyncDirection::Both, true),
+            (SyncDirection::ReceiveOnly, true),
+            (SyncDirection::SendOnly, false),
+        ] {
+            let mut config = Config::default();
+            config.sync.direction = direction;
+            config.storage.database_path = Some(":memory:".into());
+            let storage = Arc::new(ClipboardStorage::from_config(&config).await.unwrap());
+            // Paused, so a received update is stored but never touches the clipboard
+            let pause = PauseFlag::default();
+            pause.set(true);
+            let client = ClipboardClient::new(config)
+                .with_storage(storage.clone())
+                .with_pause(pause);
+
+            let checksum = ClipboardEntry::calculate_checksum("remote");
+            let update = Message::clipboard_update(
+                "text".into(),
+                "remote".into(),
+                "peer".into(),
+                checksum,
+                None,
+            );
+            client.handle_message(update).await.unwrap();
+            let stored = storage.get_count().await.unwrap();
+            assert_eq!(stored, i64::from(receives), "{:?}", direction);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_server_without_hello_ack_falls_back_until_retry() {
+        let mut client = ClipboardClient::new(Config::default());
+        client.hello_timeout = Duration::from_millis(100);
+        let addr = "legacy:8080";
+        let mut pending = Vec::new();
+
+        // An old server reads the Hello but never answers it
+        let (mut socket, mut server) = tokio::io::duplex(8192);
+        assert!(client.negotiate(&mut socket, addr, &mut pending).await.is_err());
+        let mut hello = vec![0u8; 8192];
+        let n = server.read(&mut hello).await.unwrap();
+        assert!(matches!(Message::from_bytes(&hello[..n]), Ok((Message::Hello { .. }, _))));
+
+        // The reconnect skips the handshake rather than timing out again
+        let (mut socket, mut server) = tokio::io::duplex(8192);
+        let negotiated = client.negotiate(&mut socket, addr, &mut pending).await.unwrap();
+        assert!(matches!(negotiated.compression, Compression::None));
+        drop(socket);
+        assert_eq!(server.read(&mut hello).await.unwrap(), 0, "Hello sent to a legacy server");
+
+        // Once the entry expires the server is offered a Hello again
+        let expired = Instant::now() - LEGACY_SERVER_RETRY;
+        client.legacy_servers.insert(addr.to_string(), expired);
+        let (mut socket, mut server) = tokio::io::duplex(8192);
+        assert!(client.negotiate(&mut socket, addr, &mut pending).await.is_err());
+        assert!(server.read(&mut hello).await.unwrap() > 0);
+    }
+
+    #[tokio::test]
+    async fn test_rapid_updates_collapse_to_one_send() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut config = Config::default();
+        config.client.server_host = "127.0.0.1".to_string();
+        config.client.server_port = listener.local_addr().unwrap().port();
+
+        let mut client = ClipboardClient::new(config);
+        let tx = client.get_sender();
+        // Copied while the client isn't connected yet
+        for text in ["first", "second", "third"] {
+            let checksum = ClipboardEntry::calculate_checksum(text);
+            tx.push(
+                Message::clipboard_update("text".into(), text.into(), "test".into(), checksum, None),
+            );
+        }
+        let task = tokio::spawn(async move { client.run().await });
+        let (mut socket, mut pending) = accept_client(&listener).await;
+
+        let sent = next_update(&mut socket, &mut pending, Duration::from_secs(5)).await;
+        assert_eq!(sent.as_deref(), Some("third"));
+        let more = next_update(&mut socket, &mut pending, Duration::from_millis(300)).await;
+        assert_eq!(more, None);
+        task.abort();
+    }
+
+    #[tokio::test]
+    async fn test_fails_over_and_returns_to_recovered_primary() {
+        // Reserve a port for the primary, then leave it down
+        let primary_port = {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            listener.local_addr().unwrap().port()
+        };
+        let backup = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = |port| ServerEndpoint {
+            host: "127.0.0.1".to_string(),
+            port,
+        };
+        let mut config = Config::default();
+        config.client.servers = vec![
+            endpoint(primary_port),
+            endpoint(backup.local_addr().unwrap().port()),
+        ];
+        config.sync.retry_delay_ms = 100;
+
+        let mut client = ClipboardClient::new(config);
+        let tx = client.get_sender();
+        let task = tokio::spawn(async move { client.run().await });
+
+        let (mut backup_socket, mut backup_pending) =
+            timeout(Duration::from_secs(5), accept_client(&backup)).await.unwrap();
+        let text = "on backup";
+        let checksum = ClipboardEntry::calculate_checksum(text);
+        tx.push(
+            Message::clipboard_update("text".into(), text.into(), "test".into(), checksum, None),
+        );
+        let sent =
+            next_update(&mut backup_socket, &mut backup_pending, Duration::from_secs(5)).await;
+        assert_eq!(sent.as_deref(), Some("on backup"));
+
+        // The probe's own connection is accepted first, then the client's
+        let primary = tokio::net::TcpListener::bind(("127.0.0.1", primary_port)).await.unwrap();
+        let _probe = timeout(Duration::from_secs(5), primary.accept()).await.unwrap().unwrap();
+        timeout(Duration::from_secs(5), accept_client(&primary)).await.unwrap();
+        task.abort();
+    }
+
+    /// Accept a client connection and answer its `Hello`, returning any
+    /// bytes read past it
+    async fn accept_client(listener: &tokio::net::TcpListener) -> (TcpStream, Vec<u8>) {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        let mut pending = Vec::new();
+        let mut buffer = vec![0u8; 8192];
+        loop {
+            if let Ok((message, size)) = Message::from_bytes(&pending) {
+                pending.drain(..size);
+                assert!(matches!(message, Message::Hello { .. }));
+                break;
+            }
+            let n = socket.read(&mut buffer).await.unwrap();
+            pending.extend_from_slice(&buffer[..n]);
+        }
+        let ack = Message::HelloAck {
+            compression: Some("zstd".to_string()),
+            format: None,
+        };
+        socket.write_all(&ack.to_bytes().unwrap()).await.unwrap();
+        (socket, pending)
+    }
+
+    /// The content of the next update the client sends within `wait`,
+    /// skipping heartbeats
+    async fn next_update(
+        socket: &mut tokio::net::TcpStream,
+        pending: &mut Vec<u8>,
+        wait: Duration,
+    ) -> Option<String> {
+        let mut buffer = vec![0u8; 8192];
+        timeout(wait, async {
+            loop {
+                while let Ok((message, size)) = Message::from_bytes(pending) {
+                    pending.drain(..size);
+                    if let Message::ClipboardUpdate { content, .. } = message {
+                        return content;
+                    }
+                }
+                let n = socket.read(&mut buffer).await.unwrap();
+                pending.extend_from_slice(&buffer[..n]);
+            }
+        })
+        .await
+        .ok()
     }
 }
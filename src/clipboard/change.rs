@@ -0,0 +1,242 @@
+// Cheap clipboard change detection, so the monitor only does a full read
+// (and PNG encode, for images) when something may actually have changed
+
+use std::time::Duration;
+use tracing::info;
+
+/// With XFixes events, how often the clipboard is still read in case an
+/// event was missed (e.g. the X connection dropped)
+#[cfg(target_os = "linux")]
+const EVENT_FALLBACK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Paces a clipboard monitor: waits for an OS change notification where one
+/// is available, otherwise for the poll interval
+pub struct ChangeWatcher {
+    source: Source,
+}
+
+enum Source {
+    /// No notifications; every poll reads the clipboard
+    Poll,
+    /// `NSPasteboard.changeCount` bumps on every write, and is cheap to read
+    #[cfg(target_os = "macos")]
+    ChangeCount { last: isize },
+    /// Selection-owner changes reported by the X server
+    #[cfg(target_os = "linux")]
+    XFixes(xfixes::Listener),
+}
+
+impl ChangeWatcher {
+    /// Use the platform's change notifications if `enabled` and available
+    pub fn new(enabled: bool) -> Self {
+        let source = if enabled {
+            Source::detect()
+        } else {
+            Source::Poll
+        };
+        match source {
+            Source::Poll => info!("Detecting clipboard changes by polling"),
+            #[cfg(target_os = "macos")]
+            Source::ChangeCount { .. } => info!("Detecting clipboard changes via NSPasteboard changeCount"),
+            #[cfg(target_os = "linux")]
+            Source::XFixes(_) => info!("Detecting clipboard changes via XFixes selection events"),
+        }
+        Self { source }
+    }
+
+    /// Wait for the next check, returning whether the clipboard may have
+    /// changed since the last one and so needs to be read
+    pub async fn wait(&mut self, interval: Duration) -> bool {
+        match &mut self.source {
+            Source::Poll => {
+                tokio::time::sleep(interval).await;
+                true
+            }
+            #[cfg(target_os = "macos")]
+            Source::ChangeCount { last } => {
+                tokio::time::sleep(interval).await;
+                let count = macos::change_count();
+                std::mem::replace(last, count) != count
+            }
+            #[cfg(target_os = "linux")]
+            Source::XFixes(listener) => {
+                let fallback = interval.max(EVENT_FALLBACK_INTERVAL);
+                tokio::select! {
+                    _ = listener.changed.notified() => {}
+                    _ = tokio::time::sleep(fallback) => {}
+                }
+                if !listener.is_alive() {
+                    info!("Lost XFixes connection; falling back to polling");
+                    self.source = Source::Poll;
+                }
+                true
+            }
+        }
+    }
+}
+
+impl Source {
+    #[cfg(target_os = "macos")]
+    fn detect() -> Self {
+        Source::ChangeCount {
+            last: macos::change_count(),
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    fn detect() -> Self {
+        // XFixes under XWayland only sees X clients, so copies from native
+        // Wayland apps would wait for the fallback interval
+        if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+            tracing::debug!("Wayland session: not using XFixes selection events");
+            return Source::Poll;
+        }
+        match xfixes::Listener::spawn() {
+            Ok(listener) => Source::XFixes(listener),
+            Err(e) => {
+                tracing::debug!("XFixes selection events unavailable: {}", e);
+                Source::Poll
+            }
+        }
+    }
+
+    #[cfg(not(any(target_os = "macos", target_os = "linux")))]
+    fn detect() -> Self {
+        tracing::debug!("No clipboard change notifications on this platform");
+        Source::Poll
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod macos {
+    // Both calls are safe bindings as of objc2-app-kit 0.3.2
+    use objc2_app_kit::NSPasteboard;
+
+    pub fn change_count() -> isize {
+        NSPasteboard::generalPasteboard().changeCount()
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod xfixes {
+    use anyhow::Result;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use tokio::sync::Notify;
+    use tracing::{debug, warn};
+    use x11rb::connection::Connection;
+    use x11rb::protocol::xfixes::{ConnectionExt as _, SelectionEventMask};
+    use x11rb::protocol::xproto::{ConnectionExt as _, CreateWindowAux, WindowClass};
+    use x11rb::protocol::Event;
+
+    /// A thread blocked on the X connection, notifying on every change of
+    /// the CLIPBOARD selection's owner (i.e. every copy)
+    pub struct Listener {
+        pub changed: Arc<Notify>,
+        alive: Arc<AtomicBool>,
+    }
+
+    impl Listener {
+        pub fn spawn() -> Result<Self> {
+            let (conn, screen_num) = x11rb::connect(None)?;
+            conn.xfixes_query_version(5, 0)?.reply()?;
+
+            // Selection events are delivered to a window, so make an invisible one
+            let root = conn.setup().roots[screen_num].root;
+            let window = conn.generate_id()?;
+            conn.create_window(
+                x11rb::COPY_DEPTH_FROM_PARENT,
+                window,
+                root,
+                0,
+                0,
+                1,
+                1,
+                0,
+                WindowClass::INPUT_ONLY,
+                x11rb::COPY_FROM_PARENT,
+                &CreateWindowAux::new(),
+            )?;
+            let clipboard = conn.intern_atom(false, b"CLIPBOARD")?.reply()?.atom;
+            conn.xfixes_select_selection_input(
+                window,
+                clipboard,
+                SelectionEventMask::SET_SELECTION_OWNER
+                    | SelectionEventMask::SELECTION_WINDOW_DESTROY
+                    | SelectionEventMask::SELECTION_CLIENT_CLOSE,
+            )?;
+            conn.flush()?;
+
+            let changed = Arc::new(Notify::new());
+            let alive = Arc::new(AtomicBool::new(true));
+            let listener = Self {
+                changed: Arc::clone(&changed),
+                alive: Arc::clone(&alive),
+            };
+
+            std::thread::Builder::new()
+                .name("xfixes-listener".to_string())
+                .spawn(move || loop {
+                    match conn.wait_for_event() {
+                        Ok(Event::XfixesSelectionNotify(_)) => {
+                            debug!("XFixes: clipboard owner changed");
+                            changed.notify_one();
+                        }
+                        Ok(_) => {}
+                        Err(e) => {
+                            warn!("XFixes listener stopped: {}", e);
+                            alive.store(false, Ordering::Relaxed);
+                            changed.notify_one();
+                            break;
+                        }
+                    }
+                })?;
+
+            Ok(listener)
+        }
+
+        pub fn is_alive(&self) -> bool {
+            self.alive.load(Ordering::Relaxed)
+        }
+
+        /// A listener whose X connection has already dropped
+        #[cfg(test)]
+        pub fn dead() -> Self {
+            let listener = Self {
+                changed: Arc::new(Notify::new()),
+                alive: Arc::new(AtomicBool::new(false)),
+            };
+            listener.changed.notify_one();
+            listener
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::time::{timeout, Instant};
+
+    #[tokio::test]
+    async fn test_polling_waits_for_the_interval() {
+        let mut watcher = ChangeWatcher::new(false);
+        assert!(matches!(watcher.source, Source::Poll));
+
+        let start = Instant::now();
+        assert!(watcher.wait(Duration::from_millis(50)).await);
+        assert!(start.elapsed() >= Duration::from_millis(50));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_lost_xfixes_connection_falls_back_to_polling() {
+        let mut watcher = ChangeWatcher {
+            source: Source::XFixes(xfixes::Listener::dead()),
+        };
+
+        // Wakes on the listener's last notification, not the fallback interval
+        let changed = timeout(Duration::from_secs(1), watcher.wait(Duration::from_millis(10)));
+        assert!(changed.await.unwrap());
+        assert!(matches!(watcher.source, Source::Poll));
+    }
+}
@@ -1,89 +1,225 @@
-use anyhow::Result;
+use crate::config::{ClipboardPreference, TransformConfig};
+use crate::storage::models::{strip_html, ClipboardEntry};
+use anyhow::{Context, Result};
 use arboard::{Clipboard as ArboardClipboard, ImageData};
 use std::borrow::Cow;
+use tracing::{debug, warn};
 
+pub mod change;
 #[cfg(target_os = "linux")]
 mod xclip_fallback;
 
+/// Largest image width or height we will decode into an RGBA buffer
+const MAX_IMAGE_DIMENSION: u32 = 16_384;
+
 #[derive(Debug, Clone)]
 pub enum ClipboardContent {
     Text(String),
     Image(Vec<u8>), // PNG encoded
     Html(String),
-    // Add more types as needed
+    /// Any other clipboard target, kept as raw bytes under its MIME type
+    Other { mime: String, data: Vec<u8> },
 }
 
 pub struct ClipboardManager {
     clipboard: ArboardClipboard,
+    prefer: ClipboardPreference,
+    #[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+    xclip_targets: Vec<String>,
 }
 
-impl ClipboardManager {
-    pub fn new() -> Result<Self> {
-        Ok(Self {
-            clipboard: ArboardClipboard::new()?,
-        })
-    }
-
-    /// Get the current clipboard content
-    pub fn get_content(&mut self) -> Result<Option<ClipboardContent>> {
-        use tracing::{debug, warn};
+/// Raw clipboard reads, separated so read ordering can be tested without a display
+trait ClipboardBackend {
+    fn read_image(&mut self) -> Result<Option<ClipboardContent>>;
+    fn read_text(&mut self) -> Option<ClipboardContent>;
+}
 
-        // Try to get image first (higher priority)
-        match self.clipboard.get_image() {
+impl ClipboardBackend for ArboardClipboard {
+    fn read_image(&mut self) -> Result<Option<ClipboardContent>> {
+        match self.get_image() {
             Ok(image) => {
                 debug!("Found image in clipboard");
-                let png_data = Self::image_to_png(&image)?;
-                return Ok(Some(ClipboardContent::Image(png_data)));
+                Ok(encode_image(&image))
             }
             Err(e) => {
                 debug!("No image in clipboard: {}", e);
+                Ok(None)
             }
         }
+    }
 
-        // Try to get text
-        match self.clipboard.get_text() {
+    fn read_text(&mut self) -> Option<ClipboardContent> {
+        match self.get_text() {
             Ok(text) => {
                 debug!("Found text in clipboard via arboard: {} bytes", text.len());
-                return Ok(Some(ClipboardContent::Text(text)));
+                Some(ClipboardContent::Text(text))
             }
             Err(e) => {
-                warn!("arboard failed to get text from clipboard: {}", e);
+                debug!("arboard failed to get text from clipboard: {}", e);
+                None
+            }
+        }
+    }
+}
 
-                // Try xclip fallback on Linux
-                #[cfg(target_os = "linux")]
-                {
-                    warn!("Trying xclip fallback...");
+/// PNG-encode a clipboard image, or `None` (so reading moves on to text) if
+/// it can't be encoded, e.g. because of zero or mismatched dimensions
+fn encode_image(image: &ImageData) -> Option<ClipboardContent> {
+    match ClipboardManager::image_to_png(image) {
+        Ok(png_data) => Some(ClipboardContent::Image(png_data)),
+        Err(e) => {
+            warn!(
+                "Skipping {}x{} clipboard image that could not be encoded: {}",
+                image.width, image.height, e
+            );
+            None
+        }
+    }
+}
 
-                    // List available targets for debugging
-                    if let Ok(targets) = xclip_fallback::list_available_targets() {
-                        if !targets.is_empty() {
-                            debug!("Available clipboard targets: {:?}", targets);
-                        }
-                    }
+/// Try image and text in the preferred order, returning the first found
+fn read_preferred(
+    backend: &mut impl ClipboardBackend,
+    prefer: ClipboardPreference,
+) -> Result<Option<ClipboardContent>> {
+    match prefer {
+        ClipboardPreference::Image => match backend.read_image()? {
+            Some(content) => Ok(Some(content)),
+            None => Ok(backend.read_text()),
+        },
+        ClipboardPreference::Text => match backend.read_text() {
+            Some(content) => Ok(Some(content)),
+            None => backend.read_image(),
+        },
+    }
+}
 
-                    match xclip_fallback::get_text_via_xclip() {
-                        Ok(Some(text)) => {
-                            warn!("✓ xclip fallback succeeded! Found {} bytes", text.len());
-                            warn!("NOTE: arboard has compatibility issues with your clipboard manager");
-                            warn!("Using xclip fallback mode for clipboard access");
-                            return Ok(Some(ClipboardContent::Text(text)));
-                        }
-                        Ok(None) => {
-                            debug!("xclip also reports clipboard empty");
-                        }
-                        Err(xe) => {
-                            warn!("xclip fallback also failed: {}", xe);
-                        }
-                    }
+/// Write text arboard couldn't through xclip
+#[cfg(target_os = "linux")]
+fn set_text_fallback(text: &str, _error: arboard::Error) -> Result<()> {
+    warn!("Trying xclip fallback for write...");
+    xclip_fallback::set_text_via_xclip(text)?;
+    warn!("✓ xclip fallback write succeeded");
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_text_fallback(_text: &str, error: arboard::Error) -> Result<()> {
+    Err(error.into())
+}
+
+/// Write an image arboard couldn't, handing wl-copy/xclip the PNG bytes as they are
+#[cfg(target_os = "linux")]
+fn set_image_fallback(png_data: &[u8], _error: arboard::Error) -> Result<()> {
+    warn!("Trying wl-copy/xclip fallback for image write...");
+    xclip_fallback::set_image_via_tools(png_data)?;
+    warn!("✓ Image fallback write succeeded");
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_image_fallback(_png_data: &[u8], error: arboard::Error) -> Result<()> {
+    Err(error.into())
+}
+
+/// Whether the clipboard already holds `content`. Only text and images can
+/// be read back to compare; HTML and other formats are always rewritten.
+fn already_holds(backend: &mut impl ClipboardBackend, content: &ClipboardContent) -> bool {
+    let current = match content {
+        ClipboardContent::Text(_) => backend.read_text(),
+        ClipboardContent::Image(_) => backend.read_image().ok().flatten(),
+        ClipboardContent::Html(_) | ClipboardContent::Other { .. } => return false,
+    };
+    current.is_some_and(|current| current.checksum() == content.checksum())
+}
+
+impl ClipboardManager {
+    pub fn new() -> Result<Self> {
+        Ok(Self {
+            clipboard: ArboardClipboard::new()?,
+            prefer: ClipboardPreference::default(),
+            xclip_targets: Vec::new(),
+        })
+    }
+
+    /// Choose what to read when the clipboard holds both an image and text
+    pub fn with_preference(mut self, prefer: ClipboardPreference) -> Self {
+        self.prefer = prefer;
+        self
+    }
+
+    /// Text targets for the Linux xclip fallback, in order; empty uses the defaults
+    pub fn with_xclip_targets(mut self, targets: Vec<String>) -> Self {
+        self.xclip_targets = targets;
+        self
+    }
+
+    /// Get the current clipboard content
+    pub fn get_content(&mut self) -> Result<Option<ClipboardContent>> {
+        if let Some(content) = read_preferred(&mut self.clipboard, self.prefer)? {
+            return Ok(Some(content));
+        }
+
+        warn!("arboard found neither text nor an image in the clipboard");
+
+        // Try xclip fallback on Linux
+        #[cfg(target_os = "linux")]
+        {
+            warn!("Trying xclip fallback...");
+
+            // List available targets for debugging
+            if let Ok(targets) = xclip_fallback::list_available_targets() {
+                if !targets.is_empty() {
+                    debug!("Available clipboard targets: {:?}", targets);
                 }
+            }
+
+            match xclip_fallback::get_text_via_xclip(&self.xclip_targets) {
+                Ok(Some(text)) => {
+                    warn!("✓ xclip fallback succeeded! Found {} bytes", text.len());
+                    warn!("NOTE: arboard has compatibility issues with your clipboard manager");
+                    warn!("Using xclip fallback mode for clipboard access");
+                    return Ok(Some(ClipboardContent::Text(text)));
+                }
+                Ok(None) => {
+                    debug!("xclip also reports clipboard empty");
+                }
+                Err(xe) => {
+                    warn!("xclip fallback also failed: {}", xe);
+                }
+            }
+
+            match xclip_fallback::get_image_via_tools() {
+                Ok(Some(png_data)) => {
+                    warn!("✓ Image fallback succeeded! Found {} bytes of PNG", png_data.len());
+                    return Ok(Some(ClipboardContent::Image(png_data)));
+                }
+                Ok(None) => {
+                    debug!("wl-paste/xclip found no PNG image either");
+                }
+                Err(e) => {
+                    warn!("Image fallback also failed: {}", e);
+                }
+            }
 
-                warn!("This usually means:");
-                warn!("  - Clipboard is genuinely empty");
-                warn!("  - Or clipboard has unsupported format");
-                warn!("  - Or wrong clipboard selection (PRIMARY vs CLIPBOARD)");
+            // Keep formats we can't interpret (e.g. image/svg+xml) as raw bytes
+            match xclip_fallback::get_mime_via_xclip() {
+                Ok(Some((mime, data))) => {
+                    debug!("Captured {} bytes of {} via xclip", data.len(), mime);
+                    return Ok(Some(ClipboardContent::Other { mime, data }));
+                }
+                Ok(None) => {}
+                Err(xe) => {
+                    debug!("xclip could not read other clipboard targets: {}", xe);
+                }
             }
         }
 
+        warn!("This usually means:");
+        warn!("  - Clipboard is genuinely empty");
+        warn!("  - Or clipboard has unsupported format");
+        warn!("  - Or wrong clipboard selection (PRIMARY vs CLIPBOARD)");
+
         // Try to get HTML (if available on platform)
         #[cfg(target_os = "linux")]
         {
@@ -94,89 +230,132 @@ impl ClipboardManager {
         Ok(None)
     }
 
-    /// Set clipboard content
-    pub fn set_content(&mut self, content: &ClipboardContent) -> Result<()> {
-        use tracing::warn;
+    /// Whether the clipboard owner marked the content as secret, as password
+    /// managers do. Only detectable on Linux (via the X11 TARGETS list).
+    pub fn content_is_sensitive(&self) -> bool {
+        #[cfg(target_os = "linux")]
+        {
+            xclip_fallback::list_available_targets()
+                .map(|targets| xclip_fallback::has_sensitive_target(&targets))
+                .unwrap_or(false)
+        }
+
+        #[cfg(not(target_os = "linux"))]
+        false
+    }
+
+    /// Set clipboard content, returning `false` without writing if the
+    /// clipboard already holds it, so listeners see no spurious change
+    pub fn set_content(&mut self, content: &ClipboardContent) -> Result<bool> {
+        if already_holds(&mut self.clipboard, content) {
+            debug!("Clipboard already holds this content; not rewriting it");
+            return Ok(false);
+        }
+        self.write_content(content)?;
+        Ok(true)
+    }
 
+    fn write_content(&mut self, content: &ClipboardContent) -> Result<()> {
         match content {
             ClipboardContent::Text(text) => {
                 match self.clipboard.set_text(text) {
                     Ok(_) => Ok(()),
                     Err(e) => {
                         warn!("arboard failed to set text: {}", e);
-
-                        // Try xclip fallback on Linux
-                        #[cfg(target_os = "linux")]
-                        {
-                            warn!("Trying xclip fallback for write...");
-                            xclip_fallback::set_text_via_xclip(text)?;
-                            warn!("✓ xclip fallback write succeeded");
-                            return Ok(());
-                        }
-
-                        #[cfg(not(target_os = "linux"))]
-                        return Err(e.into());
+                        set_text_fallback(text, e)
                     }
                 }
             }
             ClipboardContent::Image(png_data) => {
-                let image_data = Self::png_to_image_static(png_data)?;
-                self.clipboard.set_image(image_data)?;
-                Ok(())
+                let image_data = Self::png_to_image_static(png_data)
+                    .context("Received image could not be decoded")?;
+                match self.clipboard.set_image(image_data) {
+                    Ok(_) => Ok(()),
+                    Err(e) => {
+                        warn!("arboard failed to set image: {}", e);
+                        set_image_fallback(png_data, e)
+                    }
+                }
             }
             ClipboardContent::Html(html) => {
-                // For now, fall back to text
-                // Platform-specific HTML handling can be added
-                match self.clipboard.set_text(html) {
+                // Offer a plain-text alternate so apps that can't paste HTML
+                // (terminals, editors) still get the text
+                let plain = strip_html(html);
+                match self.clipboard.set_html(html.as_str(), Some(plain.as_str())) {
                     Ok(_) => Ok(()),
                     Err(e) => {
-                        #[cfg(target_os = "linux")]
-                        {
-                            warn!("arboard failed, trying xclip fallback...");
-                            xclip_fallback::set_text_via_xclip(html)?;
-                            return Ok(());
-                        }
-
-                        #[cfg(not(target_os = "linux"))]
-                        return Err(e.into());
+                        warn!("arboard failed to set HTML: {}", e);
+                        // xclip owns a single target, so only the text survives
+                        set_text_fallback(&plain, e)
                     }
                 }
             }
+            ClipboardContent::Other { mime, data } => {
+                #[cfg(target_os = "linux")]
+                {
+                    xclip_fallback::set_mime_via_xclip(mime, data)
+                }
+
+                // Without per-target access, textual formats are still useful as text
+                #[cfg(not(target_os = "linux"))]
+                match std::str::from_utf8(data) {
+                    Ok(text) => {
+                        warn!("Setting {} clipboard content as plain text", mime);
+                        self.clipboard.set_text(text)?;
+                        Ok(())
+                    }
+                    Err(_) => Err(anyhow::anyhow!(
+                        "Cannot set {} clipboard content on this platform",
+                        mime
+                    )),
+                }
+            }
         }
     }
 
     /// Get a checksum of the current clipboard content
     pub fn get_content_checksum(&mut self) -> Result<Option<String>> {
         if let Some(content) = self.get_content()? {
-            Ok(Some(self.calculate_checksum(&content)))
+            Ok(Some(content.checksum()))
         } else {
             Ok(None)
         }
     }
 
-    fn calculate_checksum(&self, content: &ClipboardContent) -> String {
-        use std::collections::hash_map::DefaultHasher;
-        use std::hash::{Hash, Hasher};
-
-        let mut hasher = DefaultHasher::new();
-        match content {
-            ClipboardContent::Text(text) => text.hash(&mut hasher),
-            ClipboardContent::Image(data) => data.hash(&mut hasher),
-            ClipboardContent::Html(html) => html.hash(&mut hasher),
-        }
-        format!("{:x}", hasher.finish())
-    }
-
+    /// PNG-encode raw clipboard pixels. arboard documents RGBA, but some
+    /// backends hand over RGB, grey or grey+alpha rows; those are converted
+    /// by inferring the channel count from the buffer length.
     fn image_to_png(image: &ImageData) -> Result<Vec<u8>> {
-        use image::{ImageBuffer, RgbaImage};
+        use image::{DynamicImage, ImageBuffer};
         use std::io::Cursor;
 
-        let img: RgbaImage = ImageBuffer::from_raw(
-            image.width as u32,
-            image.height as u32,
-            image.bytes.to_vec(),
-        )
-        .ok_or_else(|| anyhow::anyhow!("Failed to create image buffer"))?;
+        let (width, height) = (image.width as u32, image.height as u32);
+        let pixels = image.width * image.height;
+        if pixels == 0 {
+            anyhow::bail!("image has zero width or height");
+        }
+
+        let bytes = image.bytes.to_vec();
+        let len = bytes.len();
+        let channels = if len.is_multiple_of(pixels) { len / pixels } else { 0 };
+        let decoded = match channels {
+            4 => ImageBuffer::from_raw(width, height, bytes).map(DynamicImage::ImageRgba8),
+            3 => ImageBuffer::from_raw(width, height, bytes).map(DynamicImage::ImageRgb8),
+            2 => ImageBuffer::from_raw(width, height, bytes).map(DynamicImage::ImageLumaA8),
+            1 => ImageBuffer::from_raw(width, height, bytes).map(DynamicImage::ImageLuma8),
+            _ => None,
+        };
+        let img = decoded
+            .ok_or_else(|| {
+                anyhow::anyhow!(
+                    "{}x{} image has {} bytes, expected {} (RGBA) or 1-3 bytes per pixel",
+                    width,
+                    height,
+                    len,
+                    pixels * 4
+                )
+            })?
+            .to_rgba8();
 
         let mut png_data = Vec::new();
         img.write_to(&mut Cursor::new(&mut png_data), image::ImageFormat::Png)?;
@@ -185,13 +364,17 @@ impl ClipboardManager {
     }
 
     fn png_to_image_static(png_data: &[u8]) -> Result<ImageData<'_>> {
-        use image::ImageReader;
+        use image::{ImageReader, Limits};
         use std::io::Cursor;
 
-        let img = ImageReader::new(Cursor::new(png_data))
-            .with_guessed_format()?
-            .decode()?
-            .to_rgba8();
+        // Guard against decompression bombs: a tiny PNG can claim huge dimensions
+        let mut limits = Limits::default();
+        limits.max_image_width = Some(MAX_IMAGE_DIMENSION);
+        limits.max_image_height = Some(MAX_IMAGE_DIMENSION);
+
+        let mut reader = ImageReader::new(Cursor::new(png_data)).with_guessed_format()?;
+        reader.limits(limits);
+        let img = reader.decode()?.to_rgba8();
 
         let (width, height) = img.dimensions();
 
@@ -211,12 +394,41 @@ impl ClipboardContent {
             ClipboardContent::Text(text) => text.clone(),
             ClipboardContent::Image(data) => STANDARD.encode(data),
             ClipboardContent::Html(html) => html.clone(),
+            ClipboardContent::Other { data, .. } => STANDARD.encode(data),
+        }
+    }
+
+    /// JSON metadata to store and send alongside the content, if any
+    pub fn metadata(&self) -> Option<String> {
+        match self {
+            ClipboardContent::Other { mime, .. } => {
+                Some(serde_json::json!({ "mime": mime }).to_string())
+            }
+            _ => None,
         }
     }
 
-    pub fn from_base64(content_type: &str, data: &str) -> Result<Self> {
+    /// Decode wire content, rejecting anything that would exceed `max_bytes` once decoded
+    pub fn from_base64(
+        content_type: &str,
+        data: &str,
+        metadata: Option<&str>,
+        max_bytes: usize,
+    ) -> Result<Self> {
         use base64::{engine::general_purpose::STANDARD, Engine};
 
+        let decoded_len = match content_type {
+            "image" | "other" => data.len() / 4 * 3,
+            _ => data.len(),
+        };
+        if decoded_len > max_bytes {
+            return Err(anyhow::anyhow!(
+                "Clipboard content too large: {} bytes exceeds limit of {} bytes",
+                decoded_len,
+                max_bytes
+            ));
+        }
+
         match content_type {
             "text" => Ok(ClipboardContent::Text(data.to_string())),
             "image" => {
@@ -224,15 +436,291 @@ impl ClipboardContent {
                 Ok(ClipboardContent::Image(decoded))
             }
             "html" => Ok(ClipboardContent::Html(data.to_string())),
+            "other" => {
+                let mime = metadata
+                    .and_then(|m| serde_json::from_str::<serde_json::Value>(m).ok())
+                    .and_then(|m| m.get("mime")?.as_str().map(str::to_string))
+                    .ok_or_else(|| anyhow::anyhow!("Missing MIME type for other clipboard content"))?;
+                Ok(ClipboardContent::Other {
+                    mime,
+                    data: STANDARD.decode(data)?,
+                })
+            }
             _ => Err(anyhow::anyhow!("Unknown content type: {}", content_type)),
         }
     }
 
+    /// A description for logs: type and size, followed by up to
+    /// `max_chars` characters of text when that is non-zero
+    pub fn preview(&self, max_chars: usize) -> String {
+        match self {
+            ClipboardContent::Text(text) | ClipboardContent::Html(text) if max_chars > 0 => {
+                let shown = crate::output::truncate_chars(text, max_chars);
+                let ellipsis = if shown.len() < text.len() { "..." } else { "" };
+                format!("[{}: {} bytes] {:?}{}", self.content_type_str(), text.len(), shown, ellipsis)
+            }
+            ClipboardContent::Text(text) | ClipboardContent::Html(text) => {
+                format!("[{}: {} bytes]", self.content_type_str(), text.len())
+            }
+            ClipboardContent::Image(data) => format!("[Image: {} bytes]", data.len()),
+            ClipboardContent::Other { mime, data } => format!("[{}: {} bytes]", mime, data.len()),
+        }
+    }
+
+    /// Size of the content itself, before any base64 encoding
+    pub fn size_bytes(&self) -> usize {
+        match self {
+            ClipboardContent::Text(text) | ClipboardContent::Html(text) => text.len(),
+            ClipboardContent::Image(data) | ClipboardContent::Other { data, .. } => data.len(),
+        }
+    }
+
+    /// Checksum of the decoded content; equals the `checksum` of the
+    /// `ClipboardEntry` stored for it
+    pub fn checksum(&self) -> String {
+        match self {
+            ClipboardContent::Text(text) | ClipboardContent::Html(text) => {
+                ClipboardEntry::calculate_checksum(text)
+            }
+            ClipboardContent::Image(data) | ClipboardContent::Other { data, .. } => {
+                ClipboardEntry::checksum_bytes(data)
+            }
+        }
+    }
+
+    /// Whether a received `checksum` covers this content
+    pub fn matches_checksum(&self, checksum: &str) -> bool {
+        self.checksum() == checksum
+    }
+
     pub fn content_type_str(&self) -> &str {
         match self {
             ClipboardContent::Text(_) => "text",
             ClipboardContent::Image(_) => "image",
             ClipboardContent::Html(_) => "html",
+            ClipboardContent::Other { .. } => "other",
+        }
+    }
+
+    /// Apply the configured text rewrites; non-text content is unchanged
+    pub fn transformed(self, transform: &TransformConfig) -> Self {
+        match self {
+            ClipboardContent::Text(text) => ClipboardContent::Text(transform_text(&text, transform)),
+            other => other,
+        }
+    }
+}
+
+/// Rewrite line endings and trailing whitespace as configured
+fn transform_text(text: &str, transform: &TransformConfig) -> String {
+    let text = if transform.normalize_line_endings {
+        text.replace("\r\n", "\n").replace('\r', "\n")
+    } else {
+        text.to_string()
+    };
+
+    if !transform.trim_trailing_whitespace {
+        return text;
+    }
+
+    let mut trimmed = String::with_capacity(text.len());
+    for line in text.split_inclusive('\n') {
+        let body = line.trim_end_matches(['\r', '\n']);
+        trimmed.push_str(body.trim_end_matches([' ', '\t']));
+        trimmed.push_str(&line[body.len()..]);
+    }
+    trimmed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A clipboard holding both an image and text, like a spreadsheet copy
+    struct BothFormats;
+
+    impl ClipboardBackend for BothFormats {
+        fn read_image(&mut self) -> Result<Option<ClipboardContent>> {
+            Ok(Some(ClipboardContent::Image(vec![1, 2, 3])))
+        }
+
+        fn read_text(&mut self) -> Option<ClipboardContent> {
+            Some(ClipboardContent::Text("A1\tB1".to_string()))
+        }
+    }
+
+    #[test]
+    fn test_read_preference_orders_attempts() {
+        let text = read_preferred(&mut BothFormats, ClipboardPreference::Text).unwrap();
+        assert!(matches!(text, Some(ClipboardContent::Text(_))));
+
+        let image = read_preferred(&mut BothFormats, ClipboardPreference::Image).unwrap();
+        assert!(matches!(image, Some(ClipboardContent::Image(_))));
+    }
+
+    #[test]
+    fn test_setting_current_content_is_skipped() {
+        let same = ClipboardContent::Text("A1\tB1".to_string());
+        assert!(already_holds(&mut BothFormats, &same));
+        assert!(already_holds(&mut BothFormats, &ClipboardContent::Image(vec![1, 2, 3])));
+
+        let changed = ClipboardContent::Text("A2\tB2".to_string());
+        assert!(!already_holds(&mut BothFormats, &changed));
+        // HTML can't be read back, so it is always written
+        let html = ClipboardContent::Html("A1\tB1".to_string());
+        assert!(!already_holds(&mut BothFormats, &html));
+    }
+
+    #[test]
+    fn test_transform_normalizes_line_endings() {
+        let transform = TransformConfig {
+            normalize_line_endings: true,
+            ..Default::default()
+        };
+        assert_eq!(transform_text("a\r\nb\rc\n", &transform), "a\nb\nc\n");
+        assert_eq!(
+            transform_text("a\r\nb", &TransformConfig::default()),
+            "a\r\nb"
+        );
+    }
+
+    #[test]
+    fn test_transform_trims_trailing_whitespace() {
+        let transform = TransformConfig {
+            trim_trailing_whitespace: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            transform_text("fn main() {  \r\n\tbody\t\n}  ", &transform),
+            "fn main() {\r\n\tbody\n}"
+        );
+
+        let image = ClipboardContent::Image(vec![b' ']).transformed(&transform);
+        assert!(matches!(image, ClipboardContent::Image(data) if data == b" "));
+    }
+
+    #[test]
+    fn test_from_base64_rejects_oversized_payload() {
+        let data = "A".repeat(4096);
+        assert!(ClipboardContent::from_base64("image", &data, None, 1024).is_err());
+        assert!(ClipboardContent::from_base64("text", &data, None, 1024).is_err());
+        assert!(ClipboardContent::from_base64("image", &data, None, 4096).is_ok());
+    }
+
+    #[test]
+    fn test_other_content_round_trips_with_mime() {
+        let content = ClipboardContent::Other {
+            mime: "image/svg+xml".to_string(),
+            data: b"<svg/>".to_vec(),
+        };
+        let metadata = content.metadata();
+
+        let decoded = ClipboardContent::from_base64(
+            content.content_type_str(),
+            &content.to_base64(),
+            metadata.as_deref(),
+            1024,
+        )
+        .unwrap();
+
+        match decoded {
+            ClipboardContent::Other { mime, data } => {
+                assert_eq!(mime, "image/svg+xml");
+                assert_eq!(data, b"<svg/>");
+            }
+            _ => panic!("Wrong content type"),
         }
+        assert!(ClipboardContent::from_base64("other", "PHN2Zy8+", None, 1024).is_err());
+    }
+
+    #[test]
+    fn test_monitor_checksum_equals_stored_checksum() {
+        use crate::storage::models::ClipboardContentType;
+
+        let contents = [
+            ClipboardContent::Text("hello".to_string()),
+            ClipboardContent::Html("<b>hi</b>".to_string()),
+            ClipboardContent::Image(vec![0x89, b'P', b'N', b'G']),
+            ClipboardContent::Other {
+                mime: "image/svg+xml".to_string(),
+                data: b"<svg/>".to_vec(),
+            },
+        ];
+        for content in &contents {
+            let entry = ClipboardEntry::new(
+                ClipboardContentType::from(content.content_type_str()),
+                content.to_base64(),
+                "test".to_string(),
+            );
+            assert_eq!(content.checksum(), entry.checksum);
+        }
+
+        // SHA-256, the same on every build
+        assert_eq!(
+            contents[0].checksum(),
+            "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824"
+        );
+    }
+
+    #[test]
+    fn test_preview_hides_text_unless_enabled() {
+        let text = ClipboardContent::Text("héllo wörld".to_string());
+        assert_eq!(text.preview(0), "[text: 13 bytes]");
+        // Truncation counts characters, so multi-byte text never splits
+        assert_eq!(text.preview(2), "[text: 13 bytes] \"hé\"...");
+        assert_eq!(text.preview(50), "[text: 13 bytes] \"héllo wörld\"");
+    }
+
+    #[test]
+    fn test_unencodable_image_is_skipped() {
+        let empty = ImageData {
+            width: 0,
+            height: 0,
+            bytes: Cow::Owned(Vec::new()),
+        };
+        assert!(encode_image(&empty).is_none());
+
+        let tiny = ImageData {
+            width: 1,
+            height: 1,
+            bytes: Cow::Owned(vec![0, 0, 0, 255]),
+        };
+        assert!(matches!(encode_image(&tiny), Some(ClipboardContent::Image(_))));
+
+        // Neither RGBA nor a whole number of bytes per pixel
+        let mismatched = ImageData {
+            width: 2,
+            height: 2,
+            bytes: Cow::Owned(vec![0; 15]),
+        };
+        let err = ClipboardManager::image_to_png(&mismatched).unwrap_err();
+        assert!(err.to_string().contains("2x2 image has 15 bytes, expected 16"));
+        assert!(encode_image(&mismatched).is_none());
+    }
+
+    #[test]
+    fn test_rgb_image_is_converted_to_rgba() {
+        let rgb = ImageData {
+            width: 2,
+            height: 1,
+            bytes: Cow::Owned(vec![255, 0, 0, 0, 0, 255]),
+        };
+        let png = ClipboardManager::image_to_png(&rgb).unwrap();
+
+        let decoded = ClipboardManager::png_to_image_static(&png).unwrap();
+        assert_eq!(decoded.bytes.as_ref(), &[255, 0, 0, 255, 0, 0, 255, 255]);
+    }
+
+    #[test]
+    fn test_png_with_huge_dimensions_is_rejected() {
+        use image::{ImageBuffer, Rgba};
+        use std::io::Cursor;
+
+        let img = ImageBuffer::from_pixel(MAX_IMAGE_DIMENSION + 1, 1, Rgba([0u8, 0, 0, 255]));
+        let mut png = Vec::new();
+        img.write_to(&mut Cursor::new(&mut png), image::ImageFormat::Png)
+            .unwrap();
+
+        assert!(ClipboardManager::png_to_image_static(&png).is_err());
     }
 }
@@ -1,61 +1,83 @@
-// Fallback clipboard implementation using xclip directly
+// Fallback clipboard implementation using xclip (and wl-clipboard for images)
 // Used when arboard can't access clipboard (some clipboard managers)
 
 use anyhow::Result;
 use std::process::Command;
 use tracing::{debug, warn};
 
-pub fn get_text_via_xclip() -> Result<Option<String>> {
-    debug!("Attempting to read clipboard via xclip fallback");
-
-    // Helper function to validate clipboard content
-    fn is_valid_content(content: &str) -> bool {
-        // Reject empty content
-        if content.is_empty() {
-            return false;
-        }
+/// Text targets tried in order when `clipboard.xclip_targets` is unset.
+/// UTF8_STRING is the most reliable for Ghostty and modern terminals.
+pub const DEFAULT_TEXT_TARGETS: &[&str] = &["UTF8_STRING", "STRING", "TEXT", "text/plain"];
 
-        // If content is more than 1 character, it's valid
-        if content.len() > 1 {
-            return true;
-        }
+// Helper function to validate clipboard content
+fn is_valid_content(content: &str) -> bool {
+    // Reject empty content
+    if content.is_empty() {
+        return false;
+    }
 
-        // Single character - only accept if it's alphanumeric
-        // This filters out error indicators like '(' from xclip
-        let ch = content.chars().next().unwrap();
-        ch.is_alphanumeric()
+    // If content is more than 1 character, it's valid
+    if content.len() > 1 {
+        return true;
     }
 
-    // Try multiple targets in order of reliability for Ghostty and modern terminals
-    // UTF8_STRING is most reliable for Ghostty terminal
-    let targets = ["UTF8_STRING", "STRING", "TEXT", "text/plain"];
+    // Single character - only accept if it's alphanumeric
+    // This filters out error indicators like '(' from xclip
+    let ch = content.chars().next().unwrap();
+    ch.is_alphanumeric()
+}
+
+/// Read `targets` in order (the defaults if empty), returning the first
+/// valid text
+fn read_first_target(
+    targets: &[String],
+    mut read: impl FnMut(&str) -> Result<Option<String>>,
+) -> Result<Option<String>> {
+    let targets: Vec<&str> = if targets.is_empty() {
+        DEFAULT_TEXT_TARGETS.to_vec()
+    } else {
+        targets.iter().map(String::as_str).collect()
+    };
 
-    for target in &targets {
+    for target in targets {
         debug!("Trying xclip target: {}", target);
 
-        let output = Command::new("xclip")
-            .args(&["-o", "-selection", "clipboard", "-t", target])
-            .output()?;
-
-        if output.status.success() {
-            if let Ok(content) = String::from_utf8(output.stdout) {
-                if is_valid_content(&content) {
-                    debug!("xclip: found {} bytes via {} target", content.len(), target);
-                    return Ok(Some(content));
-                } else {
-                    debug!("xclip: target {} returned invalid/empty content", target);
-                }
+        match read(target)? {
+            Some(content) if is_valid_content(&content) => {
+                debug!("xclip: found {} bytes via {} target", content.len(), target);
+                return Ok(Some(content));
             }
-        } else {
-            let error = String::from_utf8_lossy(&output.stderr);
-            debug!("xclip failed with target {}: {}", target, error);
+            _ => debug!("xclip: target {} returned invalid/empty content", target),
         }
     }
+    Ok(None)
+}
+
+fn read_xclip_target(target: &str) -> Result<Option<String>> {
+    let output = Command::new("xclip")
+        .args(["-o", "-selection", "clipboard", "-t", target])
+        .output()?;
+
+    if !output.status.success() {
+        let error = String::from_utf8_lossy(&output.stderr);
+        debug!("xclip failed with target {}: {}", target, error);
+        return Ok(None);
+    }
+    Ok(String::from_utf8(output.stdout).ok())
+}
+
+/// Read clipboard text, trying `targets` in order (the defaults if empty)
+pub fn get_text_via_xclip(targets: &[String]) -> Result<Option<String>> {
+    debug!("Attempting to read clipboard via xclip fallback");
+
+    if let Some(content) = read_first_target(targets, read_xclip_target)? {
+        return Ok(Some(content));
+    }
 
     // Try xsel as a last resort
     debug!("Trying xsel as alternative...");
     if let Ok(xsel_output) = Command::new("xsel")
-        .args(&["-o", "-b"])
+        .args(["-o", "-b"])
         .output()
     {
         if xsel_output.status.success() {
@@ -76,7 +98,7 @@ pub fn set_text_via_xclip(text: &str) -> Result<()> {
     debug!("Attempting to write clipboard via xclip fallback");
 
     let mut child = Command::new("xclip")
-        .args(&["-selection", "clipboard"])
+        .args(["-selection", "clipboard"])
         .stdin(std::process::Stdio::piped())
         .spawn()?;
 
@@ -95,24 +117,11 @@ pub fn set_text_via_xclip(text: &str) -> Result<()> {
     Ok(())
 }
 
-pub fn get_checksum_via_xclip() -> Result<Option<String>> {
-    if let Some(text) = get_text_via_xclip()? {
-        use std::collections::hash_map::DefaultHasher;
-        use std::hash::{Hash, Hasher};
-
-        let mut hasher = DefaultHasher::new();
-        text.hash(&mut hasher);
-        Ok(Some(format!("{:x}", hasher.finish())))
-    } else {
-        Ok(None)
-    }
-}
-
 pub fn list_available_targets() -> Result<Vec<String>> {
     debug!("Listing available clipboard targets");
 
     let output = Command::new("xclip")
-        .args(&["-o", "-selection", "clipboard", "-t", "TARGETS"])
+        .args(["-o", "-selection", "clipboard", "-t", "TARGETS"])
         .output()?;
 
     if !output.status.success() {
@@ -130,3 +139,190 @@ pub fn list_available_targets() -> Result<Vec<String>> {
     debug!("Available clipboard targets: {:?}", targets);
     Ok(targets)
 }
+
+/// Pick a MIME target worth preserving when neither text nor an image could be read
+fn pick_mime_target(targets: &[String]) -> Option<&str> {
+    targets
+        .iter()
+        .map(String::as_str)
+        .find(|t| t.contains('/') && !t.starts_with("text/plain"))
+}
+
+/// Password managers offer one of these targets to ask that content not be recorded
+pub fn has_sensitive_target(targets: &[String]) -> bool {
+    targets.iter().any(|t| {
+        t == "x-kde-passwordManagerHint" || t.to_ascii_lowercase().contains("concealed")
+    })
+}
+
+pub fn get_mime_via_xclip() -> Result<Option<(String, Vec<u8>)>> {
+    let targets = list_available_targets()?;
+    let Some(mime) = pick_mime_target(&targets) else {
+        return Ok(None);
+    };
+
+    debug!("Reading clipboard target {} via xclip", mime);
+    let output = Command::new("xclip")
+        .args(["-o", "-selection", "clipboard", "-t", mime])
+        .output()?;
+
+    if !output.status.success() || output.stdout.is_empty() {
+        return Ok(None);
+    }
+
+    Ok(Some((mime.to_string(), output.stdout)))
+}
+
+pub fn set_mime_via_xclip(mime: &str, data: &[u8]) -> Result<()> {
+    let mut child = Command::new("xclip")
+        .args(["-selection", "clipboard", "-t", mime])
+        .stdin(std::process::Stdio::piped())
+        .spawn()?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        use std::io::Write;
+        stdin.write_all(data)?;
+    }
+
+    if !child.wait()?.success() {
+        return Err(anyhow::anyhow!("xclip write failed for {}", mime));
+    }
+
+    debug!("xclip: wrote {} bytes as {}", data.len(), mime);
+    Ok(())
+}
+
+const PNG_MAGIC: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+/// A clipboard tool and the arguments for one image operation
+type ToolCommand = (&'static str, &'static [&'static str]);
+
+/// Commands that print the clipboard's PNG image, wl-paste first under Wayland
+fn image_read_commands(wayland: bool) -> Vec<ToolCommand> {
+    let xclip: ToolCommand = ("xclip", &["-selection", "clipboard", "-t", "image/png", "-o"]);
+    if wayland {
+        vec![("wl-paste", &["-t", "image/png"]), xclip]
+    } else {
+        vec![xclip]
+    }
+}
+
+/// Commands that take a PNG image on stdin, wl-copy first under Wayland
+fn image_write_commands(wayland: bool) -> Vec<ToolCommand> {
+    let xclip: ToolCommand = ("xclip", &["-selection", "clipboard", "-t", "image/png", "-i"]);
+    if wayland {
+        vec![("wl-copy", &["-t", "image/png"]), xclip]
+    } else {
+        vec![xclip]
+    }
+}
+
+fn is_wayland() -> bool {
+    std::env::var_os("WAYLAND_DISPLAY").is_some()
+}
+
+/// Read the clipboard's image as PNG bytes via wl-paste or xclip
+pub fn get_image_via_tools() -> Result<Option<Vec<u8>>> {
+    for (program, args) in image_read_commands(is_wayland()) {
+        debug!("Trying {} for a PNG image", program);
+
+        match Command::new(program).args(args).output() {
+            Ok(output) if output.status.success() && output.stdout.starts_with(PNG_MAGIC) => {
+                debug!("{}: found {} bytes of PNG", program, output.stdout.len());
+                return Ok(Some(output.stdout));
+            }
+            Ok(_) => debug!("{}: no PNG image in clipboard", program),
+            Err(e) => debug!("{} unavailable: {}", program, e),
+        }
+    }
+    Ok(None)
+}
+
+/// Put PNG bytes on the clipboard via wl-copy or xclip
+pub fn set_image_via_tools(png_data: &[u8]) -> Result<()> {
+    let mut last_error = anyhow::anyhow!("no clipboard tool could write the image");
+
+    for (program, args) in image_write_commands(is_wayland()) {
+        let mut child = match Command::new(program)
+            .args(args)
+            .stdin(std::process::Stdio::piped())
+            .spawn()
+        {
+            Ok(child) => child,
+            Err(e) => {
+                debug!("{} unavailable: {}", program, e);
+                last_error = anyhow::anyhow!("{} unavailable: {}", program, e);
+                continue;
+            }
+        };
+
+        if let Some(mut stdin) = child.stdin.take() {
+            use std::io::Write;
+            stdin.write_all(png_data)?;
+        }
+
+        if child.wait()?.success() {
+            debug!("{}: wrote {} bytes of PNG", program, png_data.len());
+            return Ok(());
+        }
+        last_error = anyhow::anyhow!("{} image write failed", program);
+    }
+    Err(last_error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pick_mime_target_skips_x11_and_plain_text_targets() {
+        let targets: Vec<String> = ["TARGETS", "TIMESTAMP", "text/plain;charset=utf-8", "image/svg+xml"]
+            .iter()
+            .map(|t| t.to_string())
+            .collect();
+        assert_eq!(pick_mime_target(&targets), Some("image/svg+xml"));
+        assert_eq!(pick_mime_target(&targets[..3]), None);
+    }
+
+    #[test]
+    fn test_has_sensitive_target() {
+        let targets = |names: &[&str]| names.iter().map(|t| t.to_string()).collect::<Vec<_>>();
+
+        assert!(has_sensitive_target(&targets(&["UTF8_STRING", "x-kde-passwordManagerHint"])));
+        assert!(has_sensitive_target(&targets(&["UTF8_STRING", "org.nspasteboard.ConcealedType"])));
+        assert!(!has_sensitive_target(&targets(&["TARGETS", "UTF8_STRING", "text/plain"])));
+    }
+
+    #[test]
+    fn test_configured_text_targets_are_tried_in_order() {
+        let configured = vec!["text/plain;charset=utf-8".to_string(), "UTF8_STRING".to_string()];
+        let mut tried = Vec::new();
+        let text = read_first_target(&configured, |target| {
+            tried.push(target.to_string());
+            Ok((target == "UTF8_STRING").then(|| "from utf8".to_string()))
+        })
+        .unwrap();
+        assert_eq!(text.as_deref(), Some("from utf8"));
+        assert_eq!(tried, configured);
+
+        // Unset falls back to the built-in order
+        let mut tried = Vec::new();
+        read_first_target(&[], |target| {
+            tried.push(target.to_string());
+            Ok(None)
+        })
+        .unwrap();
+        assert_eq!(tried, DEFAULT_TEXT_TARGETS);
+    }
+
+    #[test]
+    fn test_image_commands_prefer_wl_clipboard_under_wayland() {
+        let programs = |commands: Vec<ToolCommand>| commands.iter().map(|c| c.0).collect::<Vec<_>>();
+
+        assert_eq!(programs(image_read_commands(true)), ["wl-paste", "xclip"]);
+        assert_eq!(programs(image_write_commands(true)), ["wl-copy", "xclip"]);
+        assert_eq!(image_read_commands(false), [("xclip", &["-selection", "clipboard", "-t", "image/png", "-o"][..])]);
+        assert_eq!(image_write_commands(false)[0].1, ["-selection", "clipboard", "-t", "image/png", "-i"]);
+        assert_eq!(image_read_commands(true)[0].1, ["-t", "image/png"]);
+    }
+}
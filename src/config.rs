@@ -1,6 +1,88 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::ffi::OsString;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use tracing::{debug, info, warn};
+
+/// `storage.database_path` value for a database that lives only in memory
+const IN_MEMORY_DATABASE: &str = ":memory:";
+
+/// A per-user directory clippy keeps files in
+#[derive(Debug, Clone, Copy)]
+enum AppDir {
+    Config,
+    Data,
+}
+
+impl AppDir {
+    /// clippy's directory of this kind, so it can run where `dirs` finds no
+    /// home (e.g. minimal containers)
+    fn resolve(self) -> PathBuf {
+        let platform = match self {
+            AppDir::Config => dirs::config_dir(),
+            AppDir::Data => dirs::data_local_dir(),
+        };
+        let has_platform = platform.is_some();
+        let (dir, source) =
+            self.resolve_with(|name| std::env::var_os(name), platform, |dir| dir.is_dir());
+        let dir = match std::env::current_dir() {
+            Ok(cwd) if dir.is_relative() => cwd.join(dir),
+            _ => dir,
+        };
+        if source == "current directory" {
+            info!("No {:?} directory from the environment; using {} ({})", self, dir.display(), source);
+        } else if source.starts_with("XDG_") && has_platform {
+            warn!(
+                "Using {:?} directory {} from {}, as the platform default doesn't exist; \
+                 move it there or set CLIPPY_HOME",
+                self,
+                dir.display(),
+                source
+            );
+        } else {
+            debug!("{:?} directory: {} ({})", self, dir.display(), source);
+        }
+        dir
+    }
+
+    /// In order: `$CLIPPY_HOME`, the platform default (which on Linux already
+    /// follows `$XDG_*_HOME`), `$XDG_*_HOME/clippy`, then `.clippy` under the
+    /// current directory. The XDG directory only wins over a platform default
+    /// that doesn't exist yet, so on macOS it can't hide an existing
+    /// `~/Library/Application Support/clippy`. Returns where it came from.
+    fn resolve_with(
+        self,
+        env: impl Fn(&str) -> Option<OsString>,
+        platform: Option<PathBuf>,
+        exists: impl Fn(&Path) -> bool,
+    ) -> (PathBuf, &'static str) {
+        if let Some(home) = env("CLIPPY_HOME").filter(|h| !h.is_empty()) {
+            return (PathBuf::from(home), "CLIPPY_HOME");
+        }
+        let xdg = match self {
+            AppDir::Config => "XDG_CONFIG_HOME",
+            AppDir::Data => "XDG_DATA_HOME",
+        };
+        // The XDG spec says relative values are to be ignored
+        let xdg_dir = env(xdg)
+            .map(PathBuf::from)
+            .filter(|p| p.is_absolute())
+            .map(|base| base.join("clippy"));
+        match (platform.map(|base| base.join("clippy")), xdg_dir) {
+            (Some(dir), Some(xdg_dir)) if !exists(&dir) && exists(&xdg_dir) => (xdg_dir, xdg),
+            (Some(dir), _) => (dir, "platform default"),
+            (None, Some(xdg_dir)) => (xdg_dir, xdg),
+            (None, None) => (PathBuf::from(".clippy"), "current directory"),
+        }
+    }
+}
+
+/// A configuration file that could not be read, parsed or validated
+#[derive(Debug, thiserror::Error)]
+#[error("configuration error: {0:#}")]
+pub struct ConfigError(#[from] pub anyhow::Error);
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
@@ -8,6 +90,50 @@ pub struct Config {
     pub client: ClientConfig,
     pub storage: StorageConfig,
     pub sync: SyncConfig,
+    #[serde(default)]
+    pub clipboard: ClipboardConfig,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClipboardConfig {
+    /// What to sync when an app offers both an image and text
+    #[serde(default)]
+    pub prefer: ClipboardPreference,
+    /// Linux only: X11 targets the xclip fallback reads text from, in order.
+    /// Empty uses UTF8_STRING, STRING, TEXT, text/plain.
+    #[serde(default)]
+    pub xclip_targets: Vec<String>,
+    /// Only read the clipboard when the OS reports a change (NSPasteboard
+    /// changeCount on macOS, XFixes on X11); false reads it on every poll
+    #[serde(default = "default_true")]
+    pub change_notifications: bool,
+}
+
+impl Default for ClipboardConfig {
+    fn default() -> Self {
+        Self {
+            prefer: ClipboardPreference::default(),
+            xclip_targets: Vec::new(),
+            change_notifications: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ClipboardPreference {
+    Image,
+    #[default]
+    Text,
+}
+
+/// `client.wire_format`; the protocol's `WireFormat` maps from it
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FrameFormat {
+    #[default]
+    Json,
+    Msgpack,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -18,6 +144,20 @@ pub struct ServerConfig {
     pub port: u16,
     #[serde(default)]
     pub auth_token: Option<String>,
+    /// Announce the server over mDNS (builds with the `discovery` feature)
+    #[serde(default = "default_true")]
+    pub advertise: bool,
+    /// Listen on this Unix domain socket instead of `host`/`port`
+    #[serde(default)]
+    pub unix_socket: Option<PathBuf>,
+    /// Longest a request may wait on the database before the server gives
+    /// up and closes the connection; 0 waits indefinitely
+    #[serde(default = "default_request_timeout_ms")]
+    pub request_timeout_ms: u64,
+    /// Only store and apply updates whose `source` is listed; unset accepts
+    /// every source. Sources are self-reported, so this filters, not secures.
+    #[serde(default)]
+    pub allowed_sources: Option<Vec<String>>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -29,6 +169,68 @@ pub struct ClientConfig {
     pub auth_token: Option<String>,
     #[serde(default = "default_true")]
     pub auto_connect: bool,
+    /// Ordered list of servers to try; the first entry is the primary.
+    /// When empty, `server_host`/`server_port` are used as the only server.
+    #[serde(default)]
+    pub servers: Vec<ServerEndpoint>,
+    /// Connect to a server's Unix domain socket instead of over TCP;
+    /// `server_host`, `server_port` and `servers` are then ignored
+    #[serde(default)]
+    pub unix_socket: Option<PathBuf>,
+    /// Credentials for a reverse proxy in front of the HTTP server (`clippy sync`)
+    #[serde(default)]
+    pub http_basic_auth: Option<BasicAuth>,
+    /// Name this machine reports as the source of its clipboard changes
+    /// (e.g. "laptop"); unset uses the OS name ("macos", "nixos")
+    #[serde(default)]
+    pub source_name: Option<String>,
+    /// Frame format to ask the server for: "json" (default, readable in
+    /// packet captures) or "msgpack" (smaller, images sent as raw bytes)
+    #[serde(default)]
+    pub wire_format: FrameFormat,
+}
+
+/// HTTP basic auth credentials; `Debug` never shows the password
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BasicAuth {
+    pub username: String,
+    pub password: String,
+}
+
+impl fmt::Debug for BasicAuth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BasicAuth")
+            .field("username", &self.username)
+            .field("password", &"***")
+            .finish()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ServerEndpoint {
+    pub host: String,
+    #[serde(default = "default_port")]
+    pub port: u16,
+}
+
+impl ServerEndpoint {
+    pub fn addr(&self) -> String {
+        format!("{}:{}", self.host, self.port)
+    }
+}
+
+impl ClientConfig {
+    /// Servers to connect to, in order of preference
+    pub fn endpoints(&self) -> Vec<ServerEndpoint> {
+        if self.servers.is_empty() {
+            vec![ServerEndpoint {
+                host: self.server_host.clone(),
+                port: self.server_port,
+            }]
+        } else {
+            self.servers.clone()
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -37,8 +239,41 @@ pub struct StorageConfig {
     pub max_history: usize,
     #[serde(default = "default_max_content_size_mb")]
     pub max_content_size_mb: usize,
+    /// `:memory:` keeps history in memory only, discarding it on exit
     #[serde(default)]
     pub database_path: Option<PathBuf>,
+    /// SQLite `synchronous` level: off, normal, full or extra
+    #[serde(default = "default_synchronous")]
+    pub synchronous: String,
+    /// Maximum number of pooled SQLite connections
+    #[serde(default = "default_db_pool_size")]
+    pub db_pool_size: u32,
+    /// What to do with content a password manager marked as secret:
+    /// "skip" (never capture) or "flag" (sync and store, hidden from history)
+    #[serde(default = "default_sensitive_content")]
+    pub sensitive_content: String,
+    /// Seconds between WAL checkpoints in the daemon; 0 disables them
+    #[serde(default = "default_checkpoint_interval_secs")]
+    pub checkpoint_interval_secs: u64,
+    /// Content larger than this (KB) is kept as a file under `blobs/` next to
+    /// the database instead of inline; 0 keeps everything in the database
+    #[serde(default)]
+    pub blob_threshold_kb: usize,
+    /// Content types ("text", "image", "html", "other") of local changes
+    /// stored in history; unset stores every type
+    #[serde(default)]
+    pub capture_types: Option<Vec<String>>,
+    /// Warn at startup when the database is larger than this (MB); 0 disables
+    #[serde(default = "default_warn_db_size_mb")]
+    pub warn_db_size_mb: u64,
+    /// `VACUUM` the database at startup when it is larger than this (MB);
+    /// 0 never does
+    #[serde(default)]
+    pub auto_vacuum_threshold_mb: u64,
+    /// Store images as raw PNG bytes instead of base64 text (a third
+    /// smaller); existing images are converted when the database is opened
+    #[serde(default)]
+    pub binary_images: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -49,6 +284,237 @@ pub struct SyncConfig {
     pub retry_delay_ms: u64,
     #[serde(default = "default_heartbeat_interval_ms")]
     pub heartbeat_interval_ms: u64,
+    /// How long a changed clipboard must stay unchanged before it is synced,
+    /// so apps that write several formats in a row are captured once; 0 disables
+    #[serde(default = "default_debounce_ms")]
+    pub debounce_ms: u64,
+    /// Send whatever is already on the clipboard when the daemon starts; by
+    /// default it is only sent once it changes, so restarts don't re-broadcast it
+    #[serde(default)]
+    pub send_on_startup: bool,
+    /// Re-read and re-send the clipboard this often even if its checksum
+    /// looks unchanged, as a safety net against missed changes; unset disables
+    #[serde(default)]
+    pub force_resync_interval_secs: Option<u64>,
+    /// Empty clipboard reads in a row before the clipboard counts as cleared,
+    /// so a read that briefly fails doesn't make unchanged content look new
+    #[serde(default = "default_empty_reads_before_clear")]
+    pub empty_reads_before_clear: u32,
+    /// Record items synced over HTTP (`clippy sync`) in the local history database
+    #[serde(default = "default_true")]
+    pub persist_http_history: bool,
+    /// File to append a JSON line to for every sync send/receive decision
+    #[serde(default)]
+    pub event_log: Option<PathBuf>,
+    /// URL to POST a JSON summary to whenever the local clipboard changes
+    #[serde(default)]
+    pub on_change_webhook: Option<String>,
+    /// Include the (base64) clipboard content in webhook payloads
+    #[serde(default)]
+    pub include_content: bool,
+    /// Which way clipboard changes flow: both, send_only or receive_only
+    #[serde(default)]
+    pub direction: SyncDirection,
+    /// Rewrites applied to copied text before it is sent
+    #[serde(default)]
+    pub transform: TransformConfig,
+    /// Characters of copied text to show in logs; 0 logs only type and size
+    #[serde(default)]
+    pub log_preview_chars: usize,
+    /// Regex rewrites of text in transit, applied in order
+    #[serde(default)]
+    pub replace_rules: Vec<ReplaceRule>,
+    /// Received updates larger than this many bytes are handled by
+    /// `large_apply` instead of being applied straight away; unset disables
+    #[serde(default)]
+    pub confirm_large_apply_bytes: Option<usize>,
+    /// What to do with a received update over `confirm_large_apply_bytes`
+    #[serde(default)]
+    pub large_apply: LargeApplyAction,
+    /// What `large_apply = "prompt"` does when there is no terminal or
+    /// nobody answers within `large_apply_prompt_timeout_secs`
+    #[serde(default)]
+    pub large_apply_default: LargeApplyDefault,
+    #[serde(default = "default_large_apply_prompt_timeout_secs")]
+    pub large_apply_prompt_timeout_secs: u64,
+    /// Content types of local changes sent to peers, independent of
+    /// `storage.capture_types`; unset sends every type
+    #[serde(default)]
+    pub sync_types: Option<Vec<String>>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LargeApplyAction {
+    /// Ask on the terminal when running in one, otherwise fall back to
+    /// `large_apply_default`
+    #[default]
+    Prompt,
+    /// Apply after logging a warning
+    Apply,
+    /// Drop the update after logging a warning
+    Skip,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LargeApplyDefault {
+    #[default]
+    Skip,
+    Apply,
+}
+
+fn default_large_apply_prompt_timeout_secs() -> u64 {
+    30
+}
+
+/// A regex replacement applied to text and HTML content as it is sent
+/// and/or received
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReplaceRule {
+    /// Logged when the rule fires; defaults to the pattern
+    #[serde(default)]
+    pub name: Option<String>,
+    pub pattern: String,
+    /// May refer to capture groups as `$1` or `${name}`
+    pub replacement: String,
+    #[serde(default)]
+    pub direction: RuleDirection,
+    #[serde(skip)]
+    compiled: OnceLock<regex::Regex>,
+}
+
+impl ReplaceRule {
+    /// The compiled pattern of the rule at `index` in `sync.replace_rules`.
+    /// Compiled on first use and kept, so validating the config and then
+    /// applying its rules compiles each pattern once.
+    pub fn regex(&self, index: usize) -> Result<&regex::Regex> {
+        if let Some(regex) = self.compiled.get() {
+            return Ok(regex);
+        }
+        let regex = regex::Regex::new(&self.pattern)
+            .with_context(|| format!("sync.replace_rules[{}] has an invalid pattern", index))?;
+        Ok(self.compiled.get_or_init(|| regex))
+    }
+}
+
+impl PartialEq for ReplaceRule {
+    fn eq(&self, other: &Self) -> bool {
+        (&self.name, &self.pattern, &self.replacement, self.direction)
+            == (&other.name, &other.pattern, &other.replacement, other.direction)
+    }
+}
+
+impl Eq for ReplaceRule {}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RuleDirection {
+    #[default]
+    Both,
+    Send,
+    Receive,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TransformConfig {
+    /// Convert CRLF and lone CR line endings to LF
+    #[serde(default)]
+    pub normalize_line_endings: bool,
+    /// Strip spaces and tabs from the end of every line
+    #[serde(default)]
+    pub trim_trailing_whitespace: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SyncDirection {
+    #[default]
+    Both,
+    /// Publish local changes, never apply remote ones
+    SendOnly,
+    /// Apply remote changes, never watch the local clipboard
+    ReceiveOnly,
+}
+
+/// Settings the daemon's clipboard monitors re-read on every poll, so a
+/// SIGHUP reload can change them without a restart
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LiveSettings {
+    pub interval_ms: u64,
+    pub transform: TransformConfig,
+    pub sensitive_content: String,
+}
+
+impl LiveSettings {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            interval_ms: config.sync.interval_ms,
+            transform: config.sync.transform.clone(),
+            sensitive_content: config.storage.sensitive_content.clone(),
+        }
+    }
+
+    fn apply_to(&self, config: &mut Config) {
+        config.sync.interval_ms = self.interval_ms;
+        config.sync.transform = self.transform.clone();
+        config.storage.sensitive_content = self.sensitive_content.clone();
+    }
+
+    /// Names of the settings that differ from `other`
+    pub fn changes(&self, other: &LiveSettings) -> Vec<&'static str> {
+        let mut changed = Vec::new();
+        if self.interval_ms != other.interval_ms {
+            changed.push("sync.interval_ms");
+        }
+        if self.transform != other.transform {
+            changed.push("sync.transform");
+        }
+        if self.sensitive_content != other.sensitive_content {
+            changed.push("storage.sensitive_content");
+        }
+        changed
+    }
+
+    /// Whether secret content is captured (flagged) rather than skipped
+    pub fn capture_sensitive(&self) -> bool {
+        self.sensitive_content.eq_ignore_ascii_case("flag")
+    }
+}
+
+impl ServerConfig {
+    /// Whether updates sent by `source` are accepted
+    pub fn accepts_source(&self, source: &str) -> bool {
+        self.allowed_sources
+            .as_ref()
+            .is_none_or(|sources| sources.iter().any(|s| s == source))
+    }
+}
+
+impl SyncConfig {
+    /// Whether local changes of `content_type` are sent to peers
+    pub fn syncs(&self, content_type: &str) -> bool {
+        type_listed(self.sync_types.as_deref(), content_type)
+    }
+}
+
+/// Names `storage.capture_types` and `sync.sync_types` may list, as given by
+/// `ClipboardContent::content_type_str`
+const CONTENT_TYPES: &[&str] = &["text", "image", "html", "other"];
+
+/// Whether `content_type` is in `types`, where no list means every type
+fn type_listed(types: Option<&[String]>, content_type: &str) -> bool {
+    types.is_none_or(|types| types.iter().any(|t| t.eq_ignore_ascii_case(content_type)))
+}
+
+impl SyncDirection {
+    pub fn sends(self) -> bool {
+        self != SyncDirection::ReceiveOnly
+    }
+
+    pub fn receives(self) -> bool {
+        self != SyncDirection::SendOnly
+    }
 }
 
 fn default_host() -> String {
@@ -59,6 +525,12 @@ fn default_port() -> u16 {
     9876
 }
 
+/// Long enough for a write to use up its busy retries and fail on its own,
+/// with time to spare for the rest of the request
+fn default_request_timeout_ms() -> u64 {
+    crate::storage::WRITE_RETRY_BUDGET.as_millis() as u64 + 5_000
+}
+
 fn default_max_history() -> usize {
     1000
 }
@@ -67,10 +539,38 @@ fn default_max_content_size_mb() -> usize {
     10
 }
 
+fn default_synchronous() -> String {
+    "normal".to_string()
+}
+
+fn default_db_pool_size() -> u32 {
+    8
+}
+
+fn default_sensitive_content() -> String {
+    "skip".to_string()
+}
+
+fn default_checkpoint_interval_secs() -> u64 {
+    300
+}
+
+fn default_warn_db_size_mb() -> u64 {
+    500
+}
+
 fn default_interval_ms() -> u64 {
     500
 }
 
+fn default_debounce_ms() -> u64 {
+    50
+}
+
+fn default_empty_reads_before_clear() -> u32 {
+    3
+}
+
 fn default_retry_delay_ms() -> u64 {
     5000
 }
@@ -90,34 +590,106 @@ impl Default for Config {
                 host: default_host(),
                 port: default_port(),
                 auth_token: None,
+                advertise: true,
+                unix_socket: None,
+                request_timeout_ms: default_request_timeout_ms(),
+                allowed_sources: None,
             },
             client: ClientConfig {
                 server_host: "127.0.0.1".to_string(),
                 server_port: default_port(),
                 auth_token: None,
                 auto_connect: true,
+                servers: Vec::new(),
+                unix_socket: None,
+                http_basic_auth: None,
+                source_name: None,
+                wire_format: FrameFormat::default(),
             },
             storage: StorageConfig {
                 max_history: default_max_history(),
                 max_content_size_mb: default_max_content_size_mb(),
                 database_path: None,
+                synchronous: default_synchronous(),
+                db_pool_size: default_db_pool_size(),
+                sensitive_content: default_sensitive_content(),
+                checkpoint_interval_secs: default_checkpoint_interval_secs(),
+                blob_threshold_kb: 0,
+                capture_types: None,
+                warn_db_size_mb: default_warn_db_size_mb(),
+                auto_vacuum_threshold_mb: 0,
+                binary_images: false,
             },
             sync: SyncConfig {
                 interval_ms: default_interval_ms(),
                 retry_delay_ms: default_retry_delay_ms(),
                 heartbeat_interval_ms: default_heartbeat_interval_ms(),
+                debounce_ms: default_debounce_ms(),
+                send_on_startup: false,
+                force_resync_interval_secs: None,
+                empty_reads_before_clear: default_empty_reads_before_clear(),
+                persist_http_history: true,
+                event_log: None,
+                on_change_webhook: None,
+                include_content: false,
+                direction: SyncDirection::Both,
+                transform: TransformConfig::default(),
+                log_preview_chars: 0,
+                replace_rules: Vec::new(),
+                confirm_large_apply_bytes: None,
+                large_apply: LargeApplyAction::Prompt,
+                large_apply_default: LargeApplyDefault::Skip,
+                large_apply_prompt_timeout_secs: default_large_apply_prompt_timeout_secs(),
+                sync_types: None,
             },
+            clipboard: ClipboardConfig::default(),
         }
     }
 }
 
+impl StorageConfig {
+    pub fn max_content_size_bytes(&self) -> usize {
+        self.max_content_size_mb * 1024 * 1024
+    }
+
+    /// Whether secret content is captured (flagged) rather than skipped
+    pub fn capture_sensitive(&self) -> bool {
+        self.sensitive_content.eq_ignore_ascii_case("flag")
+    }
+
+    /// Whether `database_path` asks for a database that is never written to disk
+    pub fn is_in_memory(&self) -> bool {
+        self.database_path.as_deref() == Some(Path::new(IN_MEMORY_DATABASE))
+    }
+
+    /// Size above which content is stored as a blob file, if enabled
+    pub fn blob_threshold_bytes(&self) -> Option<usize> {
+        (self.blob_threshold_kb > 0).then(|| self.blob_threshold_kb * 1024)
+    }
+
+    /// Whether local changes of `content_type` are stored in history
+    pub fn captures(&self, content_type: &str) -> bool {
+        type_listed(self.capture_types.as_deref(), content_type)
+    }
+
+    /// Largest base64 payload that can decode to `max_content_size_bytes`
+    pub fn max_encoded_size_bytes(&self) -> usize {
+        self.max_content_size_bytes().div_ceil(3) * 4
+    }
+}
+
 impl Config {
     pub fn load() -> Result<Self> {
+        Self::load_from_disk().map_err(|e| ConfigError(e).into())
+    }
+
+    fn load_from_disk() -> Result<Self> {
         let config_path = Self::config_path()?;
 
         if config_path.exists() {
             let contents = std::fs::read_to_string(&config_path)?;
             let mut config: Config = toml::from_str(&contents)?;
+            config.validate()?;
 
             // Set default database path if not specified
             if config.storage.database_path.is_none() {
@@ -132,6 +704,73 @@ impl Config {
         }
     }
 
+    /// Reject values that would break the daemon at runtime
+    fn validate(&self) -> Result<()> {
+        if self.sync.interval_ms == 0 {
+            anyhow::bail!("sync.interval_ms must be greater than 0");
+        }
+        if self.sync.heartbeat_interval_ms == 0 {
+            anyhow::bail!("sync.heartbeat_interval_ms must be greater than 0");
+        }
+        if !["skip", "flag"]
+            .iter()
+            .any(|v| self.storage.sensitive_content.eq_ignore_ascii_case(v))
+        {
+            anyhow::bail!(
+                "storage.sensitive_content must be \"skip\" or \"flag\", not {:?}",
+                self.storage.sensitive_content
+            );
+        }
+        for (i, rule) in self.sync.replace_rules.iter().enumerate() {
+            rule.regex(i)?;
+        }
+        for (key, types) in [
+            ("storage.capture_types", &self.storage.capture_types),
+            ("sync.sync_types", &self.sync.sync_types),
+        ] {
+            for name in types.iter().flatten() {
+                if !CONTENT_TYPES.iter().any(|t| t.eq_ignore_ascii_case(name)) {
+                    anyhow::bail!(
+                        "{} has unknown type {:?}; expected one of {}",
+                        key,
+                        name,
+                        CONTENT_TYPES.join(", ")
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Config sections that differ from `new` in ways `LiveSettings` can't
+    /// apply, so the daemon must be restarted for them to take effect
+    pub fn restart_required(&self, new: &Config) -> Vec<&'static str> {
+        fn differs<T: Serialize>(a: &T, b: &T) -> bool {
+            serde_json::to_value(a).ok() != serde_json::to_value(b).ok()
+        }
+
+        let mut new = new.clone();
+        LiveSettings::from_config(self).apply_to(&mut new);
+
+        let mut sections = Vec::new();
+        if differs(&self.server, &new.server) {
+            sections.push("server");
+        }
+        if differs(&self.client, &new.client) {
+            sections.push("client");
+        }
+        if differs(&self.storage, &new.storage) {
+            sections.push("storage");
+        }
+        if differs(&self.sync, &new.sync) {
+            sections.push("sync");
+        }
+        if differs(&self.clipboard, &new.clipboard) {
+            sections.push("clipboard");
+        }
+        sections
+    }
+
     pub fn save(&self) -> Result<()> {
         let config_path = Self::config_path()?;
 
@@ -146,15 +785,16 @@ impl Config {
     }
 
     pub fn config_path() -> Result<PathBuf> {
-        let config_dir = dirs::config_dir()
-            .ok_or_else(|| anyhow::anyhow!("Could not determine config directory"))?;
-        Ok(config_dir.join("clippy").join("config.toml"))
+        Ok(AppDir::Config.resolve().join("config.toml"))
     }
 
     pub fn default_database_path() -> Result<PathBuf> {
-        let data_dir = dirs::data_local_dir()
-            .ok_or_else(|| anyhow::anyhow!("Could not determine data directory"))?;
-        Ok(data_dir.join("clippy").join("clipboard.db"))
+        Ok(AppDir::Data.resolve().join("clipboard.db"))
+    }
+
+    /// While this file exists, running daemons pause sync (`clippy pause`)
+    pub fn pause_flag_path() -> Result<PathBuf> {
+        Ok(AppDir::Data.resolve().join("paused"))
     }
 
     pub fn get_database_path(&self) -> PathBuf {
@@ -164,6 +804,15 @@ impl Config {
             .unwrap_or_else(|| Self::default_database_path().unwrap())
     }
 
+    /// `client.source_name` if set, otherwise the OS-based default
+    pub fn source_name(&self) -> String {
+        self.client
+            .source_name
+            .clone()
+            .filter(|name| !name.trim().is_empty())
+            .unwrap_or_else(Self::get_source_name)
+    }
+
     pub fn get_source_name() -> String {
         #[cfg(target_os = "macos")]
         return "macos".to_string();
@@ -175,3 +824,130 @@ impl Config {
         return "unknown".to_string();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_app_dir_resolution_order() {
+        let env = |vars: &'static [(&'static str, &'static str)]| {
+            move |name: &str| {
+                vars.iter()
+                    .find(|(key, _)| *key == name)
+                    .map(|(_, value)| OsString::from(value))
+            }
+        };
+        let platform = Some(PathBuf::from("/Users/me/Library/Application Support"));
+        let existing = |dirs: &'static [&'static str]| move |dir: &Path| dirs.iter().any(|d| dir == Path::new(d));
+        let xdg_and_home = env(&[("XDG_CONFIG_HOME", "/xdg"), ("CLIPPY_HOME", "/clippy")]);
+
+        // CLIPPY_HOME is an explicit override
+        let (dir, source) = AppDir::Config.resolve_with(xdg_and_home, platform.clone(), existing(&[]));
+        assert_eq!((dir, source), (PathBuf::from("/clippy"), "CLIPPY_HOME"));
+
+        // XDG doesn't hide the platform directory...
+        let xdg = env(&[("XDG_CONFIG_HOME", "/xdg")]);
+        let both = existing(&["/xdg/clippy", "/Users/me/Library/Application Support/clippy"]);
+        let (dir, source) = AppDir::Config.resolve_with(xdg, platform.clone(), both);
+        assert_eq!(
+            (dir, source),
+            (PathBuf::from("/Users/me/Library/Application Support/clippy"), "platform default")
+        );
+        // ...but an existing one is used while the platform one doesn't exist
+        let (dir, source) = AppDir::Config.resolve_with(xdg, platform.clone(), existing(&["/xdg/clippy"]));
+        assert_eq!((dir, source), (PathBuf::from("/xdg/clippy"), "XDG_CONFIG_HOME"));
+        let (dir, _) = AppDir::Config.resolve_with(xdg, platform.clone(), existing(&[]));
+        assert_eq!(dir, PathBuf::from("/Users/me/Library/Application Support/clippy"));
+
+        // A relative XDG value is ignored; the other kind's variable doesn't apply
+        let (dir, _) = AppDir::Data.resolve_with(
+            env(&[("XDG_DATA_HOME", "relative"), ("XDG_CONFIG_HOME", "/xdg")]),
+            None,
+            existing(&["/xdg/clippy"]),
+        );
+        assert_eq!(dir, PathBuf::from(".clippy"));
+
+        // No home directory: XDG, then the current directory
+        let (dir, source) = AppDir::Data.resolve_with(env(&[("XDG_DATA_HOME", "/data")]), None, existing(&[]));
+        assert_eq!((dir, source), (PathBuf::from("/data/clippy"), "XDG_DATA_HOME"));
+        let (dir, _) = AppDir::Config.resolve_with(env(&[]), None, existing(&[]));
+        assert_eq!(dir, PathBuf::from(".clippy"));
+    }
+
+    #[test]
+    fn test_sync_direction_selects_tasks() {
+        let parse = |toml_str: &str| -> SyncConfig { toml::from_str(toml_str).unwrap() };
+
+        let both = parse("").direction;
+        assert!(both.sends() && both.receives());
+
+        let send_only = parse("direction = \"send_only\"").direction;
+        assert!(send_only.sends() && !send_only.receives());
+
+        let receive_only = parse("direction = \"receive_only\"").direction;
+        assert!(!receive_only.sends() && receive_only.receives());
+    }
+
+    #[test]
+    fn test_capture_and_sync_types_are_independent() {
+        let mut config = Config::default();
+        assert!(config.storage.captures("image") && config.sync.syncs("image"));
+
+        config.storage.capture_types = Some(vec!["text".to_string(), "image".to_string()]);
+        config.sync.sync_types = Some(vec!["text".to_string(), "html".to_string()]);
+        let handling = |t: &str| (config.storage.captures(t), config.sync.syncs(t));
+
+        assert_eq!(handling("text"), (true, true));
+        assert_eq!(handling("image"), (true, false));
+        assert_eq!(handling("html"), (false, true));
+        assert_eq!(handling("other"), (false, false));
+    }
+
+    #[test]
+    fn test_source_name_overrides_os_default() {
+        let mut config = Config::default();
+        assert_eq!(config.source_name(), Config::get_source_name());
+
+        config.client.source_name = Some("laptop".to_string());
+        assert_eq!(config.source_name(), "laptop");
+
+        config.client.source_name = Some("  ".to_string());
+        assert_eq!(config.source_name(), Config::get_source_name());
+    }
+
+    #[test]
+    fn test_reload_separates_live_and_restart_settings() {
+        let running = Config::default();
+        let mut new = running.clone();
+        new.sync.interval_ms = 250;
+        new.storage.sensitive_content = "flag".to_string();
+        new.server.port = 9999;
+
+        let changed = LiveSettings::from_config(&running).changes(&LiveSettings::from_config(&new));
+        assert_eq!(changed, ["sync.interval_ms", "storage.sensitive_content"]);
+        assert_eq!(running.restart_required(&new), ["server"]);
+
+        new.server.port = running.server.port;
+        assert!(running.restart_required(&new).is_empty());
+
+        new.sync.interval_ms = 0;
+        assert!(new.validate().is_err());
+    }
+
+    #[test]
+    fn test_unknown_content_type_names_are_rejected() {
+        let mut config = Config::default();
+        config.storage.capture_types = Some(vec!["Text".to_string(), "image".to_string()]);
+        config.sync.sync_types = Some(vec!["html".to_string(), "other".to_string()]);
+        assert!(config.validate().is_ok());
+
+        config.sync.sync_types = Some(vec!["images".to_string()]);
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("sync.sync_types") && err.contains("images"), "{}", err);
+
+        config.sync.sync_types = None;
+        config.storage.capture_types = Some(vec!["rtf".to_string()]);
+        assert!(config.validate().is_err());
+    }
+}
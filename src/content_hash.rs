@@ -0,0 +1,27 @@
+//! Dedup hash shared by the HTTP server and the HTTP sync client, so both
+//! sides always agree on when two items hold the same content.
+
+use sha2::{Digest, Sha256};
+
+/// Hex SHA-256 of the raw (decoded) clipboard bytes
+pub fn content_hash(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
+
+/// The first 8 characters of a checksum for logs. Checksums from peers are
+/// untrusted and may be shorter.
+pub fn short_checksum(checksum: &str) -> &str {
+    checksum.get(..8).unwrap_or(checksum)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_short_checksum_accepts_short_input() {
+        assert_eq!(short_checksum(&content_hash(b"abc")), "ba7816bf");
+        assert_eq!(short_checksum("abc"), "abc");
+        assert_eq!(short_checksum("é"), "é");
+    }
+}
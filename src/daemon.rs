@@ -1,14 +1,217 @@
 use crate::client::ClipboardClient;
-use crate::clipboard::{ClipboardContent, ClipboardManager};
-use crate::config::Config;
-use crate::server::ClipboardServer;
-use crate::storage::{models::ClipboardEntry, ClipboardStorage};
-use crate::sync::protocol::Message;
+use crate::clipboard::{change::ChangeWatcher, ClipboardContent, ClipboardManager};
+use crate::config::{Config, LiveSettings, SyncConfig};
+use crate::content_hash::short_checksum;
+use crate::event_log::{Direction, SyncEvent, SyncEventLog};
+use crate::server::{BroadcastUpdate, ClipboardServer};
+use crate::storage::{
+    models::{mark_sensitive, ClipboardEntry},
+    ClipboardStorage, Storage,
+};
+use crate::sync::{
+    outbound::LatestUpdate, pause::PauseFlag, protocol::Message, replace::ReplaceRules,
+    RecentlyApplied,
+};
+use crate::webhook::ChangeNotifier;
 use anyhow::Result;
-use std::sync::Arc;
-use tokio::sync::mpsc;
+use std::sync::{Arc, RwLock};
+use tokio::sync::watch;
 use tokio::time::{sleep, Duration};
-use tracing::{error, info, warn};
+use tracing::{debug, error, info, warn};
+
+/// Most times a debounce restarts before a still-changing clipboard is
+/// taken as it is
+const MAX_DEBOUNCE_ROUNDS: usize = 10;
+
+/// Wait until the clipboard stops changing: re-read the checksum after
+/// `delay` and start over whenever it moved. `read` returns the current
+/// checksum; the settled one is returned.
+async fn debounce<F>(mut checksum: String, delay: Duration, mut read: F) -> Result<Option<String>>
+where
+    F: FnMut() -> Result<Option<String>>,
+{
+    if delay.is_zero() {
+        return Ok(Some(checksum));
+    }
+    for _ in 0..MAX_DEBOUNCE_ROUNDS {
+        sleep(delay).await;
+        match read()? {
+            Some(current) if current == checksum => return Ok(Some(checksum)),
+            Some(current) => {
+                debug!("Clipboard still changing; restarting debounce");
+                checksum = current;
+            }
+            None => return Ok(None),
+        }
+    }
+    Ok(Some(checksum))
+}
+
+/// `sync.force_resync_interval_secs`: fires periodically so the monitor
+/// re-sends the clipboard even when its checksum looks unchanged
+struct ResyncTimer(Option<tokio::time::Interval>);
+
+impl ResyncTimer {
+    fn new(period: Option<Duration>) -> Self {
+        Self(period.filter(|period| !period.is_zero()).map(|period| {
+            let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            interval
+        }))
+    }
+
+    /// Wait for the next forced resync; never completes when disabled
+    async fn tick(&mut self) {
+        match &mut self.0 {
+            Some(interval) => {
+                interval.tick().await;
+            }
+            None => std::future::pending().await,
+        }
+    }
+}
+
+/// Wait for a monitor's next clipboard check: `(changed, forced)`, where
+/// `forced` is a resync that should be sent even if the checksum is unchanged
+async fn next_check(
+    watcher: &mut ChangeWatcher,
+    resync: &mut ResyncTimer,
+    interval: Duration,
+) -> (bool, bool) {
+    tokio::select! {
+        changed = watcher.wait(interval) => (changed, false),
+        _ = resync.tick() => (true, true),
+    }
+}
+
+/// `sync.empty_reads_before_clear`: counts empty clipboard reads in a row,
+/// so only a clipboard that stays empty resets the monitor's last checksum
+struct EmptyReads {
+    needed: u32,
+    seen: u32,
+}
+
+impl EmptyReads {
+    fn new(needed: u32) -> Self {
+        Self {
+            needed: needed.max(1),
+            seen: 0,
+        }
+    }
+
+    /// Count an empty read, returning whether the clipboard is now taken as cleared
+    fn record_empty(&mut self) -> bool {
+        self.seen = self.seen.saturating_add(1);
+        self.seen >= self.needed
+    }
+
+    /// The clipboard held something again
+    fn reset(&mut self) {
+        self.seen = 0;
+    }
+}
+
+/// The configured webhook notifier; a broken webhook setup is logged and
+/// leaves notifications off rather than stopping the monitor
+fn change_notifier(config: &Config) -> Option<ChangeNotifier> {
+    ChangeNotifier::from_config(config).unwrap_or_else(|e| {
+        error!("Webhook notifications disabled: {:#}", e);
+        None
+    })
+}
+
+/// The update sent for a local change. Its checksum covers `content` as
+/// sent, after any transform or replace rules, since that is what receivers
+/// verify.
+fn local_update(
+    content: &ClipboardContent,
+    source: String,
+    metadata: Option<String>,
+) -> Message {
+    Message::clipboard_update(
+        content.content_type_str().to_string(),
+        content.to_base64(),
+        source,
+        content.checksum(),
+        metadata,
+    )
+}
+
+/// The checksum a clipboard monitor starts from: the clipboard as it is now,
+/// so content already there is not re-sent, or none with `send_on_startup`
+fn initial_checksum<F>(send_on_startup: bool, read: F) -> Option<String>
+where
+    F: FnOnce() -> Result<Option<String>>,
+{
+    if send_on_startup {
+        return None;
+    }
+    match read() {
+        Ok(checksum) => checksum,
+        Err(e) => {
+            warn!("Cannot read the clipboard at startup: {}", e);
+            None
+        }
+    }
+}
+
+/// What a clipboard monitor last sent, and the rules both monitors use to
+/// decide whether a clipboard read is a change worth sending
+struct ChangeTracker {
+    last_checksum: Option<String>,
+    empty_reads: EmptyReads,
+    debounce: Duration,
+}
+
+impl ChangeTracker {
+    fn new(last_checksum: Option<String>, sync: &SyncConfig) -> Self {
+        Self {
+            last_checksum,
+            empty_reads: EmptyReads::new(sync.empty_reads_before_clear),
+            debounce: Duration::from_millis(sync.debounce_ms),
+        }
+    }
+
+    /// Handle one check of the clipboard, returning the checksum to send if
+    /// it changed. A `forced` check sends whatever is there. `read` returns
+    /// the current checksum and is called again while debouncing.
+    async fn on_check<F>(&mut self, forced: bool, mut read: F) -> Result<Option<String>>
+    where
+        F: FnMut() -> Result<Option<String>>,
+    {
+        if forced {
+            // Treated as a change, however the checksum compares
+            self.last_checksum = None;
+        }
+
+        let Some(checksum) = read()? else {
+            if self.last_checksum.is_some() && self.empty_reads.record_empty() {
+                info!("Clipboard cleared (was: {:?})", self.last_checksum.as_deref().map(short_checksum));
+                self.last_checksum = None;
+            }
+            return Ok(None);
+        };
+        self.empty_reads.reset();
+        if self.last_checksum.as_ref() == Some(&checksum) {
+            return Ok(None);
+        }
+
+        let Some(checksum) = debounce(checksum, self.debounce, &mut read).await? else {
+            return Ok(None);
+        };
+        if self.last_checksum.as_ref() == Some(&checksum) {
+            debug!("Clipboard settled back to its previous content");
+            return Ok(None);
+        }
+        debug!(
+            "Checksum changed: {:?} -> {}",
+            self.last_checksum.as_deref().map(short_checksum),
+            short_checksum(&checksum)
+        );
+        self.last_checksum = Some(checksum.clone());
+        Ok(Some(checksum))
+    }
+}
 
 pub enum DaemonMode {
     Server,
@@ -19,39 +222,149 @@ pub enum DaemonMode {
 pub struct ClipboardDaemon {
     config: Config,
     mode: DaemonMode,
+    live: Arc<RwLock<LiveSettings>>,
+    pause: PauseFlag, // Set by `clippy pause`, see `spawn_pause_watcher`
 }
 
 impl ClipboardDaemon {
     pub fn new(config: Config, mode: DaemonMode) -> Self {
-        Self { config, mode }
+        let live = Arc::new(RwLock::new(LiveSettings::from_config(&config)));
+        Self {
+            config,
+            mode,
+            live,
+            pause: PauseFlag::default(),
+        }
     }
 
     pub async fn run(&self) -> Result<()> {
-        let storage = ClipboardStorage::new(
-            self.config.get_database_path(),
-            self.config.storage.max_history,
-        )
-        .await?;
+        let sqlite = Arc::new(ClipboardStorage::from_config(&self.config).await?);
+        sqlite.check_size(&self.config).await;
+        sqlite.convert_base64_images().await?;
+        let storage: Arc<dyn Storage> = sqlite.clone();
+        let replace = ReplaceRules::compile(&self.config.sync.replace_rules)?;
+        let checkpoint_task = self.spawn_checkpoint_task(Arc::clone(&storage));
+        let reload_task = self.spawn_reload_task();
+        let pause_task = self.spawn_pause_watcher();
 
         match self.mode {
             DaemonMode::Server => {
-                self.run_server_only(storage).await?;
+                self.run_server_only(storage, replace).await?;
             }
             DaemonMode::Client => {
-                self.run_client_only().await?;
+                self.run_client_only(storage, replace).await?;
             }
             DaemonMode::Both => {
-                self.run_both(storage).await?;
+                self.run_both(storage, replace).await?;
             }
         }
 
+        for task in [checkpoint_task, reload_task, pause_task].into_iter().flatten() {
+            task.abort();
+        }
+
+        let dedup = sqlite.dedup_stats();
+        info!("History this session: New: {}, Deduped: {}", dedup.new_entries, dedup.deduped);
+
         Ok(())
     }
 
-    async fn run_server_only(&self, storage: ClipboardStorage) -> Result<()> {
+    /// Pause sync while the `clippy pause` flag file exists
+    fn spawn_pause_watcher(&self) -> Option<tokio::task::JoinHandle<()>> {
+        match Config::pause_flag_path() {
+            Ok(path) => Some(self.pause.watch(path)),
+            Err(e) => {
+                warn!("Cannot watch for `clippy pause`: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Reload `config.toml` on SIGHUP, applying the settings that can change
+    /// while running and logging the ones that need a restart
+    fn spawn_reload_task(&self) -> Option<tokio::task::JoinHandle<()>> {
+        #[cfg(unix)]
+        {
+            use tokio::signal::unix::{signal, SignalKind};
+
+            let mut hangup = match signal(SignalKind::hangup()) {
+                Ok(hangup) => hangup,
+                Err(e) => {
+                    warn!("Cannot listen for SIGHUP; config reload is disabled: {}", e);
+                    return None;
+                }
+            };
+            let running = self.config.clone();
+            let live = Arc::clone(&self.live);
+
+            Some(tokio::spawn(async move {
+                while hangup.recv().await.is_some() {
+                    info!("SIGHUP received, reloading configuration");
+                    match Config::load() {
+                        Ok(new) => Self::apply_reload(&running, &new, &live),
+                        Err(e) => error!("Keeping current configuration: {:#}", e),
+                    }
+                }
+            }))
+        }
+
+        #[cfg(not(unix))]
+        None
+    }
+
+    fn apply_reload(running: &Config, new: &Config, live: &RwLock<LiveSettings>) {
+        let settings = LiveSettings::from_config(new);
+        let changed = {
+            let mut current = live.write().unwrap();
+            let changed = current.changes(&settings);
+            *current = settings;
+            changed
+        };
+
+        if changed.is_empty() {
+            info!("No live settings changed");
+        } else {
+            info!("Applied changed settings: {}", changed.join(", "));
+        }
+
+        let restart = running.restart_required(new);
+        if !restart.is_empty() {
+            warn!(
+                "Changes to [{}] require a daemon restart to take effect",
+                restart.join("], [")
+            );
+        }
+    }
+
+    /// Periodically truncate the WAL so an always-on daemon's disk use stays bounded
+    fn spawn_checkpoint_task(
+        &self,
+        storage: Arc<dyn Storage>,
+    ) -> Option<tokio::task::JoinHandle<()>> {
+        let secs = self.config.storage.checkpoint_interval_secs;
+        if secs == 0 {
+            return None;
+        }
+
+        Some(tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(secs));
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                if let Err(e) = storage.checkpoint().await {
+                    warn!("Database checkpoint failed: {}", e);
+                }
+            }
+        }))
+    }
+
+    async fn run_server_only(&self, storage: Arc<dyn Storage>, replace: ReplaceRules) -> Result<()> {
         info!("Starting in server-only mode");
 
-        let server = ClipboardServer::new(self.config.clone(), storage).await?;
+        let server = ClipboardServer::new(self.config.clone(), storage)
+            .await?
+            .with_replace_rules(replace)
+            .with_pause(self.pause.clone());
         let clipboard_rx = server.get_clipboard_receiver();
 
         let server_task = tokio::spawn(async move {
@@ -62,44 +375,64 @@ impl ClipboardDaemon {
 
         let monitor_task = self.spawn_clipboard_monitor(clipboard_rx);
 
+        // Returning on a signal lets the runtime drop the listener, which
+        // removes a Unix socket file
         tokio::select! {
             _ = server_task => {},
             _ = monitor_task => {},
+            _ = Self::wait_for_shutdown_signal() => info!("Shutting down"),
         }
 
         Ok(())
     }
 
-    async fn run_client_only(&self) -> Result<()> {
+    async fn run_client_only(&self, storage: Arc<dyn Storage>, replace: ReplaceRules) -> Result<()> {
         info!("Starting in client-only mode");
 
-        let mut client = ClipboardClient::new(self.config.clone());
+        let client = ClipboardClient::new(self.config.clone())
+            .with_storage(storage)
+            .with_replace_rules(replace.clone())
+            .with_shutdown(Self::spawn_shutdown_listener())
+            .with_pause(self.pause.clone());
         let client_tx = client.get_sender();
+        let applied = client.recently_applied();
 
-        let client_task = tokio::spawn(async move {
-            if let Err(e) = client.run().await {
-                error!("Client error: {}", e);
-            }
-        });
+        let client_task = self.spawn_client(client);
 
-        let monitor_task = self.spawn_clipboard_monitor_for_client(client_tx);
+        let monitor_task = self.spawn_monitor(Self::monitor_clipboard_changes(
+            self.config.clone(),
+            Arc::clone(&self.live),
+            self.pause.clone(),
+            client_tx,
+            applied,
+            replace,
+        ));
 
         tokio::select! {
             _ = client_task => {},
-            _ = monitor_task => {},
+            _ = Self::join_optional(monitor_task) => {},
         }
 
         Ok(())
     }
 
-    async fn run_both(&self, storage: ClipboardStorage) -> Result<()> {
+    async fn run_both(&self, storage: Arc<dyn Storage>, replace: ReplaceRules) -> Result<()> {
         info!("Starting in both server and client mode");
 
-        let storage = Arc::new(storage);
-        let server = ClipboardServer::new(self.config.clone(), (*storage).clone()).await?;
-
-        let mut client = ClipboardClient::new(self.config.clone());
+        let client = ClipboardClient::new(self.config.clone())
+            .with_storage(Arc::clone(&storage))
+            .with_replace_rules(replace.clone())
+            .with_shutdown(Self::spawn_shutdown_listener())
+            .with_pause(self.pause.clone());
         let client_tx = client.get_sender();
+        // Updates applied by either side are echoes to the monitor below
+        let applied = client.recently_applied();
+
+        let server = ClipboardServer::new(self.config.clone(), Arc::clone(&storage))
+            .await?
+            .with_recently_applied(applied.clone())
+            .with_replace_rules(replace.clone())
+            .with_pause(self.pause.clone());
 
         // Start server
         let server_handle = {
@@ -112,56 +445,149 @@ impl ClipboardDaemon {
         };
 
         // Start client
-        let client_handle = tokio::spawn(async move {
-            if let Err(e) = client.run().await {
-                error!("Client error: {}", e);
-            }
-        });
+        let client_handle = self.spawn_client(client);
 
         // Monitor clipboard and send to server
-        let monitor_handle = {
-            let config = self.config.clone();
-            let storage = Arc::clone(&storage);
-            tokio::spawn(async move {
-                Self::monitor_clipboard_for_server(config, storage, client_tx).await;
-            })
-        };
+        let monitor_handle = self.spawn_monitor(Self::monitor_clipboard_for_server(
+            self.config.clone(),
+            Arc::clone(&self.live),
+            self.pause.clone(),
+            Arc::clone(&storage),
+            client_tx,
+            applied,
+            replace,
+        ));
 
         tokio::select! {
             _ = server_handle => {},
             _ = client_handle => {},
-            _ = monitor_handle => {},
+            _ = Self::join_optional(monitor_handle) => {},
         }
 
         Ok(())
     }
 
-    fn spawn_clipboard_monitor(
+    /// Start the local clipboard monitor, unless `sync.direction` is receive-only
+    fn spawn_monitor(
         &self,
-        mut clipboard_rx: tokio::sync::broadcast::Receiver<ClipboardEntry>,
-    ) -> tokio::task::JoinHandle<()> {
+        monitor: impl std::future::Future<Output = ()> + Send + 'static,
+    ) -> Option<tokio::task::JoinHandle<()>> {
+        if !self.config.sync.direction.sends() {
+            info!("Receive-only mode: not watching the local clipboard");
+            return None;
+        }
+        Some(tokio::spawn(monitor))
+    }
+
+    /// Wait for a task that may not have been started; never completes if not
+    async fn join_optional(task: Option<tokio::task::JoinHandle<()>>) {
+        match task {
+            Some(task) => {
+                let _ = task.await;
+            }
+            None => std::future::pending().await,
+        }
+    }
+
+    /// Run the client connection, waiting for SIGUSR1 first when
+    /// `client.auto_connect` is off. Local changes until then are not sent.
+    fn spawn_client(&self, mut client: ClipboardClient) -> tokio::task::JoinHandle<()> {
+        let auto_connect = self.config.client.auto_connect;
+        if !auto_connect {
+            info!("Auto-connect is disabled; send SIGUSR1 to connect to the server");
+        }
+
         tokio::spawn(async move {
-            while let Ok(_entry) = clipboard_rx.recv().await {
-                // Handle clipboard updates from server
-                info!("Received clipboard update from server");
+            if !auto_connect {
+                client.discard_until(Self::wait_for_connect_request()).await;
+                info!("Connect requested");
+            }
+
+            if let Err(e) = client.run().await {
+                error!("Client error: {}", e);
             }
         })
     }
 
-    fn spawn_clipboard_monitor_for_client(
+    /// Flip to true on Ctrl-C or SIGTERM, so the client can say goodbye to
+    /// the server before the daemon exits
+    fn spawn_shutdown_listener() -> watch::Receiver<bool> {
+        let (tx, rx) = watch::channel(false);
+        tokio::spawn(async move {
+            Self::wait_for_shutdown_signal().await;
+            info!("Shutting down");
+            let _ = tx.send(true);
+        });
+        rx
+    }
+
+    async fn wait_for_shutdown_signal() {
+        #[cfg(unix)]
+        {
+            use tokio::signal::unix::{signal, SignalKind};
+
+            match signal(SignalKind::terminate()) {
+                Ok(mut term) => {
+                    tokio::select! {
+                        _ = tokio::signal::ctrl_c() => {}
+                        _ = term.recv() => {}
+                    }
+                    return;
+                }
+                Err(e) => warn!("Cannot listen for SIGTERM: {}", e),
+            }
+        }
+
+        let _ = tokio::signal::ctrl_c().await;
+    }
+
+    async fn wait_for_connect_request() {
+        #[cfg(unix)]
+        {
+            use tokio::signal::unix::{signal, SignalKind};
+
+            match signal(SignalKind::user_defined1()) {
+                Ok(mut usr1) => {
+                    usr1.recv().await;
+                    return;
+                }
+                Err(e) => warn!("Cannot listen for SIGUSR1: {}", e),
+            }
+        }
+
+        std::future::pending::<()>().await
+    }
+
+    fn spawn_clipboard_monitor(
         &self,
-        client_tx: mpsc::Sender<Message>,
+        mut clipboard_rx: tokio::sync::broadcast::Receiver<Arc<BroadcastUpdate>>,
     ) -> tokio::task::JoinHandle<()> {
-        let config = self.config.clone();
-
         tokio::spawn(async move {
-            Self::monitor_clipboard_changes(config, client_tx).await;
+            while let Ok(update) = clipboard_rx.recv().await {
+                // Handle clipboard updates from server
+                info!(
+                    "Received clipboard update from server ({})",
+                    short_checksum(&update.entry.checksum)
+                );
+            }
         })
     }
 
-    async fn monitor_clipboard_changes(config: Config, client_tx: mpsc::Sender<Message>) {
+    async fn monitor_clipboard_changes(
+        config: Config,
+        live: Arc<RwLock<LiveSettings>>,
+        pause: PauseFlag,
+        client_tx: LatestUpdate,
+        applied: RecentlyApplied,
+        replace: ReplaceRules,
+    ) {
         info!("🚀 Initializing clipboard manager...");
-        let mut clipboard = match ClipboardManager::new() {
+        let mut clipboard = match ClipboardManager::new()
+            .map(|c| {
+                c.with_preference(config.clipboard.prefer)
+                    .with_xclip_targets(config.clipboard.xclip_targets.clone())
+            })
+        {
             Ok(c) => {
                 info!("✓ Clipboard manager initialized successfully");
                 c
@@ -176,102 +602,135 @@ impl ClipboardDaemon {
             }
         };
 
-        let mut last_checksum: Option<String> = None;
-        let interval = Duration::from_millis(config.sync.interval_ms);
+        let last_checksum = initial_checksum(config.sync.send_on_startup, || {
+            clipboard.get_content_checksum()
+        });
+        if last_checksum.is_some() {
+            info!("📋 Initialized with current clipboard content");
+        }
+        let notifier = change_notifier(&config);
+        let event_log = SyncEventLog::from_config(&config.sync);
+        let source_name = config.source_name();
+        let skipped = |checksum: &str, reason: &str| {
+            if let Some(log) = &event_log {
+                log.record(
+                    SyncEvent::new(Direction::Outbound, &source_name, checksum, "skipped")
+                        .with_reason(reason),
+                );
+            }
+        };
 
         info!("✓ Starting clipboard monitor (checking every {}ms)", config.sync.interval_ms);
         info!("🔄 Monitor loop started - waiting for clipboard changes...");
 
+        let mut watcher = ChangeWatcher::new(config.clipboard.change_notifications);
+        let mut resync = ResyncTimer::new(
+            config.sync.force_resync_interval_secs.map(Duration::from_secs),
+        );
+        let mut tracker = ChangeTracker::new(last_checksum, &config.sync);
         let mut iteration = 0;
+        let mut was_paused = false;
         loop {
-            sleep(interval).await;
+            let settings = live.read().unwrap().clone();
+            let (changed, forced) = next_check(
+                &mut watcher,
+                &mut resync,
+                Duration::from_millis(settings.interval_ms),
+            )
+            .await;
             iteration += 1;
 
+            if pause.is_paused() {
+                was_paused = true;
+                continue;
+            }
+            if std::mem::take(&mut was_paused) {
+                // What was copied while paused stays local
+                tracker.last_checksum =
+                    initial_checksum(false, || clipboard.get_content_checksum());
+                continue;
+            }
+            if !changed {
+                continue;
+            }
+            if forced {
+                info!("🔁 Forced resync: re-sending the current clipboard");
+            }
+
             // Log every 10 iterations to show we're still polling
             if iteration % 10 == 0 {
-                info!("🔄 Monitor active (iteration {}, last_checksum: {:?})", iteration, last_checksum.as_ref().map(|s| &s[..8]));
+                debug!(
+                    "🔄 Monitor active (iteration {}, last_checksum: {:?})",
+                    iteration,
+                    tracker.last_checksum.as_deref().map(short_checksum)
+                );
             }
 
-            match clipboard.get_content_checksum() {
-                Ok(Some(checksum)) => {
-                    // Log every checksum check in verbose mode
-                    if iteration % 10 == 1 {
-                        info!("Current clipboard checksum: {}", &checksum[..8]);
+            let checksum = match tracker.on_check(forced, || clipboard.get_content_checksum()).await {
+                Ok(Some(checksum)) => checksum,
+                Ok(None) => continue,
+                Err(e) => {
+                    error!("❌ Error checking clipboard: {}", e);
+                    error!("This might be a clipboard access issue - check permissions");
+                    continue;
+                }
+            };
+            info!("⚡ Clipboard changed: {}", short_checksum(&checksum));
+
+            info!("🔍 Reading clipboard content...");
+            match clipboard.get_content() {
+                Ok(Some(content)) => {
+                    if applied.is_echo(&content) {
+                        debug!("Not re-sending update just received from the server");
+                        skipped(&checksum, "echo");
+                        continue;
                     }
+                    let content = replace.on_send(content.transformed(&settings.transform));
+                    let wire_checksum = content.checksum();
+
+                    info!(
+                        "🔍 Detected LOCAL clipboard change (type: {}, checksum: {})",
+                        content.content_type_str(),
+                        short_checksum(&wire_checksum)
+                    );
+
+                    let content_preview = content.preview(config.sync.log_preview_chars);
 
-                    if last_checksum.as_ref() != Some(&checksum) {
-                        info!("⚡ CHECKSUM CHANGED! Old: {:?}, New: {}",
-                            last_checksum.as_ref().map(|s| &s[..8]), &checksum[..8]);
-
-                        last_checksum = Some(checksum.clone());
-
-                        info!("🔍 Reading clipboard content...");
-                        match clipboard.get_content() {
-                            Ok(Some(content)) => {
-                                info!(
-                                    "🔍 Detected LOCAL clipboard change (type: {}, checksum: {})",
-                                    content.content_type_str(),
-                                    &checksum[..8]
-                                );
-
-                                let content_preview = match &content {
-                                    ClipboardContent::Text(text) => {
-                                        if text.len() > 50 {
-                                            format!("{}...", &text[..50])
-                                        } else {
-                                            text.clone()
-                                        }
-                                    }
-                                    ClipboardContent::Image(data) => {
-                                        format!("[Image: {} bytes]", data.len())
-                                    }
-                                    ClipboardContent::Html(html) => {
-                                        if html.len() > 50 {
-                                            format!("{}...", &html[..50])
-                                        } else {
-                                            html.clone()
-                                        }
-                                    }
-                                };
-
-                                info!("📋 Content preview: {}", content_preview);
-
-                                let message = Message::ClipboardUpdate {
-                                    content_type: content.content_type_str().to_string(),
-                                    content: content.to_base64(),
-                                    timestamp: chrono::Utc::now(),
-                                    source: Config::get_source_name(),
-                                    checksum: checksum.clone(),
-                                };
-
-                                info!("📤 Sending clipboard update to server...");
-                                if let Err(e) = client_tx.send(message).await {
-                                    error!("❌ Failed to send clipboard update: {}", e);
-                                } else {
-                                    info!("✓ Clipboard update sent to server");
-                                }
-                            }
-                            Ok(None) => {
-                                warn!("⚠ Clipboard checksum exists but content is None");
-                            }
-                            Err(e) => {
-                                error!("❌ Failed to read clipboard content: {}", e);
-                            }
+                    let sensitive = clipboard.content_is_sensitive();
+                    let mut metadata = content.metadata();
+                    if sensitive {
+                        if !settings.capture_sensitive() {
+                            info!("🔒 Skipping clipboard content marked as secret");
+                            skipped(&checksum, "sensitive");
+                            continue;
                         }
+                        metadata = Some(mark_sensitive(metadata.as_deref()));
+                    } else {
+                        info!("📋 Content preview: {}", content_preview);
                     }
-                }
-                Ok(None) => {
-                    if iteration % 10 == 1 {
-                        info!("Clipboard is empty");
+
+                    // Secrets never leave through the webhook
+                    if let Some(notifier) = notifier.as_ref().filter(|_| !sensitive) {
+                        notifier.notify(&content, &wire_checksum);
                     }
-                    if last_checksum.is_some() {
-                        info!("Clipboard cleared (was: {:?})", last_checksum.as_ref().map(|s| &s[..8]));
-                        last_checksum = None;
+
+                    if !config.sync.syncs(content.content_type_str()) {
+                        info!(
+                            "Not sending {} content (not in sync_types)",
+                            content.content_type_str()
+                        );
+                        skipped(&checksum, "sync_types");
+                        continue;
                     }
+
+                    client_tx.push(local_update(&content, config.source_name(), metadata));
+                    info!("📤 Clipboard update ready to send to the server");
+                }
+                Ok(None) => {
+                    warn!("⚠ Clipboard checksum exists but content is None");
                 }
                 Err(e) => {
-                    error!("❌ Error checking clipboard: {}", e);
-                    error!("This might be a clipboard access issue - check permissions");
+                    error!("❌ Failed to read clipboard content: {}", e);
                 }
             }
         }
@@ -279,10 +738,19 @@ impl ClipboardDaemon {
 
     async fn monitor_clipboard_for_server(
         config: Config,
-        storage: Arc<ClipboardStorage>,
-        client_tx: mpsc::Sender<Message>,
+        live: Arc<RwLock<LiveSettings>>,
+        pause: PauseFlag,
+        storage: Arc<dyn Storage>,
+        client_tx: LatestUpdate,
+        applied: RecentlyApplied,
+        replace: ReplaceRules,
     ) {
-        let mut clipboard = match ClipboardManager::new() {
+        let mut clipboard = match ClipboardManager::new()
+            .map(|c| {
+                c.with_preference(config.clipboard.prefer)
+                    .with_xclip_targets(config.clipboard.xclip_targets.clone())
+            })
+        {
             Ok(c) => c,
             Err(e) => {
                 error!("Failed to initialize clipboard manager: {}", e);
@@ -290,65 +758,298 @@ impl ClipboardDaemon {
             }
         };
 
-        let mut last_checksum: Option<String> = None;
-        let interval = Duration::from_millis(config.sync.interval_ms);
+        let last_checksum = initial_checksum(config.sync.send_on_startup, || {
+            clipboard.get_content_checksum()
+        });
+        let notifier = change_notifier(&config);
+        let event_log = SyncEventLog::from_config(&config.sync);
+        let source_name = config.source_name();
+        let skipped = |checksum: &str, reason: &str| {
+            if let Some(log) = &event_log {
+                log.record(
+                    SyncEvent::new(Direction::Outbound, &source_name, checksum, "skipped")
+                        .with_reason(reason),
+                );
+            }
+        };
 
+        let mut watcher = ChangeWatcher::new(config.clipboard.change_notifications);
+        let mut resync = ResyncTimer::new(
+            config.sync.force_resync_interval_secs.map(Duration::from_secs),
+        );
+        let mut tracker = ChangeTracker::new(last_checksum, &config.sync);
+        let mut was_paused = false;
         loop {
-            sleep(interval).await;
-
-            match clipboard.get_content_checksum() {
-                Ok(Some(checksum)) => {
-                    if last_checksum.as_ref() != Some(&checksum) {
-                        last_checksum = Some(checksum.clone());
-
-                        if let Ok(Some(content)) = clipboard.get_content() {
-                            info!("Detected clipboard change");
-
-                            let content_type = match &content {
-                                ClipboardContent::Text(_) => {
-                                    crate::storage::models::ClipboardContentType::Text
-                                }
-                                ClipboardContent::Image(_) => {
-                                    crate::storage::models::ClipboardContentType::Image
-                                }
-                                ClipboardContent::Html(_) => {
-                                    crate::storage::models::ClipboardContentType::Html
-                                }
-                            };
-
-                            let entry = ClipboardEntry::new(
-                                content_type,
-                                content.to_base64(),
-                                Config::get_source_name(),
-                            );
-
-                            // Store locally
-                            if let Err(e) = storage.insert(&entry).await {
-                                error!("Failed to store clipboard entry: {}", e);
-                            }
-
-                            // Send to remote via client
-                            let message = Message::ClipboardUpdate {
-                                content_type: content.content_type_str().to_string(),
-                                content: content.to_base64(),
-                                timestamp: chrono::Utc::now(),
-                                source: Config::get_source_name(),
-                                checksum: entry.checksum,
-                            };
-
-                            if let Err(e) = client_tx.send(message).await {
-                                error!("Failed to send clipboard update: {}", e);
-                            }
-                        }
-                    }
-                }
-                Ok(None) => {
-                    last_checksum = None;
-                }
+            let settings = live.read().unwrap().clone();
+            let (changed, forced) = next_check(
+                &mut watcher,
+                &mut resync,
+                Duration::from_millis(settings.interval_ms),
+            )
+            .await;
+
+            if pause.is_paused() {
+                was_paused = true;
+                continue;
+            }
+            if std::mem::take(&mut was_paused) {
+                // What was copied while paused stays local
+                tracker.last_checksum =
+                    initial_checksum(false, || clipboard.get_content_checksum());
+                continue;
+            }
+            if !changed {
+                continue;
+            }
+            if forced {
+                info!("Forced resync: re-sending the current clipboard");
+            }
+
+            let checksum = match tracker.on_check(forced, || clipboard.get_content_checksum()).await {
+                Ok(Some(checksum)) => checksum,
+                Ok(None) => continue,
                 Err(e) => {
                     error!("Error checking clipboard: {}", e);
+                    continue;
+                }
+            };
+
+            if let Ok(Some(content)) = clipboard.get_content() {
+                if applied.is_echo(&content) {
+                    debug!("Not re-sending update just received from the server");
+                    skipped(&checksum, "echo");
+                    continue;
+                }
+                let content = replace.on_send(content.transformed(&settings.transform));
+                info!("Detected clipboard change");
+
+                let sensitive = clipboard.content_is_sensitive();
+                if sensitive && !settings.capture_sensitive() {
+                    info!("Skipping clipboard content marked as secret");
+                    skipped(&checksum, "sensitive");
+                    continue;
+                }
+
+                if let Some(notifier) = notifier.as_ref().filter(|_| !sensitive) {
+                    notifier.notify(&content, &content.checksum());
+                }
+
+                let content_type = crate::storage::models::ClipboardContentType::from(
+                    content.content_type_str(),
+                );
+
+                let mut entry = ClipboardEntry::new(
+                    content_type,
+                    content.to_base64(),
+                    config.source_name(),
+                );
+                if let Some(metadata) = content.metadata() {
+                    entry = entry.with_metadata(metadata);
+                }
+                if sensitive {
+                    entry = entry.mark_sensitive();
+                }
+
+                // Store locally
+                if !config.storage.captures(content.content_type_str()) {
+                    info!("Not storing {} content (not in capture_types)", content.content_type_str());
+                } else if let Err(e) = storage.insert(&entry).await {
+                    error!("Failed to store clipboard entry: {}", e);
+                }
+
+                // Send to remote via client
+                if !config.sync.syncs(content.content_type_str()) {
+                    info!("Not sending {} content (not in sync_types)", content.content_type_str());
+                    skipped(&checksum, "sync_types");
+                    continue;
                 }
+                client_tx.push(local_update(&content, config.source_name(), entry.metadata));
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::SyncDirection;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use tokio::net::TcpListener;
+    use tokio::time::timeout;
+
+    #[test]
+    fn test_transformed_update_carries_its_own_checksum() {
+        let mut config = Config::default();
+        config.sync.transform.trim_trailing_whitespace = true;
+        let copied = ClipboardContent::Text("hello   \nworld\t".to_string());
+        let sent = copied.clone().transformed(&config.sync.transform);
+        assert_ne!(sent.checksum(), copied.checksum());
+
+        let message = local_update(&sent, "test".to_string(), None);
+        let Message::ClipboardUpdate { content_type, content, checksum, metadata, .. } = message
+        else {
+            panic!("expected a plain update, got {:?}", message);
+        };
+        // What the server and client check before applying an update
+        let received =
+            ClipboardContent::from_base64(&content_type, &content, metadata.as_deref(), usize::MAX)
+                .unwrap();
+        assert!(received.matches_checksum(&checksum));
+        assert!(!received.matches_checksum(&copied.checksum()));
+    }
+
+    async fn daemon_for(listener: &TcpListener, auto_connect: bool) -> ClipboardDaemon {
+        let mut config = Config::default();
+        config.client.server_host = "127.0.0.1".to_string();
+        config.client.server_port = listener.local_addr().unwrap().port();
+        config.client.auto_connect = auto_connect;
+        ClipboardDaemon::new(config, DaemonMode::Client)
+    }
+
+    #[tokio::test]
+    async fn test_client_does_not_connect_when_auto_connect_is_off() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let daemon = daemon_for(&listener, false).await;
+
+        let task = daemon.spawn_client(ClipboardClient::new(daemon.config.clone()));
+        let accepted = timeout(Duration::from_millis(300), listener.accept()).await;
+        assert!(accepted.is_err(), "client connected despite auto_connect = false");
+        task.abort();
+    }
+
+    #[tokio::test]
+    async fn test_client_connects_when_auto_connect_is_on() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let daemon = daemon_for(&listener, true).await;
+
+        let task = daemon.spawn_client(ClipboardClient::new(daemon.config.clone()));
+        let accepted = timeout(Duration::from_secs(5), listener.accept()).await;
+        assert!(accepted.is_ok());
+        task.abort();
+    }
+
+    #[tokio::test]
+    async fn test_monitor_is_only_started_when_sending() {
+        for (direction, sends) in [
+            (SyncDirection::Both, true),
+            (SyncDirection::SendOnly, true),
+            (SyncDirection::ReceiveOnly, false),
+        ] {
+            let mut config = Config::default();
+            config.sync.direction = direction;
+            let daemon = ClipboardDaemon::new(config, DaemonMode::Client);
+
+            let started = Arc::new(AtomicBool::new(false));
+            let flag = Arc::clone(&started);
+            let task = daemon.spawn_monitor(async move {
+                flag.store(true, Ordering::SeqCst);
+            });
+            assert_eq!(task.is_some(), sends, "{:?}", direction);
+            if let Some(task) = task {
+                task.await.unwrap();
+            }
+            assert_eq!(started.load(Ordering::SeqCst), sends, "{:?}", direction);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_debounce_waits_for_a_stable_checksum() {
+        let delay = Duration::from_millis(1);
+        let scripted = |reads: &[&str]| {
+            let mut reads: std::collections::VecDeque<_> =
+                reads.iter().map(|r| r.to_string()).collect();
+            move || Ok(reads.pop_front())
+        };
+
+        // An intermediate format "a" is replaced by "b", which then holds
+        let settled = debounce("a".to_string(), delay, scripted(&["b", "b"])).await;
+        assert_eq!(settled.unwrap().as_deref(), Some("b"));
+
+        // Unchanged on the first re-read: no restart
+        let settled = debounce("a".to_string(), delay, scripted(&["a", "b"])).await;
+        assert_eq!(settled.unwrap().as_deref(), Some("a"));
+
+        // Emptied while waiting
+        let settled = debounce("a".to_string(), delay, scripted(&[])).await;
+        assert_eq!(settled.unwrap(), None);
+
+        // Disabled: no re-read at all
+        let settled = debounce("a".to_string(), Duration::ZERO, scripted(&[])).await;
+        assert_eq!(settled.unwrap().as_deref(), Some("a"));
+    }
+
+    #[tokio::test]
+    async fn test_forced_resync_fires_on_its_interval() {
+        let period = Duration::from_millis(200);
+        let mut resync = ResyncTimer::new(Some(period));
+        assert!(timeout(Duration::from_millis(100), resync.tick()).await.is_err());
+        assert!(timeout(period, resync.tick()).await.is_ok());
+        assert!(timeout(period * 2, resync.tick()).await.is_ok());
+
+        // Disabled by default
+        let mut resync = ResyncTimer::new(None);
+        assert!(timeout(period * 2, resync.tick()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_forced_resync_resends_an_unchanged_checksum() {
+        // Polling far slower than the resync, so only the resync can fire
+        let mut watcher = ChangeWatcher::new(false);
+        let mut resync = ResyncTimer::new(Some(Duration::from_millis(50)));
+        let mut tracker = ChangeTracker::new(Some("abc".to_string()), &Config::default().sync);
+        let read = || Ok(Some("abc".to_string()));
+
+        assert_eq!(tracker.on_check(false, read).await.unwrap(), None);
+
+        let check = next_check(&mut watcher, &mut resync, Duration::from_secs(60));
+        let (changed, forced) = timeout(Duration::from_secs(1), check).await.unwrap();
+        assert!(changed && forced);
+        let sent = tracker.on_check(forced, read).await.unwrap();
+        assert_eq!(sent.as_deref(), Some("abc"), "resync did not re-send");
+    }
+
+    #[tokio::test]
+    async fn test_transient_empty_read_does_not_resend() {
+        // Feeds a monitor's checks a sequence of clipboard reads, returning
+        // how many would be sent
+        async fn sends(reads: &[Option<&str>], needed: u32) -> usize {
+            let mut sync = Config::default().sync;
+            sync.empty_reads_before_clear = needed;
+            sync.debounce_ms = 0;
+            let mut tracker = ChangeTracker::new(None, &sync);
+            let mut sent = 0;
+            for read in reads {
+                let read = || Ok(read.map(str::to_string));
+                if tracker.on_check(false, read).await.unwrap().is_some() {
+                    sent += 1;
+                }
+            }
+            sent
+        }
+
+        // A single unreadable poll between two identical reads
+        assert_eq!(sends(&[Some("a"), None, Some("a")], 3).await, 1);
+        // Two blips, separated by a good read, don't add up
+        assert_eq!(sends(&[Some("a"), None, None, Some("a"), None, Some("a")], 3).await, 1);
+        // Actually cleared, then the same content copied again
+        assert_eq!(sends(&[Some("a"), None, None, None, Some("a")], 3).await, 2);
+        // 1 (and 0) keep the old behaviour of clearing on the first empty read
+        assert_eq!(sends(&[Some("a"), None, Some("a")], 1).await, 2);
+        assert_eq!(sends(&[Some("a"), None, Some("a")], 0).await, 2);
+    }
+
+    #[test]
+    fn test_clipboard_unchanged_across_restart_is_not_resent() {
+        // The monitor sends when a poll differs from its last checksum
+        let on_clipboard = "abc".to_string();
+        let last = initial_checksum(false, || Ok(Some(on_clipboard.clone())));
+        assert_eq!(last.as_ref(), Some(&on_clipboard));
+
+        let last = initial_checksum(true, || Ok(Some(on_clipboard.clone())));
+        assert_eq!(last, None);
+
+        // An unreadable clipboard is sent once it can be read
+        let last = initial_checksum(false, || Err(anyhow::anyhow!("no display")));
+        assert_eq!(last, None);
+    }
+}
@@ -0,0 +1,119 @@
+//! mDNS advertisement and browsing for clippy servers on the local network.
+//! Only built with the `discovery` feature.
+
+use anyhow::{Context, Result};
+use mdns_sd::{ServiceDaemon, ServiceEvent, ServiceInfo};
+use serde::Serialize;
+use std::time::{Duration, Instant};
+
+/// DNS-SD service type both servers register under
+pub const SERVICE_TYPE: &str = "_clippy._tcp.local.";
+
+/// TXT property telling the TCP sync server ("tcp") and the HTTP server
+/// ("http") apart
+const PROTOCOL_KEY: &str = "protocol";
+
+/// A server found on the network
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Discovered {
+    pub name: String,
+    pub host: String,
+    pub port: u16,
+    pub protocol: String,
+}
+
+/// Keeps a service registered until dropped
+pub struct Advertisement {
+    daemon: ServiceDaemon,
+    fullname: String,
+}
+
+impl Drop for Advertisement {
+    fn drop(&mut self) {
+        let _ = self.daemon.unregister(&self.fullname);
+        let _ = self.daemon.shutdown();
+    }
+}
+
+/// Announce a server listening on `port` on every local interface
+pub fn advertise(port: u16, protocol: &str) -> Result<Advertisement> {
+    let host = local_host_name();
+    let instance = format!("clippy {} on {}", protocol, host);
+    // A name of our own, so we never contend for the machine's own record
+    let host_name = format!("{}-clippy-{}.local.", host, protocol);
+
+    let info = ServiceInfo::new(
+        SERVICE_TYPE,
+        &instance,
+        &host_name,
+        "",
+        port,
+        &[(PROTOCOL_KEY, protocol)][..],
+    )
+    .context("invalid mDNS service description")?
+    .enable_addr_auto();
+    let fullname = info.get_fullname().to_string();
+
+    let daemon = ServiceDaemon::new().context("failed to start mDNS responder")?;
+    daemon
+        .register(info)
+        .context("failed to register mDNS service")?;
+
+    Ok(Advertisement { daemon, fullname })
+}
+
+/// Collect the servers that answer within `wait`. Blocks for the whole wait.
+pub fn browse(wait: Duration) -> Result<Vec<Discovered>> {
+    let daemon = ServiceDaemon::new().context("failed to start mDNS browser")?;
+    let events = daemon
+        .browse(SERVICE_TYPE)
+        .context("failed to browse for clippy servers")?;
+
+    let deadline = Instant::now() + wait;
+    let mut found = Vec::new();
+    while let Some(remaining) = deadline.checked_duration_since(Instant::now()) {
+        let Ok(event) = events.recv_timeout(remaining) else {
+            break;
+        };
+        let ServiceEvent::ServiceResolved(info) = event else {
+            continue;
+        };
+
+        // Prefer IPv4; Parallels and most home setups route it more reliably
+        let Some(address) = info
+            .get_addresses()
+            .iter()
+            .min_by_key(|addr| (addr.is_ipv6(), addr.to_string()))
+        else {
+            continue;
+        };
+        let server = Discovered {
+            name: info.get_fullname().to_string(),
+            host: address.to_string(),
+            port: info.get_port(),
+            protocol: info
+                .get_property_val_str(PROTOCOL_KEY)
+                .unwrap_or("tcp")
+                .to_string(),
+        };
+        if !found.contains(&server) {
+            found.push(server);
+        }
+    }
+
+    let _ = daemon.shutdown();
+    Ok(found)
+}
+
+fn local_host_name() -> String {
+    std::env::var("HOSTNAME")
+        .or_else(|_| std::env::var("COMPUTERNAME"))
+        .ok()
+        .or_else(|| {
+            std::fs::read_to_string("/etc/hostname")
+                .ok()
+                .map(|name| name.trim().to_string())
+        })
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "clippy".to_string())
+}
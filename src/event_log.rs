@@ -0,0 +1,132 @@
+use crate::config::SyncConfig;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use tracing::warn;
+
+/// Size at which the event log is rotated to `<path>.1`
+const MAX_EVENT_LOG_BYTES: u64 = 5 * 1024 * 1024;
+
+/// Serializes appends and rotation across tasks
+static WRITE_LOCK: Mutex<()> = Mutex::new(());
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    Outbound,
+    Inbound,
+}
+
+/// One sync decision, written as a JSON line
+#[derive(Debug, Serialize)]
+pub struct SyncEvent {
+    pub timestamp: DateTime<Utc>,
+    pub direction: Direction,
+    pub source: String,
+    pub checksum: String,
+    pub action: &'static str, // "sent", "applied", "skipped" or "failed"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+impl SyncEvent {
+    pub fn new(direction: Direction, source: &str, checksum: &str, action: &'static str) -> Self {
+        Self {
+            timestamp: Utc::now(),
+            direction,
+            source: source.to_string(),
+            checksum: checksum.to_string(),
+            action,
+            reason: None,
+        }
+    }
+
+    pub fn with_reason(mut self, reason: impl Into<String>) -> Self {
+        self.reason = Some(reason.into());
+        self
+    }
+}
+
+/// Append-only JSON-lines record of sync decisions (`sync.event_log`)
+#[derive(Debug, Clone)]
+pub struct SyncEventLog {
+    path: PathBuf,
+    max_bytes: u64,
+}
+
+impl SyncEventLog {
+    /// Returns `None` when no event log is configured
+    pub fn from_config(config: &SyncConfig) -> Option<Self> {
+        config.event_log.clone().map(|path| Self {
+            path,
+            max_bytes: MAX_EVENT_LOG_BYTES,
+        })
+    }
+
+    /// Record an event; failures are logged and otherwise ignored
+    pub fn record(&self, event: SyncEvent) {
+        if let Err(e) = self.append(&event) {
+            warn!("Failed to write sync event log {}: {}", self.path.display(), e);
+        }
+    }
+
+    fn append(&self, event: &SyncEvent) -> anyhow::Result<()> {
+        let mut line = serde_json::to_string(event)?;
+        line.push('\n');
+
+        let _guard = WRITE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        if let Ok(metadata) = std::fs::metadata(&self.path) {
+            if metadata.len() >= self.max_bytes {
+                let mut rotated = self.path.clone().into_os_string();
+                rotated.push(".1");
+                std::fs::rename(&self.path, rotated)?;
+            }
+        }
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        file.write_all(line.as_bytes())?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_log_rotates_at_the_size_cap() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("events.jsonl");
+        let log = SyncEventLog {
+            path: path.clone(),
+            max_bytes: 200,
+        };
+        let event = |n: usize| SyncEvent::new(Direction::Outbound, "test", &n.to_string(), "sent");
+
+        let mut written = 0;
+        while !dir.path().join("events.jsonl.1").exists() {
+            log.record(event(written));
+            written += 1;
+            assert!(written < 100, "log never rotated");
+        }
+
+        // The rotated file holds the older events, the live one starts over
+        let rotated = std::fs::read_to_string(dir.path().join("events.jsonl.1")).unwrap();
+        let live = std::fs::read_to_string(&path).unwrap();
+        assert!(rotated.len() as u64 >= 200);
+        assert_eq!(live.lines().count(), 1);
+        assert!(live.contains(&format!("\"checksum\":\"{}\"", written - 1)));
+    }
+}
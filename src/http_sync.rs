@@ -1,42 +1,155 @@
 use crate::clipboard::{ClipboardContent, ClipboardManager};
-use crate::config::Config;
+use crate::config::{BasicAuth, ClipboardPreference, Config, SyncDirection, TransformConfig};
+use crate::content_hash::{content_hash, short_checksum};
+use crate::storage::{
+    models::{BatchResult, ClipboardContentType, ClipboardEntry},
+    ClipboardStorage, Storage,
+};
+use crate::sync::large_apply::LargeApplyGuard;
+use crate::sync::pause::PauseFlag;
+use crate::sync::replace::ReplaceRules;
 use anyhow::{Context, Result};
 use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use reqwest::{Method, RequestBuilder};
 use serde::{Deserialize, Serialize};
 use std::time::Duration;
 use tokio::time::sleep;
-use tracing::{error, info, warn};
+use tracing::{debug, error, info, warn};
+
+/// Longest the server holds a long-poll request before answering 204
+const LONG_POLL_HOLD: Duration = Duration::from_secs(30);
+
+/// Longest wait between attempts while the server keeps failing
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Doubles the wait between attempts on each consecutive failure, up to
+/// `MAX_BACKOFF`, and tells the caller when an outage starts and ends so
+/// each is logged once
+struct Backoff {
+    base: Duration,
+    failures: u32,
+}
+
+impl Backoff {
+    fn new(base: Duration) -> Self {
+        Self { base, failures: 0 }
+    }
+
+    /// How long to wait before the next attempt
+    fn delay(&self) -> Duration {
+        if self.failures == 0 {
+            return self.base;
+        }
+        self.base
+            .max(Duration::from_millis(100))
+            .saturating_mul(1 << self.failures.min(16))
+            .min(MAX_BACKOFF)
+    }
+
+    /// Record a failure; true if it is the first of an outage
+    fn fail(&mut self) -> bool {
+        self.failures = self.failures.saturating_add(1);
+        self.failures == 1
+    }
+
+    /// Record a success; true if it ends an outage
+    fn succeed(&mut self) -> bool {
+        std::mem::take(&mut self.failures) > 0
+    }
+}
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ClipboardItem {
     pub id: u64,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub content: Option<String>, // Base64-encoded (not present in POST response)
-    pub hash: String,    // MD5 hash
+    pub hash: String,    // SHA-256 of the decoded content
     #[serde(skip_serializing_if = "Option::is_none")]
     pub timestamp: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub size: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>, // The sender's content_type_str(), when it sent one
+}
+
+#[derive(Debug, Deserialize)]
+struct HistoryResponse {
+    items: Vec<ClipboardItem>,
 }
 
 #[derive(Debug, Serialize)]
 struct ClipboardSubmit {
     content: String, // Base64-encoded
+    content_type: String,
 }
 
-#[derive(Debug, Deserialize)]
-struct HealthResponse {
-    status: String,
-    items_count: usize,
-    uptime_seconds: u64,
+/// `GET /health` from the HTTP server
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HealthResponse {
+    pub status: String,
+    pub items_count: usize,
+    pub uptime_seconds: u64,
+    /// Changes whenever the server restarts; absent from older servers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub instance_id: Option<String>,
+    /// Id the server will give its next item
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_id: Option<u64>,
+}
+
+/// Interpret decoded server content, trusting the declared content type when
+/// present and falling back to a UTF-8 check when it is not
+fn decode_item_content(content_type: Option<&str>, bytes: Vec<u8>) -> ClipboardContent {
+    match content_type {
+        Some("image") => ClipboardContent::Image(bytes),
+        Some("text") => ClipboardContent::Text(String::from_utf8_lossy(&bytes).into_owned()),
+        Some("html") => ClipboardContent::Html(String::from_utf8_lossy(&bytes).into_owned()),
+        _ => match String::from_utf8(bytes) {
+            Ok(text) => ClipboardContent::Text(text),
+            Err(e) => ClipboardContent::Image(e.into_bytes()),
+        },
+    }
+}
+
+/// The raw bytes sent over HTTP; the server and hashes work on these for
+/// every content type
+fn content_bytes(content: &ClipboardContent) -> &[u8] {
+    match content {
+        ClipboardContent::Text(text) | ClipboardContent::Html(text) => text.as_bytes(),
+        ClipboardContent::Image(data) | ClipboardContent::Other { data, .. } => data,
+    }
+}
+
+/// Build a local history entry for synced content
+fn history_entry(content: &ClipboardContent, source: String) -> ClipboardEntry {
+    ClipboardEntry::new(
+        ClipboardContentType::from(content.content_type_str()),
+        content.to_base64(),
+        source,
+    )
 }
 
 pub struct HttpSyncClient {
     server_url: String,
     poll_interval: Duration,
     client: reqwest::Client,
+    basic_auth: Option<BasicAuth>, // For a reverse proxy in front of the server
     last_sent_hash: Option<String>,
     last_received_id: u64,
+    server_instance: Option<String>, // From the last health check
+    storage: Option<ClipboardStorage>, // Local history; sent and received items are stored when set
+    pull_history: bool,
+    direction: SyncDirection,
+    prefer: ClipboardPreference,
+    xclip_targets: Vec<String>,
+    long_poll: bool,
+    transform: TransformConfig,
+    replace: ReplaceRules,
+    large_apply: LargeApplyGuard,
+    log_preview_chars: usize,
+    pause: PauseFlag,
+    source: String, // Recorded on history entries for local changes
+    capture_sensitive: bool, // Sync secrets (flagged in history) instead of skipping them
 }
 
 impl HttpSyncClient {
@@ -50,25 +163,166 @@ impl HttpSyncClient {
             server_url,
             poll_interval: Duration::from_millis(poll_interval_ms),
             client,
+            basic_auth: None,
             last_sent_hash: None,
             last_received_id: 0,
+            server_instance: None,
+            storage: None,
+            pull_history: false,
+            direction: SyncDirection::Both,
+            prefer: ClipboardPreference::default(),
+            xclip_targets: Vec::new(),
+            long_poll: false,
+            transform: TransformConfig::default(),
+            replace: ReplaceRules::default(),
+            large_apply: LargeApplyGuard::default(),
+            log_preview_chars: 0,
+            pause: PauseFlag::default(),
+            source: Config::get_source_name(),
+            capture_sensitive: false,
         }
     }
 
-    pub fn from_config(config: &Config) -> Self {
-        let server_url = format!(
-            "http://{}:{}",
-            config.client.server_host, config.client.server_port
+    /// Label local changes with `client.source_name` in history
+    pub fn with_source_name(mut self, source: String) -> Self {
+        self.source = source;
+        self
+    }
+
+    /// `storage.sensitive_content = "flag"`: sync content a password manager
+    /// marked as secret, flagged in history, instead of skipping it
+    pub fn with_capture_sensitive(mut self, capture: bool) -> Self {
+        self.capture_sensitive = capture;
+        self
+    }
+
+    /// Rewrite copied text (line endings, trailing whitespace) before sending
+    pub fn with_transform(mut self, transform: TransformConfig) -> Self {
+        self.transform = transform;
+        self
+    }
+
+    /// Rewrite text in transit with `sync.replace_rules`
+    pub fn with_replace_rules(mut self, replace: ReplaceRules) -> Self {
+        self.replace = replace;
+        self
+    }
+
+    /// Authenticate every request with HTTP basic auth
+    pub fn with_basic_auth(mut self, basic_auth: Option<BasicAuth>) -> Self {
+        self.basic_auth = basic_auth;
+        self
+    }
+
+    /// Confirm or skip received items over `sync.confirm_large_apply_bytes`
+    pub fn with_large_apply(mut self, large_apply: LargeApplyGuard) -> Self {
+        self.large_apply = large_apply;
+        self
+    }
+
+    /// Neither send nor apply clipboard items while `pause` is set
+    pub fn with_pause(mut self, pause: PauseFlag) -> Self {
+        self.pause = pause;
+        self
+    }
+
+    /// Log up to this many characters of synced text; 0 logs only type and size
+    pub fn with_log_preview_chars(mut self, chars: usize) -> Self {
+        self.log_preview_chars = chars;
+        self
+    }
+
+    /// Wait on `/api/clipboard/wait` instead of polling `/api/clipboard/latest`
+    pub fn with_long_poll(mut self) -> Self {
+        self.long_poll = true;
+        self
+    }
+
+    /// What to read when the local clipboard holds both an image and text
+    pub fn with_clipboard_preference(mut self, prefer: ClipboardPreference) -> Self {
+        self.prefer = prefer;
+        self
+    }
+
+    /// Text targets for the Linux xclip fallback, in order
+    pub fn with_xclip_targets(mut self, targets: Vec<String>) -> Self {
+        self.xclip_targets = targets;
+        self
+    }
+
+    /// Only push local changes, only pull remote ones, or both
+    pub fn with_direction(mut self, direction: SyncDirection) -> Self {
+        self.direction = direction;
+        self
+    }
+
+    /// Record synced clipboard items in local storage so `clippy history` sees them
+    pub fn with_storage(mut self, storage: ClipboardStorage) -> Self {
+        self.storage = Some(storage);
+        self
+    }
+
+    /// Import the server's history into local storage before syncing starts
+    pub fn with_history_import(mut self) -> Self {
+        self.pull_history = true;
+        self
+    }
+
+    /// A fresh client for a background task, sharing this client's settings
+    fn worker(&self) -> Self {
+        let mut worker = Self::new(
+            self.server_url.clone(),
+            self.poll_interval.as_millis() as u64,
         );
-        Self::new(server_url, config.sync.interval_ms)
+        worker.basic_auth = self.basic_auth.clone();
+        worker.server_instance = self.server_instance.clone();
+        worker.storage = self.storage.clone();
+        worker.direction = self.direction;
+        worker.prefer = self.prefer;
+        worker.xclip_targets = self.xclip_targets.clone();
+        worker.long_poll = self.long_poll;
+        worker.transform = self.transform.clone();
+        worker.replace = self.replace.clone();
+        worker.large_apply = self.large_apply;
+        worker.log_preview_chars = self.log_preview_chars;
+        worker.pause = self.pause.clone();
+        worker.source = self.source.clone();
+        worker.capture_sensitive = self.capture_sensitive;
+        worker
+    }
+
+    /// Store `entry` in local history, if enabled; `applied` counts it as
+    /// copied to the clipboard from a sync
+    async fn persist(&self, entry: ClipboardEntry, applied: bool) {
+        let Some(storage) = &self.storage else {
+            return;
+        };
+
+        match storage.insert(&entry).await {
+            Ok(id) if applied => {
+                if let Err(e) = storage.record_apply(id).await {
+                    warn!("⚠️  Failed to count applied entry {}: {}", id, e);
+                }
+            }
+            Ok(_) => {}
+            Err(e) => warn!("⚠️  Failed to store clipboard entry locally: {}", e),
+        }
+    }
+
+    /// Start a request to `url`, with basic auth when configured
+    fn request(&self, method: Method, url: &str) -> RequestBuilder {
+        let request = self.client.request(method, url);
+        match &self.basic_auth {
+            Some(auth) => request.basic_auth(&auth.username, Some(&auth.password)),
+            None => request,
+        }
     }
 
     /// Test connectivity to the server
     pub async fn health_check(&self) -> Result<HealthResponse> {
         let url = format!("{}/health", self.server_url);
         let response = self
-            .client
-            .get(&url)
+            .request(Method::GET, &url)
             .send()
             .await
             .context("Failed to connect to server")?;
@@ -85,15 +339,100 @@ impl HttpSyncClient {
         Ok(health)
     }
 
+    /// Remember the server instance from `health`. A different instance
+    /// means the server restarted and numbers items from 1 again, so
+    /// `last_received_id` is reset; returns whether that happened.
+    fn note_server_instance(&mut self, health: &HealthResponse) -> bool {
+        let Some(instance_id) = &health.instance_id else {
+            return false;
+        };
+        let restarted = self
+            .server_instance
+            .as_ref()
+            .is_some_and(|known| known != instance_id);
+        if restarted {
+            info!("🔄 Server restarted; receiving its items from the start");
+            self.last_received_id = 0;
+        }
+        self.server_instance = Some(instance_id.clone());
+        restarted
+    }
+
+    /// Health-check the server and reset receive state if it restarted
+    async fn check_for_restart(&mut self) {
+        match self.health_check().await {
+            Ok(health) => {
+                self.note_server_instance(&health);
+            }
+            Err(e) => debug!("Health check failed: {}", e),
+        }
+    }
+
+    /// Get the server's clipboard history, oldest first
+    async fn get_history_from_server(&self) -> Result<Vec<ClipboardItem>> {
+        let url = format!("{}/api/clipboard/history", self.server_url);
+        let response = self
+            .request(Method::GET, &url)
+            .send()
+            .await
+            .context("Failed to get history from server")?;
+
+        if !response.status().is_success() {
+            anyhow::bail!("Server returned error: {}", response.status());
+        }
+
+        let history = response
+            .json::<HistoryResponse>()
+            .await
+            .context("Failed to parse history response")?;
+
+        Ok(history.items)
+    }
+
+    /// Copy the server's history into local storage in one batch; items
+    /// already stored locally are skipped
+    async fn import_history(&self, storage: &ClipboardStorage) -> Result<BatchResult> {
+        let items = self.get_history_from_server().await?;
+        let mut entries = Vec::with_capacity(items.len());
+
+        for item in items {
+            let Some(content_base64) = item.content else {
+                continue;
+            };
+            let bytes = match BASE64.decode(&content_base64) {
+                Ok(bytes) => bytes,
+                Err(e) => {
+                    warn!("⚠️  Skipping server item {}: {}", item.id, e);
+                    continue;
+                }
+            };
+
+            let content = decode_item_content(item.content_type.as_deref(), bytes);
+            let mut entry = history_entry(&content, "http".to_string());
+            if let Some(timestamp) = item
+                .timestamp
+                .as_deref()
+                .and_then(|t| chrono::DateTime::parse_from_rfc3339(t).ok())
+            {
+                entry.timestamp = timestamp.with_timezone(&chrono::Utc);
+                entry.created_at = entry.timestamp;
+            }
+            entries.push(entry);
+        }
+
+        storage.insert_batch(&entries).await
+    }
+
     /// Send clipboard content to server
-    async fn send_to_server(&self, content: &str) -> Result<ClipboardItem> {
-        let encoded = BASE64.encode(content.as_bytes());
-        let submit = ClipboardSubmit { content: encoded };
+    async fn send_to_server(&self, content: &ClipboardContent) -> Result<ClipboardItem> {
+        let submit = ClipboardSubmit {
+            content: BASE64.encode(content_bytes(content)),
+            content_type: content.content_type_str().to_string(),
+        };
 
         let url = format!("{}/api/clipboard", self.server_url);
         let response = self
-            .client
-            .post(&url)
+            .request(Method::POST, &url)
             .json(&submit)
             .send()
             .await
@@ -115,8 +454,7 @@ impl HttpSyncClient {
     async fn get_from_server(&self) -> Result<Option<ClipboardItem>> {
         let url = format!("{}/api/clipboard/latest", self.server_url);
         let response = self
-            .client
-            .get(&url)
+            .request(Method::GET, &url)
             .send()
             .await
             .context("Failed to get clipboard from server")?;
@@ -132,58 +470,59 @@ impl HttpSyncClient {
         }
     }
 
+    /// Wait for an item newer than the last one received; `None` if the
+    /// server's hold time elapsed without one
+    async fn wait_on_server(&self) -> Result<Option<ClipboardItem>> {
+        let url = format!(
+            "{}/api/clipboard/wait?since_id={}",
+            self.server_url, self.last_received_id
+        );
+        let response = self
+            .request(Method::GET, &url)
+            .timeout(LONG_POLL_HOLD + Duration::from_secs(10))
+            .send()
+            .await
+            .context("Failed to wait for clipboard from server")?;
+
+        match response.status() {
+            reqwest::StatusCode::NO_CONTENT => Ok(None),
+            status if status.is_success() => {
+                let item = response
+                    .json::<ClipboardItem>()
+                    .await
+                    .context("Failed to parse clipboard item")?;
+                Ok(Some(item))
+            }
+            status => anyhow::bail!("Server rejected long-poll request: {}", status),
+        }
+    }
+
     /// Monitor local clipboard and send changes to server
     async fn monitor_local_clipboard(&mut self, clipboard: &mut ClipboardManager) -> Result<()> {
         info!("🔍 Starting local clipboard monitor");
+        let mut backoff = Backoff::new(self.poll_interval);
+        let mut was_paused = false;
 
         loop {
-            sleep(self.poll_interval).await;
+            sleep(backoff.delay()).await;
+            if self.pause.is_paused() {
+                was_paused = true;
+                continue;
+            }
 
             // Get current clipboard content
             match clipboard.get_content() {
                 Ok(Some(content)) => {
-                    let content_str = match &content {
-                        ClipboardContent::Text(text) => text.clone(),
-                        ClipboardContent::Image(data) => {
-                            // For images, we'll use base64 directly
-                            BASE64.encode(data)
-                        }
-                        ClipboardContent::Html(html) => html.clone(),
-                    };
-
-                    // Calculate hash
-                    let current_hash = format!("{:x}", md5::compute(content_str.as_bytes()));
-
-                    // Check if content changed
-                    if self.last_sent_hash.as_ref() != Some(&current_hash) {
-                        let preview = if content_str.len() > 50 {
-                            format!("{}...", &content_str[..50])
-                        } else {
-                            content_str.clone()
-                        };
-
-                        info!(
-                            "🔍 Local clipboard changed: '{}' ({} bytes, hash: {})",
-                            preview,
-                            content_str.len(),
-                            &current_hash[..8]
-                        );
-
-                        // Send to server
-                        match self.send_to_server(&content_str).await {
-                            Ok(item) => {
-                                info!(
-                                    "📤 Sent to server: id={}, hash={}",
-                                    item.id,
-                                    &item.hash[..8]
-                                );
-                                self.last_sent_hash = Some(current_hash);
-                            }
-                            Err(e) => {
-                                error!("❌ Failed to send to server: {}", e);
-                            }
-                        }
+                    let content = self.replace.on_send(content.transformed(&self.transform));
+
+                    // What was copied while paused stays local
+                    if std::mem::take(&mut was_paused) {
+                        self.last_sent_hash = Some(content_hash(content_bytes(&content)));
+                        continue;
                     }
+
+                    let sensitive = || clipboard.content_is_sensitive();
+                    self.send_local_change(content, sensitive, &mut backoff).await;
                 }
                 Ok(None) => {
                     // Clipboard is empty
@@ -195,102 +534,180 @@ impl HttpSyncClient {
         }
     }
 
+    /// Send a local clipboard read to the server if it changed. Content a
+    /// password manager marked as secret (`sensitive`, only checked for a
+    /// change) is skipped, or with `capture_sensitive` sent and flagged in
+    /// local history, as the daemon does.
+    async fn send_local_change(
+        &mut self,
+        content: ClipboardContent,
+        sensitive: impl FnOnce() -> bool,
+        backoff: &mut Backoff,
+    ) {
+        // Hash the raw bytes, as poll_server does for received items
+        let current_hash = content_hash(content_bytes(&content));
+        if self.last_sent_hash.as_ref() == Some(&current_hash) {
+            return;
+        }
+
+        let sensitive = sensitive();
+        if sensitive && !self.capture_sensitive {
+            info!("🔒 Skipping clipboard content marked as secret");
+            self.last_sent_hash = Some(current_hash);
+            return;
+        }
+
+        info!(
+            "🔍 Local clipboard changed: {} (hash: {})",
+            if sensitive {
+                "[secret]".to_string()
+            } else {
+                content.preview(self.log_preview_chars)
+            },
+            short_checksum(&current_hash)
+        );
+
+        // Send to server
+        match self.send_to_server(&content).await {
+            Ok(item) => {
+                if backoff.succeed() {
+                    info!("✅ Server is reachable again; sending resumed");
+                }
+                info!(
+                    "📤 Sent to server: id={}, hash={}",
+                    item.id,
+                    short_checksum(&item.hash)
+                );
+                self.last_sent_hash = Some(current_hash);
+                let mut entry = history_entry(&content, self.source.clone());
+                if sensitive {
+                    entry = entry.mark_sensitive();
+                }
+                self.persist(entry, false).await;
+            }
+            Err(e) => {
+                if backoff.fail() {
+                    error!("❌ Failed to send to server: {}; retrying with backoff", e);
+                } else {
+                    debug!("Send still failing: {}", e);
+                }
+            }
+        }
+    }
+
+    /// Apply a new server item to the local clipboard. Returns whether it is
+    /// done with: applied, or deliberately skipped; false leaves it to be
+    /// retried on the next poll.
+    async fn apply_item(&mut self, item: &ClipboardItem, clipboard: &mut ClipboardManager) -> bool {
+        // Skip if no content
+        let Some(ref content_base64) = item.content else {
+            warn!("⚠️  Server item {} has no content", item.id);
+            return true;
+        };
+
+        let decoded_bytes = match BASE64.decode(content_base64) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                error!("❌ Failed to decode clipboard content: {}", e);
+                return true;
+            }
+        };
+
+        // Only apply if different from what we sent (no log spam)
+        let received_hash = content_hash(&decoded_bytes);
+        if self.last_sent_hash.as_ref() == Some(&received_hash) {
+            return true;
+        }
+
+        let clipboard_content = self.replace.on_receive(decode_item_content(
+            item.content_type.as_deref(),
+            decoded_bytes,
+        ));
+
+        info!(
+            "📥 Received from server: id={}, {} (hash: {})",
+            item.id,
+            clipboard_content.preview(self.log_preview_chars),
+            short_checksum(&received_hash)
+        );
+
+        if self.pause.is_paused() {
+            info!("⏸ Sync paused: not applying item {}", item.id);
+            return true;
+        }
+        if !self.large_apply.allow(&clipboard_content).await {
+            return true;
+        }
+
+        // Apply to local clipboard
+        match clipboard.set_content(&clipboard_content) {
+            Ok(written) => {
+                // What the monitor will read back, after any rewrite
+                self.last_sent_hash =
+                    Some(content_hash(content_bytes(&clipboard_content)));
+                if written {
+                    info!("✅ Applied to local clipboard");
+                } else {
+                    info!("Local clipboard already holds item {}", item.id);
+                }
+                self.persist(history_entry(&clipboard_content, "http".to_string()), written)
+                    .await;
+                true
+            }
+            Err(e) => {
+                error!("❌ Failed to apply to clipboard: {}", e);
+                false
+            }
+        }
+    }
+
     /// Poll server for clipboard changes
     async fn poll_server(&mut self, clipboard: &mut ClipboardManager) -> Result<()> {
         info!("📥 Starting server poll loop");
+        let mut backoff = Backoff::new(self.poll_interval);
 
         loop {
-            sleep(self.poll_interval).await;
+            let latest = if self.long_poll {
+                self.wait_on_server().await
+            } else {
+                sleep(backoff.delay()).await;
+                self.get_from_server().await
+            };
+            if latest.is_ok() && backoff.succeed() {
+                info!("✅ Reconnected to server");
+                self.check_for_restart().await;
+            }
 
-            match self.get_from_server().await {
+            match latest {
                 Ok(Some(item)) => {
+                    // Ids only go backwards when the server restarted
+                    if item.id < self.last_received_id && self.server_instance.is_some() {
+                        self.check_for_restart().await;
+                    }
                     // Check if this is a new item
-                    if item.id > self.last_received_id {
-                        // Skip if no content
-                        let Some(ref content_base64) = item.content else {
-                            warn!("⚠️  Server item {} has no content", item.id);
-                            continue;
-                        };
-
-                        // Decode content
-                        match BASE64.decode(content_base64) {
-                            Ok(decoded_bytes) => {
-                                match String::from_utf8(decoded_bytes.clone()) {
-                                    Ok(content) => {
-                                        // Calculate hash of decoded content
-                                        let content_hash =
-                                            format!("{:x}", md5::compute(content.as_bytes()));
-
-                                        // Only apply if different from what we sent
-                                        if self.last_sent_hash.as_ref() != Some(&content_hash) {
-                                            let preview = if content.len() > 50 {
-                                                format!("{}...", &content[..50])
-                                            } else {
-                                                content.clone()
-                                            };
-
-                                            info!(
-                                                "📥 Received from server: id={}, '{}' ({} bytes, hash: {})",
-                                                item.id,
-                                                preview,
-                                                content.len(),
-                                                &content_hash[..8]
-                                            );
-
-                                            // Apply to local clipboard
-                                            let clipboard_content = ClipboardContent::Text(content);
-                                            match clipboard.set_content(&clipboard_content) {
-                                                Ok(_) => {
-                                                    self.last_received_id = item.id;
-                                                    self.last_sent_hash = Some(content_hash);
-                                                    info!("✅ Applied to local clipboard");
-                                                }
-                                                Err(e) => {
-                                                    error!("❌ Failed to apply to clipboard: {}", e);
-                                                }
-                                            }
-                                        }
-                                        // Silently skip if hash matches (no log spam)
-                                    }
-                                    Err(_) => {
-                                        // Binary data (image)
-                                        let content_hash =
-                                            format!("{:x}", md5::compute(&decoded_bytes));
-
-                                        if self.last_sent_hash.as_ref() != Some(&content_hash) {
-                                            info!(
-                                                "📥 Received image from server: id={}, {} bytes",
-                                                item.id,
-                                                decoded_bytes.len()
-                                            );
-
-                                            let clipboard_content =
-                                                ClipboardContent::Image(decoded_bytes);
-                                            match clipboard.set_content(&clipboard_content) {
-                                                Ok(_) => {
-                                                    self.last_received_id = item.id;
-                                                    self.last_sent_hash = Some(content_hash);
-                                                    info!("✅ Applied image to local clipboard");
-                                                }
-                                                Err(e) => {
-                                                    error!("❌ Failed to apply image: {}", e);
-                                                }
-                                            }
-                                        }
-                                    }
-                                }
-                            }
-                            Err(e) => {
-                                error!("❌ Failed to decode clipboard content: {}", e);
-                            }
-                        }
+                    if item.id <= self.last_received_id {
+                        continue;
+                    }
+                    if self.apply_item(&item, clipboard).await {
+                        self.last_received_id = item.id;
+                    } else if self.long_poll {
+                        // Still unseen, so the next long-poll returns it straight away
+                        sleep(backoff.delay()).await;
                     }
                 }
                 Ok(None) => {
                     // No clipboard items on server yet
                 }
                 Err(e) => {
-                    warn!("⚠️  Failed to poll server: {}", e);
+                    if backoff.fail() {
+                        warn!("⚠️  Lost connection to server: {}; retrying with backoff", e);
+                    } else {
+                        debug!("Server still unreachable: {}", e);
+                    }
+                    if self.long_poll {
+                        // Back off instead of hammering an unreachable server
+                        sleep(backoff.delay()).await;
+                    }
                 }
             }
         }
@@ -300,12 +717,20 @@ impl HttpSyncClient {
     pub async fn run(&mut self) -> Result<()> {
         info!("🚀 Starting HTTP clipboard sync");
         info!("📍 Server URL: {}", self.server_url);
-        info!("📊 Poll interval: {}ms", self.poll_interval.as_millis());
+        if let Some(auth) = &self.basic_auth {
+            info!("🔐 Using HTTP basic auth as {}", auth.username);
+        }
+        if self.long_poll {
+            info!("📊 Receiving via long-poll");
+        } else {
+            info!("📊 Poll interval: {}ms", self.poll_interval.as_millis());
+        }
 
         // Test server connectivity
         info!("🔗 Testing server connectivity...");
         match self.health_check().await {
             Ok(health) => {
+                self.note_server_instance(&health);
                 info!("✅ Server is reachable");
                 info!("   Status: {}", health.status);
                 info!("   Items: {}", health.items_count);
@@ -317,65 +742,219 @@ impl HttpSyncClient {
             }
         }
 
+        if self.pull_history {
+            if let Some(storage) = &self.storage {
+                info!("📚 Importing server history...");
+                match self.import_history(storage).await {
+                    Ok(result) => info!(
+                        "✅ Imported {} history items ({} already stored)",
+                        result.inserted, result.skipped
+                    ),
+                    Err(e) => warn!("⚠️  Failed to import server history: {}", e),
+                }
+            }
+        }
+
         // Initialize clipboard manager
         info!("🚀 Initializing clipboard manager...");
-        let mut clipboard = ClipboardManager::new().context("Failed to initialize clipboard")?;
+        let mut clipboard = ClipboardManager::new()
+            .context("Failed to initialize clipboard")?
+            .with_preference(self.prefer)
+            .with_xclip_targets(self.xclip_targets.clone());
         info!("✓ Clipboard manager initialized successfully");
 
         // Initialize with current clipboard content
         let mut initial_hash = None;
         if let Ok(Some(content)) = clipboard.get_content() {
-            let content_str = match &content {
-                ClipboardContent::Text(text) => text.clone(),
-                ClipboardContent::Image(data) => BASE64.encode(data),
-                ClipboardContent::Html(html) => html.clone(),
-            };
-            let hash = format!("{:x}", md5::compute(content_str.as_bytes()));
+            let hash = content_hash(content_bytes(&content));
             initial_hash = Some(hash);
             info!("📋 Initialized with current clipboard content");
         }
 
-        // Spawn both monitor and poll tasks
-        let monitor_handle = {
-            let mut client_clone = Self::new(
-                self.server_url.clone(),
-                self.poll_interval.as_millis() as u64,
-            );
+        // Spawn the monitor (send) and poll (receive) tasks this direction needs
+        let mut handles = Vec::new();
+
+        if self.direction.sends() {
+            let mut client_clone = self.worker();
             if let Some(hash) = initial_hash.clone() {
                 client_clone.last_sent_hash = Some(hash);
             }
-            let mut clipboard_clone = ClipboardManager::new()?;
-            tokio::spawn(async move {
+            let mut clipboard_clone = ClipboardManager::new()?
+                .with_preference(self.prefer)
+                .with_xclip_targets(self.xclip_targets.clone());
+            handles.push(tokio::spawn(async move {
                 if let Err(e) = client_clone
                     .monitor_local_clipboard(&mut clipboard_clone)
                     .await
                 {
                     error!("Monitor error: {}", e);
                 }
-            })
-        };
+            }));
+        } else {
+            info!("📥 Receive-only mode: not watching the local clipboard");
+        }
 
-        let poll_handle = {
-            let mut client_clone = Self::new(
-                self.server_url.clone(),
-                self.poll_interval.as_millis() as u64,
-            );
+        if self.direction.receives() {
+            let mut client_clone = self.worker();
             if let Some(hash) = initial_hash {
                 client_clone.last_sent_hash = Some(hash);
             }
             let mut clipboard_clone = ClipboardManager::new()?;
-            tokio::spawn(async move {
+            handles.push(tokio::spawn(async move {
                 if let Err(e) = client_clone.poll_server(&mut clipboard_clone).await {
                     error!("Poll error: {}", e);
                 }
-            })
-        };
+            }));
+        } else {
+            info!("📤 Send-only mode: not polling the server");
+        }
 
         info!("✓ Background processes started");
 
-        // Wait for both tasks
-        tokio::try_join!(monitor_handle, poll_handle)?;
+        // Wait for the tasks
+        for handle in handles {
+            handle.await?;
+        }
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_declared_image_is_not_treated_as_text() {
+        // Valid UTF-8, but the server says it is an image
+        let bytes = b"GIF89a plain ascii".to_vec();
+        match decode_item_content(Some("image"), bytes.clone()) {
+            ClipboardContent::Image(data) => assert_eq!(data, bytes),
+            other => panic!("expected image, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_submitted_image_round_trips() {
+        // Valid UTF-8 bytes would be mistaken for text without the declared type
+        let image = ClipboardContent::Image(b"GIF89a plain ascii".to_vec());
+        let encoded = BASE64.encode(content_bytes(&image));
+
+        let bytes = BASE64.decode(encoded).unwrap();
+        match decode_item_content(Some(image.content_type_str()), bytes) {
+            ClipboardContent::Image(data) => assert_eq!(data, b"GIF89a plain ascii"),
+            other => panic!("expected image, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_missing_content_type_uses_utf8_heuristic() {
+        assert!(matches!(
+            decode_item_content(None, b"hello".to_vec()),
+            ClipboardContent::Text(ref t) if t == "hello"
+        ));
+        assert!(matches!(
+            decode_item_content(None, vec![0x89, 0x50, 0xff, 0xfe]),
+            ClipboardContent::Image(_)
+        ));
+    }
+
+    #[test]
+    fn test_backoff_grows_to_cap_and_resets() {
+        let mut backoff = Backoff::new(Duration::from_millis(200));
+        assert_eq!(backoff.delay(), Duration::from_millis(200));
+
+        assert!(backoff.fail());
+        assert!(!backoff.fail());
+        assert_eq!(backoff.delay(), Duration::from_millis(800));
+        for _ in 0..20 {
+            backoff.fail();
+        }
+        assert_eq!(backoff.delay(), MAX_BACKOFF);
+
+        assert!(backoff.succeed());
+        assert!(!backoff.succeed());
+        assert_eq!(backoff.delay(), Duration::from_millis(200));
+    }
+
+    #[test]
+    fn test_declared_text_with_invalid_utf8_stays_text() {
+        assert!(matches!(
+            decode_item_content(Some("text"), vec![b'h', b'i', 0xff]),
+            ClipboardContent::Text(_)
+        ));
+    }
+
+    #[test]
+    fn test_server_restart_resets_last_received_id() {
+        let health = |instance_id: Option<&str>| HealthResponse {
+            status: "healthy".to_string(),
+            items_count: 1,
+            uptime_seconds: 5,
+            instance_id: instance_id.map(str::to_string),
+            next_id: Some(2),
+        };
+        let mut client = HttpSyncClient::new("http://localhost:8080".to_string(), 200);
+
+        assert!(!client.note_server_instance(&health(Some("first"))));
+        client.last_received_id = 42;
+        assert!(!client.note_server_instance(&health(Some("first"))));
+        // Older servers don't report an instance
+        assert!(!client.note_server_instance(&health(None)));
+        assert_eq!(client.last_received_id, 42);
+
+        assert!(client.note_server_instance(&health(Some("second"))));
+        assert_eq!(client.last_received_id, 0);
+    }
+
+    #[tokio::test]
+    async fn test_sensitive_content_is_not_sent() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let mut client = HttpSyncClient::new(url, 200);
+        let mut backoff = Backoff::new(client.poll_interval);
+        let accept = || async {
+            let accepted = tokio::time::timeout(Duration::from_millis(300), listener.accept()).await;
+            // Hang up right away so the request fails instead of waiting
+            accepted.is_ok()
+        };
+
+        let secret = ClipboardContent::Text("hunter2".to_string());
+        let (_, connected) = tokio::join!(
+            client.send_local_change(secret.clone(), || true, &mut backoff),
+            accept()
+        );
+        assert!(!connected, "secret was sent to the server");
+        // Not reconsidered on every poll
+        assert_eq!(client.last_sent_hash, Some(content_hash(content_bytes(&secret))));
+
+        // The same read is sent when secrets are captured
+        let mut client = client.with_capture_sensitive(true);
+        client.last_sent_hash = None;
+        let (_, connected) = tokio::join!(
+            client.send_local_change(secret, || true, &mut backoff),
+            accept()
+        );
+        assert!(connected);
+    }
+
+    #[test]
+    fn test_basic_auth_header_is_attached() {
+        let url = "http://localhost:8080/health";
+        let plain = HttpSyncClient::new("http://localhost:8080".to_string(), 200);
+        let request = plain.request(Method::GET, url).build().unwrap();
+        assert!(request.headers().get(reqwest::header::AUTHORIZATION).is_none());
+
+        let auth = BasicAuth {
+            username: "user".to_string(),
+            password: "pass".to_string(),
+        };
+        let client = plain.with_basic_auth(Some(auth.clone()));
+        let request = client.request(Method::GET, url).build().unwrap();
+        assert_eq!(
+            request.headers()[reqwest::header::AUTHORIZATION],
+            "Basic dXNlcjpwYXNz" // base64("user:pass")
+        );
+        assert!(!format!("{:?}", auth).contains("pass\""));
+    }
+}
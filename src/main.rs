@@ -1,21 +1,51 @@
 mod client;
 mod clipboard;
 mod config;
+mod content_hash;
 mod daemon;
+#[cfg(feature = "discovery")]
+mod discovery;
+mod event_log;
 mod http_sync;
+mod output;
 mod server;
 mod storage;
 mod sync;
+mod webhook;
 
 use anyhow::Result;
 use clap::{Parser, Subcommand};
-use config::Config;
+use config::{Config, ConfigError};
 use daemon::{ClipboardDaemon, DaemonMode};
-use storage::{models::ClipboardSearchQuery, ClipboardStorage};
+use serde::Serialize;
+use storage::{
+    models::{
+        grep_prefilter, ClipboardEntry, ClipboardSearchQuery, ClipboardStats, HistoryCursor,
+        HistoryOrder, MatchMode,
+    },
+    ClipboardStorage, Storage,
+};
+use sync::{large_apply::LargeApplyGuard, pause::PauseFlag, replace::ReplaceRules};
+use std::io::IsTerminal;
+use std::process::ExitCode;
 use tracing::Level;
 
+// Exit codes, so scripts can tell outcomes apart; 2 is left to clap for
+// usage errors
+const EXIT_RUNTIME_ERROR: u8 = 1;
+const EXIT_CONFIG_ERROR: u8 = 3;
+const EXIT_OUT_OF_SYNC: u8 = 4;
+const EXIT_NOT_FOUND: u8 = 5;
+
+/// Characters of content shown per entry in history and search listings
+const PREVIEW_CHARS: usize = 100;
+
+/// Characters `clippy diff` shows before and after the first difference
+const DIFF_CONTEXT_CHARS: usize = 10;
+const DIFF_EXCERPT_CHARS: usize = 40;
+
 #[derive(Parser)]
-#[command(name = "clippy")]
+#[command(name = "clippy", version)]
 #[command(about = "Cross-platform clipboard synchronization tool", long_about = None)]
 struct Cli {
     #[command(subcommand)]
@@ -24,6 +54,18 @@ struct Cli {
     /// Enable verbose logging
     #[arg(short, long, global = true)]
     verbose: bool,
+
+    /// Only show warnings and errors
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Disable colors and emoji (also honors NO_COLOR and non-terminal output)
+    #[arg(long, global = true)]
+    no_color: bool,
+
+    /// Print results as a single JSON document instead of text
+    #[arg(long, global = true)]
+    json: bool,
 }
 
 #[derive(Subcommand)]
@@ -48,11 +90,26 @@ enum Commands {
         /// Poll interval in milliseconds (default: 200)
         #[arg(short, long)]
         interval: Option<u64>,
+
+        /// Import the server's clipboard history into local storage on startup
+        #[arg(long)]
+        pull_history: bool,
+
+        /// Hold requests open until the server has a new item instead of polling
+        #[arg(long)]
+        long_poll: bool,
+    },
+
+    /// Check that the HTTP sync server is reachable and show its status
+    ServerInfo {
+        /// Server URL (default: the client's server_host and server_port)
+        #[arg(short, long)]
+        server: Option<String>,
     },
 
     /// Show clipboard history
     History {
-        /// Number of entries to show
+        /// Number of entries to show (0 for all)
         #[arg(short, long, default_value = "20")]
         limit: usize,
 
@@ -60,13 +117,32 @@ enum Commands {
         #[arg(short, long, default_value = "0")]
         offset: usize,
 
-        /// Filter by source (macos or nixos)
+        /// Filter by source (macos, nixos or a client.source_name)
         #[arg(short, long)]
         source: Option<String>,
 
         /// Filter by content type (text, image, html)
         #[arg(short, long)]
         type_filter: Option<String>,
+
+        /// Sort by last use (recent), first copy (created) or how often
+        /// entries were copied back to a clipboard (frequency)
+        #[arg(long, value_enum, default_value_t)]
+        order: HistoryOrder,
+
+        /// Only show text/HTML entries containing this text (case-insensitive).
+        /// HTML is matched after stripping tags; images are never matched.
+        #[arg(long)]
+        grep: Option<String>,
+
+        /// Also show entries flagged as sensitive by a password manager
+        #[arg(long)]
+        include_sensitive: bool,
+
+        /// Keep running and print new entries as they are stored, like
+        /// `tail -f`; `--limit` sets how many existing ones are shown first
+        #[arg(short, long, alias = "tail")]
+        follow: bool,
     },
 
     /// Search clipboard history
@@ -74,9 +150,26 @@ enum Commands {
         /// Search text
         query: String,
 
-        /// Number of results
+        /// Only entries whose whole content is the search text
+        #[arg(long, conflicts_with = "prefix")]
+        exact: bool,
+
+        /// Only entries whose content starts with the search text
+        #[arg(long)]
+        prefix: bool,
+
+        /// Number of results (0 for all)
         #[arg(short, long, default_value = "20")]
         limit: usize,
+
+        /// Sort by last use (recent), first copy (created) or how often
+        /// entries were copied back to a clipboard (frequency)
+        #[arg(long, value_enum, default_value_t)]
+        order: HistoryOrder,
+
+        /// Also show entries flagged as sensitive by a password manager
+        #[arg(long)]
+        include_sensitive: bool,
     },
 
     /// Clear clipboard history
@@ -86,6 +179,51 @@ enum Commands {
         yes: bool,
     },
 
+    /// Print the Nth most recent history entry, or copy it back to the clipboard
+    Recent {
+        /// Position in history, counting from 1 (the most recent)
+        #[arg(value_parser = clap::value_parser!(u64).range(1..))]
+        n: u64,
+
+        /// Write the entry to the local clipboard instead of printing it
+        #[arg(long)]
+        copy: bool,
+    },
+
+    /// Check whether the local clipboard matches the last stored history entry
+    Diff,
+
+    /// Re-send a history entry to machines connected to the running daemon
+    Replay {
+        /// History entry ID (see `clippy history`)
+        id: i64,
+    },
+
+    /// Stop syncing clipboard changes in running daemons until `clippy resume`
+    Pause,
+
+    /// Resume syncing after `clippy pause`
+    Resume,
+
+    /// Write image history entries to a directory as PNG files
+    ExportImages {
+        /// Directory to write `<id>.png` files into (created if missing)
+        #[arg(long)]
+        output_dir: std::path::PathBuf,
+    },
+
+    /// Find clippy servers on the local network via mDNS
+    #[cfg(feature = "discovery")]
+    Discover {
+        /// Seconds to listen for answers
+        #[arg(long, default_value = "3")]
+        timeout_secs: u64,
+
+        /// Point the client config at the first TCP server found
+        #[arg(long)]
+        write_config: bool,
+    },
+
     /// Show statistics
     Stats,
 
@@ -99,23 +237,266 @@ enum Commands {
         #[arg(long)]
         init: bool,
     },
+
+    /// Show version and build information, for bug reports
+    Version,
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    let cli = Cli::parse();
+/// How command results are printed: text, or with `--json` exactly one JSON
+/// document on stdout. Side notes go to stderr in JSON mode.
+#[derive(Clone, Copy)]
+struct Output {
+    json: bool,
+}
 
-    // Initialize logging
-    let log_level = if cli.verbose {
+impl Output {
+    /// Print `value` as JSON, or hand it to `human` to print as text
+    fn emit<T: Serialize>(self, value: &T, human: impl FnOnce(&T)) -> Result<()> {
+        if self.json {
+            println!("{}", serde_json::to_string_pretty(value)?);
+        } else {
+            human(value);
+        }
+        Ok(())
+    }
+
+    /// One item of a stream: a JSON line per item in JSON mode
+    fn emit_line<T: Serialize>(self, value: &T, human: impl FnOnce(&T)) -> Result<()> {
+        if self.json {
+            println!("{}", serde_json::to_string(value)?);
+        } else {
+            human(value);
+        }
+        Ok(())
+    }
+
+    /// A result that is only a status line; `{"message": ...}` in JSON mode
+    fn message(self, text: &str) -> Result<()> {
+        self.emit(&serde_json::json!({ "message": text }), |_| println!("{}", text))
+    }
+
+    /// Extra information that is not part of the result
+    fn note(self, text: &str) {
+        if self.json {
+            eprintln!("{}", text);
+        } else {
+            println!("{}", text);
+        }
+    }
+}
+
+/// `clippy stats` result
+#[derive(Serialize)]
+struct StatsReport {
+    #[serde(flatten)]
+    stats: ClipboardStats,
+    max_history: usize,
+    database_path: std::path::PathBuf,
+}
+
+/// `clippy version` result
+#[derive(Serialize)]
+struct VersionReport {
+    version: &'static str,
+    commit: &'static str, // "unknown" when built outside a git checkout
+    target: &'static str,
+    protocol_version: u32,
+}
+
+impl VersionReport {
+    fn current() -> Self {
+        Self {
+            version: env!("CARGO_PKG_VERSION"),
+            commit: env!("CLIPPY_GIT_COMMIT"),
+            target: env!("CLIPPY_TARGET"),
+            protocol_version: sync::protocol::PROTOCOL_VERSION,
+        }
+    }
+}
+
+/// `clippy diff` result
+#[derive(Serialize)]
+struct DiffReport {
+    in_sync: bool,
+    clipboard: Option<DiffSide>, // None when the clipboard is empty
+    stored: Option<DiffSide>,    // None when history is empty
+}
+
+#[derive(Serialize)]
+struct DiffSide {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<i64>,
+    content_type: String,
+    checksum: String,
+}
+
+impl DiffSide {
+    fn describe(&self) -> String {
+        let short = content_hash::short_checksum(&self.checksum);
+        match self.id {
+            Some(id) => format!("{}, checksum {}, entry {}", self.content_type, short, id),
+            None => format!("{}, checksum {}", self.content_type, short),
+        }
+    }
+}
+
+/// How often `clippy history --follow` checks for new entries
+const FOLLOW_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(500);
+
+/// One `clippy history` entry as text
+fn print_history_entry(entry: &ClipboardEntry) {
+    println!("ID: {}", entry.id.unwrap_or(0));
+    println!("Type: {}", entry.content_type.as_str());
+    println!("Source: {}", entry.source);
+    println!("Time: {}", entry.timestamp.format("%Y-%m-%d %H:%M:%S"));
+    println!("Checksum: {}", entry.checksum);
+    if entry.apply_count > 0 {
+        println!("Applied: {} times", entry.apply_count);
+    }
+
+    // Show preview of content
+    let preview = output::preview(&entry.content, PREVIEW_CHARS);
+
+    match entry.content_type {
+        storage::models::ClipboardContentType::Text => {
+            println!("Content: {}", preview);
+        }
+        storage::models::ClipboardContentType::Image => {
+            println!("Content: [Image data, {} bytes]", entry.size_bytes);
+        }
+        // Unknown types may not be text; never print them raw
+        storage::models::ClipboardContentType::Other(ref kind) => {
+            println!(
+                "Content: [{} data {}, {} bytes]",
+                kind,
+                entry.metadata.as_deref().unwrap_or("{}"),
+                entry.size_bytes
+            );
+        }
+        _ => {
+            println!("Content: {}", preview);
+        }
+    }
+
+    println!("---");
+}
+
+/// `clippy history --follow`: print entries used after `cursor` as they
+/// arrive, oldest first, until Ctrl-C
+async fn follow_history(
+    storage: &ClipboardStorage,
+    mut query: ClipboardSearchQuery,
+    grep: Option<&str>,
+    mut cursor: HistoryCursor,
+    out: Output,
+) -> Result<()> {
+    query.limit = 0;
+    query.offset = 0;
+    if let Some(pattern) = grep {
+        query.search_text = Some(grep_prefilter(pattern).to_string());
+    }
+
+    let stop = tokio::signal::ctrl_c();
+    tokio::pin!(stop);
+    loop {
+        tokio::select! {
+            _ = &mut stop => return Ok(()),
+            _ = tokio::time::sleep(FOLLOW_POLL_INTERVAL) => {}
+        }
+
+        query.after = Some(cursor);
+        let mut entries = storage.search(&query).await?;
+        entries.sort_by_key(HistoryCursor::of);
+        for entry in entries {
+            cursor = cursor.max(HistoryCursor::of(&entry));
+            if grep.is_none_or(|pattern| entry.grep_matches(pattern)) {
+                out.emit_line(&entry, print_history_entry)?;
+            }
+        }
+    }
+}
+
+/// Short excerpts of two texts, starting a little before where they first differ
+fn diff_excerpts(old: &str, new: &str) -> (String, String) {
+    let common = old.chars().zip(new.chars()).take_while(|(a, b)| a == b).count();
+    let start = common.saturating_sub(DIFF_CONTEXT_CHARS);
+    let excerpt = |s: &str| {
+        let offset = s.char_indices().nth(start).map_or(s.len(), |(i, _)| i);
+        let rest = &s[offset..];
+        let shown = output::truncate_chars(rest, DIFF_EXCERPT_CHARS);
+        format!(
+            "{}{:?}{}",
+            if start > 0 { "..." } else { "" },
+            shown,
+            if shown.len() < rest.len() { "..." } else { "" }
+        )
+    };
+    (excerpt(old), excerpt(new))
+}
+
+/// Uptime in its two largest units, e.g. "3h 12m"
+fn format_uptime(seconds: u64) -> String {
+    let (days, hours) = (seconds / 86_400, seconds / 3_600 % 24);
+    let (minutes, secs) = (seconds / 60 % 60, seconds % 60);
+    if days > 0 {
+        format!("{}d {}h", days, hours)
+    } else if hours > 0 {
+        format!("{}h {}m", hours, minutes)
+    } else if minutes > 0 {
+        format!("{}m {}s", minutes, secs)
+    } else {
+        format!("{}s", secs)
+    }
+}
+
+fn log_level(verbose: bool, quiet: bool) -> Level {
+    if verbose {
         Level::DEBUG
+    } else if quiet {
+        Level::WARN
     } else {
         Level::INFO
-    };
+    }
+}
 
-    tracing_subscriber::fmt()
-        .with_max_level(log_level)
+#[tokio::main]
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
+
+    // Initialize logging
+    let color = output::color_enabled(cli.no_color) && !cli.json;
+    let logger = tracing_subscriber::fmt()
+        .with_max_level(log_level(cli.verbose, cli.quiet))
         .with_target(false)
-        .init();
+        .with_ansi(color);
+    if color {
+        logger.init();
+    } else if cli.json {
+        // Keep stdout for the JSON document
+        logger
+            .with_writer(|| output::PlainWriter(std::io::stderr()))
+            .init();
+    } else {
+        logger
+            .with_writer(|| output::PlainWriter(std::io::stdout()))
+            .init();
+    }
+
+    match run(cli).await {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("Error: {:?}", e);
+            if e.downcast_ref::<ConfigError>().is_some() {
+                ExitCode::from(EXIT_CONFIG_ERROR)
+            } else {
+                ExitCode::from(EXIT_RUNTIME_ERROR)
+            }
+        }
+    }
+}
+
+async fn run(cli: Cli) -> Result<ExitCode> {
+    let out = Output { json: cli.json };
 
     match cli.command {
         Commands::Start { server, client } => {
@@ -131,7 +512,12 @@ async fn main() -> Result<()> {
             daemon.run().await?;
         }
 
-        Commands::Sync { server, interval } => {
+        Commands::Sync {
+            server,
+            interval,
+            pull_history,
+            long_poll,
+        } => {
             let config = Config::load()?;
 
             let server_url = server.unwrap_or_else(|| {
@@ -140,8 +526,53 @@ async fn main() -> Result<()> {
 
             let poll_interval = interval.unwrap_or(200);
 
-            let mut sync_client = http_sync::HttpSyncClient::new(server_url, poll_interval);
-            sync_client.run().await?;
+            let mut sync_client = http_sync::HttpSyncClient::new(server_url, poll_interval)
+                .with_direction(config.sync.direction)
+                .with_clipboard_preference(config.clipboard.prefer)
+                .with_xclip_targets(config.clipboard.xclip_targets.clone())
+                .with_transform(config.sync.transform.clone())
+                .with_replace_rules(ReplaceRules::compile(&config.sync.replace_rules)?)
+                .with_large_apply(LargeApplyGuard::from_config(&config.sync))
+                .with_basic_auth(config.client.http_basic_auth.clone())
+                .with_source_name(config.source_name())
+                .with_capture_sensitive(config.storage.capture_sensitive())
+                .with_log_preview_chars(config.sync.log_preview_chars);
+            // --pull-history implies local persistence
+            if config.sync.persist_http_history || pull_history {
+                let storage = ClipboardStorage::from_config(&config).await?;
+                sync_client = sync_client.with_storage(storage);
+            }
+            if pull_history {
+                sync_client = sync_client.with_history_import();
+            }
+            if long_poll {
+                sync_client = sync_client.with_long_poll();
+            }
+            let pause = PauseFlag::default();
+            let _pause_watcher = pause.watch(Config::pause_flag_path()?);
+            sync_client.with_pause(pause).run().await?;
+        }
+
+        Commands::ServerInfo { server } => {
+            use anyhow::Context;
+
+            let config = Config::load()?;
+            let server_url = server.unwrap_or_else(|| {
+                format!("http://{}:{}", config.client.server_host, config.client.server_port)
+            });
+
+            let health = http_sync::HttpSyncClient::new(server_url.clone(), config.sync.interval_ms)
+                .with_basic_auth(config.client.http_basic_auth.clone())
+                .health_check()
+                .await
+                .with_context(|| format!("Server at {} is unreachable", server_url))?;
+
+            out.emit(&health, |health| {
+                println!("Server: {}", server_url);
+                println!("Status: {}", health.status);
+                println!("Items: {}", health.items_count);
+                println!("Uptime: {}", format_uptime(health.uptime_seconds));
+            })?;
         }
 
         Commands::History {
@@ -149,81 +580,108 @@ async fn main() -> Result<()> {
             offset,
             source,
             type_filter,
+            order,
+            grep,
+            include_sensitive,
+            follow,
         } => {
             let config = Config::load()?;
-            let storage = ClipboardStorage::new(
-                config.get_database_path(),
-                config.storage.max_history,
-            )
-            .await?;
+            let storage = ClipboardStorage::from_config(&config).await?;
+            // Taken before the backlog is read, so nothing stored in between is missed
+            let cursor = if follow { Some(storage.latest_cursor().await?) } else { None };
 
             let content_type = type_filter
-                .and_then(|t| storage::models::ClipboardContentType::from_str(&t));
+                .map(|t| storage::models::ClipboardContentType::from(t.as_str()));
 
-            let query = ClipboardSearchQuery {
+            let mut query = ClipboardSearchQuery {
                 content_type,
                 source,
                 search_text: None,
+                match_mode: MatchMode::Substring,
+                order,
+                include_sensitive,
                 limit,
                 offset,
+                after: None,
             };
 
-            let entries = storage.search(&query).await?;
+            let entries = match &grep {
+                Some(pattern) => {
+                    // LIKE narrows the candidates; the decoded match decides.
+                    // Paginate after matching, over the whole (bounded) history.
+                    query.search_text = Some(grep_prefilter(pattern).to_string());
+                    query.limit = config.storage.max_history;
+                    query.offset = 0;
 
-            if entries.is_empty() {
-                println!("No clipboard history found");
-            } else {
+                    storage
+                        .search(&query)
+                        .await?
+                        .into_iter()
+                        .filter(|entry| entry.grep_matches(pattern))
+                        .skip(offset)
+                        .take(if limit == 0 { usize::MAX } else { limit })
+                        .collect()
+                }
+                None => storage.search(&query).await?,
+            };
+
+            if let Some(cursor) = cursor {
+                // Oldest first, so the newest entry is always the last printed
+                out.note("Following clipboard history (Ctrl-C to stop)");
+                for entry in entries.iter().rev() {
+                    out.emit_line(entry, print_history_entry)?;
+                }
+                follow_history(&storage, query, grep.as_deref(), cursor, out).await?;
+                return Ok(ExitCode::SUCCESS);
+            }
+
+            out.emit(&entries, |entries| {
+                if entries.is_empty() {
+                    println!("No clipboard history found");
+                    return;
+                }
                 println!("\nClipboard History ({} entries):\n", entries.len());
                 for entry in entries {
-                    println!("ID: {}", entry.id.unwrap_or(0));
-                    println!("Type: {}", entry.content_type.as_str());
-                    println!("Source: {}", entry.source);
-                    println!("Time: {}", entry.timestamp.format("%Y-%m-%d %H:%M:%S"));
-                    println!("Checksum: {}", entry.checksum);
-
-                    // Show preview of content
-                    let preview = if entry.content.len() > 100 {
-                        format!("{}...", &entry.content[..100])
-                    } else {
-                        entry.content.clone()
-                    };
-
-                    match entry.content_type {
-                        storage::models::ClipboardContentType::Text => {
-                            println!("Content: {}", preview);
-                        }
-                        storage::models::ClipboardContentType::Image => {
-                            println!("Content: [Image data, {} bytes]", entry.content.len());
-                        }
-                        _ => {
-                            println!("Content: {}", preview);
-                        }
-                    }
-
-                    println!("---");
+                    print_history_entry(entry);
                 }
+            })?;
+            if entries.is_empty() {
+                return Ok(ExitCode::from(EXIT_NOT_FOUND));
             }
         }
 
-        Commands::Search { query, limit } => {
+        Commands::Search {
+            query,
+            exact,
+            prefix,
+            limit,
+            order,
+            include_sensitive,
+        } => {
             let config = Config::load()?;
-            let storage = ClipboardStorage::new(
-                config.get_database_path(),
-                config.storage.max_history,
-            )
-            .await?;
+            let storage = ClipboardStorage::from_config(&config).await?;
 
+            let match_mode = match (exact, prefix) {
+                (true, _) => MatchMode::Exact,
+                (_, true) => MatchMode::Prefix,
+                _ => MatchMode::Substring,
+            };
             let search_query = ClipboardSearchQuery {
                 search_text: Some(query.clone()),
+                match_mode,
+                order,
+                include_sensitive,
                 limit,
                 ..Default::default()
             };
 
             let entries = storage.search(&search_query).await?;
 
-            if entries.is_empty() {
-                println!("No results found for '{}'", query);
-            } else {
+            out.emit(&entries, |entries| {
+                if entries.is_empty() {
+                    println!("No results found for '{}'", query);
+                    return;
+                }
                 println!("\nSearch Results for '{}' ({} entries):\n", query, entries.len());
                 for entry in entries {
                     println!("ID: {}", entry.id.unwrap_or(0));
@@ -231,73 +689,377 @@ async fn main() -> Result<()> {
                     println!("Source: {}", entry.source);
                     println!("Time: {}", entry.timestamp.format("%Y-%m-%d %H:%M:%S"));
 
-                    let preview = if entry.content.len() > 100 {
-                        format!("{}...", &entry.content[..100])
-                    } else {
-                        entry.content.clone()
-                    };
+                    let preview = output::preview(&entry.content, PREVIEW_CHARS);
                     println!("Content: {}", preview);
                     println!("---");
                 }
+            })?;
+            if entries.is_empty() {
+                return Ok(ExitCode::from(EXIT_NOT_FOUND));
             }
         }
 
         Commands::Clear { yes } => {
             if !yes {
+                // Never block a script on a prompt nobody can answer
+                if !std::io::stdin().is_terminal() {
+                    anyhow::bail!("refusing to clear history without confirmation; pass --yes");
+                }
+
                 println!("This will clear all clipboard history. Are you sure? (y/N)");
                 let mut input = String::new();
                 std::io::stdin().read_line(&mut input)?;
                 if !input.trim().eq_ignore_ascii_case("y") {
-                    println!("Cancelled");
-                    return Ok(());
+                    out.message("Cancelled")?;
+                    return Ok(ExitCode::from(EXIT_RUNTIME_ERROR));
                 }
             }
 
             let config = Config::load()?;
-            let storage = ClipboardStorage::new(
-                config.get_database_path(),
-                config.storage.max_history,
-            )
-            .await?;
+            let storage = ClipboardStorage::from_config(&config).await?;
 
             storage.clear().await?;
-            println!("Clipboard history cleared");
+            out.message("Clipboard history cleared")?;
+        }
+
+        Commands::Recent { n, copy } => {
+            let config = Config::load()?;
+            let storage = ClipboardStorage::from_config(&config).await?;
+
+            let query = ClipboardSearchQuery {
+                limit: 1,
+                offset: (n - 1) as usize,
+                ..Default::default()
+            };
+            let Some(entry) = storage.search(&query).await?.into_iter().next() else {
+                let count = storage.get_count().await?;
+                out.message(&format!("No entry {}: history has {} entries", n, count))?;
+                return Ok(ExitCode::from(EXIT_NOT_FOUND));
+            };
+
+            if copy {
+                let content = clipboard::ClipboardContent::from_base64(
+                    entry.content_type.as_str(),
+                    &entry.content,
+                    entry.metadata.as_deref(),
+                    config.storage.max_content_size_bytes(),
+                )?;
+                clipboard::ClipboardManager::new()?.set_content(&content)?;
+                if let Some(id) = entry.id {
+                    storage.record_apply(id).await?;
+                }
+                out.message(&format!(
+                    "Copied entry {} ({}) to the clipboard",
+                    entry.id.unwrap_or(0),
+                    entry.content_type.as_str()
+                ))?;
+            } else {
+                out.emit(&entry, |entry| match entry.content_type {
+                    storage::models::ClipboardContentType::Text
+                    | storage::models::ClipboardContentType::Html => {
+                        println!("{}", entry.content);
+                    }
+                    _ => {
+                        println!(
+                            "[{} data, {} bytes] (use --copy to put it on the clipboard)",
+                            entry.content_type.as_str(),
+                            entry.size_bytes
+                        );
+                    }
+                })?;
+            }
+        }
+
+        Commands::Diff => {
+            let config = Config::load()?;
+            let storage = ClipboardStorage::from_config(&config).await?;
+            let mut clipboard = clipboard::ClipboardManager::new()?
+                .with_preference(config.clipboard.prefer)
+                .with_xclip_targets(config.clipboard.xclip_targets.clone());
+
+            // Compare what the monitor would have stored, after its rewrites
+            let replace = ReplaceRules::compile(&config.sync.replace_rules)?;
+            let current = clipboard
+                .get_content()?
+                .map(|content| replace.on_send(content.transformed(&config.sync.transform)));
+            let sensitive = clipboard.content_is_sensitive();
+            let latest = storage.get_latest().await?;
+
+            let in_sync = match (&current, &latest) {
+                (Some(content), Some(entry)) => content.matches_checksum(&entry.checksum),
+                (None, None) => true,
+                _ => false,
+            };
+            let report = DiffReport {
+                in_sync,
+                clipboard: current.as_ref().map(|content| DiffSide {
+                    id: None,
+                    content_type: content.content_type_str().to_string(),
+                    checksum: content.checksum(),
+                }),
+                stored: latest.as_ref().map(|entry| DiffSide {
+                    id: entry.id,
+                    content_type: entry.content_type.as_str().to_string(),
+                    checksum: entry.checksum.clone(),
+                }),
+            };
+
+            out.emit(&report, |report| {
+                if report.in_sync {
+                    match &report.stored {
+                        Some(stored) => println!("In sync: the clipboard matches {}", stored.describe()),
+                        None => println!("In sync: the clipboard and history are both empty"),
+                    }
+                    return;
+                }
+
+                println!("Out of sync with the last stored entry");
+                match &report.clipboard {
+                    Some(side) => println!("  Clipboard: {}", side.describe()),
+                    None => println!("  Clipboard: empty"),
+                }
+                match &report.stored {
+                    Some(side) => println!("  Stored:    {}", side.describe()),
+                    None => println!("  Stored:    nothing (history is empty)"),
+                }
+
+                // Secrets are compared but never shown
+                if let (Some(clipboard::ClipboardContent::Text(text)), Some(entry)) =
+                    (&current, &latest)
+                {
+                    if entry.content_type.as_str() == "text" && !sensitive && !entry.is_sensitive() {
+                        let (stored, text) = diff_excerpts(&entry.content, text);
+                        println!("  - stored:    {}", stored);
+                        println!("  + clipboard: {}", text);
+                    }
+                }
+            })?;
+
+            if !in_sync {
+                return Ok(ExitCode::from(EXIT_OUT_OF_SYNC));
+            }
+        }
+
+        Commands::Replay { id } => {
+            let config = Config::load()?;
+
+            match client::request_replay(&config, id).await? {
+                client::ReplayOutcome::Replayed => {
+                    out.message(&format!("Replayed entry {} to connected clients", id))?;
+                }
+                client::ReplayOutcome::NotFound(message) => {
+                    out.message(&message)?;
+                    return Ok(ExitCode::from(EXIT_NOT_FOUND));
+                }
+            }
+        }
+
+        #[cfg(feature = "discovery")]
+        Commands::Discover {
+            timeout_secs,
+            write_config,
+        } => {
+            let servers = tokio::task::spawn_blocking(move || {
+                discovery::browse(std::time::Duration::from_secs(timeout_secs))
+            })
+            .await??;
+
+            out.emit(&servers, |servers| {
+                if servers.is_empty() {
+                    println!("No clippy servers found");
+                }
+                for server in servers {
+                    println!("{}:{} ({}, {})", server.host, server.port, server.protocol, server.name);
+                }
+            })?;
+            if servers.is_empty() {
+                return Ok(ExitCode::from(EXIT_NOT_FOUND));
+            }
+
+            if write_config {
+                let Some(server) = servers.iter().find(|s| s.protocol == "tcp") else {
+                    out.note("No TCP sync server found; config unchanged");
+                    return Ok(ExitCode::from(EXIT_NOT_FOUND));
+                };
+                let mut config = Config::load()?;
+                config.client.server_host = server.host.clone();
+                config.client.server_port = server.port;
+                config.save()?;
+                out.note(&format!("Client config now points at {}:{}", server.host, server.port));
+                if !config.client.servers.is_empty() {
+                    out.note("Note: client.servers is set and takes precedence over server_host");
+                }
+            }
+        }
+
+        Commands::Pause => {
+            let path = Config::pause_flag_path()?;
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            std::fs::write(&path, "")?;
+            out.message("Sync paused; clipboard changes stay local until `clippy resume`")?;
+        }
+
+        Commands::Resume => {
+            let path = Config::pause_flag_path()?;
+            match std::fs::remove_file(&path) {
+                Ok(()) => out.message("Sync resumed")?,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                    out.message("Sync is not paused")?
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        Commands::ExportImages { output_dir } => {
+            use anyhow::Context;
+            use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+            use futures::TryStreamExt;
+
+            let config = Config::load()?;
+            let storage = ClipboardStorage::from_config(&config).await?;
+
+            std::fs::create_dir_all(&output_dir)
+                .with_context(|| format!("Failed to create {}", output_dir.display()))?;
+
+            // Streamed so a long history is never held in memory at once
+            let mut entries = std::pin::pin!(storage.stream_all());
+            let mut written = 0;
+            let mut written_bytes = 0;
+            while let Some(entry) = entries.try_next().await? {
+                if !matches!(entry.content_type, storage::models::ClipboardContentType::Image)
+                    || entry.is_sensitive()
+                {
+                    continue;
+                }
+                let id = entry.id.unwrap_or(0);
+                let png = match BASE64.decode(&entry.content) {
+                    Ok(png) => png,
+                    Err(e) => {
+                        eprintln!("Skipping entry {}: invalid image data ({})", id, e);
+                        continue;
+                    }
+                };
+
+                let path = output_dir.join(format!("{}.png", id));
+                std::fs::write(&path, png)
+                    .with_context(|| format!("Failed to write {}", path.display()))?;
+                // Keep the copy time visible in file browsers
+                std::fs::File::options()
+                    .write(true)
+                    .open(&path)?
+                    .set_modified(entry.timestamp.into())?;
+                written += 1;
+                written_bytes += entry.size_bytes;
+            }
+
+            let summary = serde_json::json!({
+                "written": written,
+                "bytes": written_bytes,
+                "output_dir": output_dir,
+            });
+            out.emit(&summary, |_| {
+                println!(
+                    "Wrote {} image(s) ({} bytes) to {}",
+                    written,
+                    written_bytes,
+                    output_dir.display()
+                )
+            })?;
+            if written == 0 {
+                return Ok(ExitCode::from(EXIT_NOT_FOUND));
+            }
         }
 
         Commands::Stats => {
             let config = Config::load()?;
-            let storage = ClipboardStorage::new(
-                config.get_database_path(),
-                config.storage.max_history,
-            )
-            .await?;
-
-            let count = storage.get_count().await?;
-            println!("\nClipboard Statistics:");
-            println!("Total entries: {}", count);
-            println!("Max history: {}", config.storage.max_history);
-            println!("Database path: {}", config.get_database_path().display());
+            let storage = ClipboardStorage::from_config(&config).await?;
+
+            let report = StatsReport {
+                stats: storage.stats().await?,
+                max_history: config.storage.max_history,
+                database_path: config.get_database_path(),
+            };
+            out.emit(&report, |report| {
+                println!("\nClipboard Statistics:");
+                println!("Total entries: {}", report.stats.total);
+                println!("Total size: {} bytes", report.stats.total_bytes);
+                println!("Max history: {}", report.max_history);
+                println!("Database path: {}", report.database_path.display());
+            })?;
         }
 
         Commands::Config { show, init } => {
             if show {
                 let config = Config::load()?;
-                println!("\nCurrent Configuration:");
-                println!("{}", toml::to_string_pretty(&config)?);
+                let text = toml::to_string_pretty(&config)?;
+                out.emit(&config, |_| {
+                    println!("\nCurrent Configuration:");
+                    println!("{}", text);
+                })?;
             } else if init {
                 let config = Config::default();
                 config.save()?;
-                println!(
+                out.note(&format!(
                     "Configuration initialized at: {}",
                     Config::config_path()?.display()
-                );
-                println!("\nDefault configuration:");
-                println!("{}", toml::to_string_pretty(&config)?);
+                ));
+                let text = toml::to_string_pretty(&config)?;
+                out.emit(&config, |_| {
+                    println!("\nDefault configuration:");
+                    println!("{}", text);
+                })?;
             } else {
-                println!("Use --show to display current config or --init to create default config");
+                out.message("Use --show to display current config or --init to create default config")?;
             }
         }
+
+        Commands::Version => {
+            out.emit(&VersionReport::current(), |report| {
+                println!("clippy {} (commit {})", report.version, report.commit);
+                println!("Target: {}", report.target);
+                println!("Protocol version: {}", report.protocol_version);
+            })?;
+        }
     }
 
-    Ok(())
+    Ok(ExitCode::SUCCESS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_log_level_selection() {
+        assert_eq!(log_level(false, false), Level::INFO);
+        assert_eq!(log_level(true, false), Level::DEBUG);
+        assert_eq!(log_level(false, true), Level::WARN);
+    }
+
+    #[test]
+    fn test_diff_excerpts_start_near_the_first_difference() {
+        let (old, new) = diff_excerpts("hello world, old ending", "hello world, new ending");
+        assert_eq!(old, "...\"lo world, old ending\"");
+        assert_eq!(new, "...\"lo world, new ending\"");
+
+        let (old, new) = diff_excerpts("héllo", &"héllo!".repeat(10));
+        assert_eq!(old, "\"héllo\"");
+        let shown: String = "héllo!".repeat(10).chars().take(DIFF_EXCERPT_CHARS).collect();
+        assert_eq!(new, format!("{:?}...", shown));
+    }
+
+    #[test]
+    fn test_quiet_conflicts_with_verbose() {
+        assert!(Cli::try_parse_from(["clippy", "--quiet", "stats"]).is_ok());
+        assert!(Cli::try_parse_from(["clippy", "-q", "-v", "stats"]).is_err());
+    }
+
+    #[test]
+    fn test_format_uptime() {
+        assert_eq!(format_uptime(42), "42s");
+        assert_eq!(format_uptime(3 * 3_600 + 12 * 60 + 5), "3h 12m");
+        assert_eq!(format_uptime(2 * 86_400 + 60), "2d 0h");
+    }
 }
@@ -0,0 +1,102 @@
+use std::io::{self, IsTerminal, Write};
+
+/// Whether terminal output should use ANSI colors and emoji. Disabled by
+/// `--no-color`, a non-empty `NO_COLOR` (https://no-color.org) or when
+/// stdout is not a terminal.
+pub fn color_enabled(no_color_flag: bool) -> bool {
+    let no_color_env = std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
+    !no_color_flag && !no_color_env && io::stdout().is_terminal()
+}
+
+fn is_emoji(c: char) -> bool {
+    matches!(c,
+        '\u{2600}'..='\u{27BF}'     // Misc symbols and dingbats (✓ ✅ ❌ ⚠ ⚡)
+        | '\u{2B50}'..='\u{2B55}'
+        | '\u{1F000}'..='\u{1FAFF}' // Pictographs (🚀 📋 🔄 ...)
+        | '\u{FE0F}'                // Emoji presentation selector
+        | '\u{200D}'                // Zero-width joiner
+    )
+}
+
+/// The first `max_chars` characters of `s`, never splitting a character
+pub fn truncate_chars(s: &str, max_chars: usize) -> &str {
+    match s.char_indices().nth(max_chars) {
+        Some((end, _)) => &s[..end],
+        None => s,
+    }
+}
+
+/// At most `max_chars` characters of `s` followed by "..." if it was cut.
+/// Only the shown prefix is copied, so huge single-line content is cheap.
+pub fn preview(s: &str, max_chars: usize) -> String {
+    let shown = truncate_chars(s, max_chars);
+    if shown.len() < s.len() {
+        format!("{}...", shown)
+    } else {
+        shown.to_string()
+    }
+}
+
+/// Remove emoji along with the spacing that followed them
+pub fn strip_emoji(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut after_emoji = false;
+
+    for c in s.chars() {
+        if is_emoji(c) {
+            after_emoji = true;
+        } else if after_emoji && c == ' ' {
+            continue;
+        } else {
+            after_emoji = false;
+            out.push(c);
+        }
+    }
+
+    out
+}
+
+/// Writer that strips emoji from log lines before passing them on; invalid
+/// UTF-8 is written lossily so plain output stays plain
+pub struct PlainWriter<W: Write>(pub W);
+
+impl<W: Write> Write for PlainWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let text = String::from_utf8_lossy(buf);
+        self.0.write_all(strip_emoji(&text).as_bytes())?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_strip_emoji_removes_prefixes() {
+        assert_eq!(strip_emoji("🚀 Starting sync"), "Starting sync");
+        assert_eq!(strip_emoji("⚠️  Failed to poll"), "Failed to poll");
+        assert_eq!(strip_emoji("x ✓ done"), "x done");
+        assert_eq!(strip_emoji("NixOS → macOS"), "NixOS → macOS");
+    }
+
+    #[test]
+    fn test_plain_writer_replaces_invalid_utf8() {
+        let mut writer = PlainWriter(Vec::new());
+        writer.write_all(b"\xF0\x9F\x9A\x80 bad \xFF byte\n").unwrap();
+        assert_eq!(String::from_utf8(writer.0).unwrap(), "bad \u{FFFD} byte\n");
+    }
+
+    #[test]
+    fn test_preview_of_huge_single_line() {
+        // 50MB of minified-looking content with a multi-byte char at the cut
+        let huge = format!("{}é{}", "a".repeat(99), "x".repeat(50 * 1024 * 1024));
+        let shown = preview(&huge, 100);
+        assert_eq!(shown, format!("{}é...", "a".repeat(99)));
+        assert_eq!(preview("short", 100), "short");
+    }
+}
@@ -1,39 +1,171 @@
+use crate::clipboard::{ClipboardContent, ClipboardManager};
 use crate::config::Config;
-use crate::storage::{models::ClipboardEntry, ClipboardStorage};
-use crate::sync::protocol::Message;
+use crate::event_log::{Direction, SyncEvent, SyncEventLog};
+use crate::storage::{models::ClipboardEntry, Storage};
+use crate::sync::{
+    large_apply::LargeApplyGuard,
+    pause::PauseFlag,
+    protocol::{Compression, Message, WireFormat},
+    replace::ReplaceRules,
+    transport::Stream,
+    RecentlyApplied,
+};
 use anyhow::Result;
-use std::sync::Arc;
+use std::sync::{Arc, OnceLock};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::{TcpListener, TcpStream};
+use tokio::net::TcpListener;
 use tokio::sync::broadcast;
+use tokio::time::{Duration, Instant};
 use tracing::{error, info, warn};
 
+/// What a connection's client has done so far
+#[derive(Debug, Clone, Copy, Default)]
+struct ConnectionState {
+    authenticated: bool,
+    /// Set by the client's `Hello`; updates are sent plain until then
+    compression: Compression,
+    /// Set by the client's `Hello`; frames are JSON until then
+    format: WireFormat,
+}
+
+/// A request gave up waiting on storage; the connection is closed after
+/// the client is told, dropping the stalled call with it
+#[derive(Debug, thiserror::Error)]
+#[error("{op} timed out after {limit:?}")]
+struct RequestTimeout {
+    op: &'static str,
+    limit: Duration,
+}
+
+/// Run a storage call, bounded by `server.request_timeout_ms`
+async fn with_timeout<T>(
+    config: &Config,
+    op: &'static str,
+    call: impl std::future::Future<Output = Result<T>>,
+) -> Result<Result<T>, RequestTimeout> {
+    if config.server.request_timeout_ms == 0 {
+        return Ok(call.await);
+    }
+    let limit = Duration::from_millis(config.server.request_timeout_ms);
+    tokio::time::timeout(limit, call)
+        .await
+        .map_err(|_| RequestTimeout { op, limit })
+}
+
 pub struct ClipboardServer {
     config: Arc<Config>,
-    storage: Arc<ClipboardStorage>,
-    clipboard_tx: broadcast::Sender<ClipboardEntry>,
+    storage: Arc<dyn Storage>,
+    clipboard_tx: broadcast::Sender<Arc<BroadcastUpdate>>,
+    local: LocalClipboard,
+}
+
+/// A stored entry replayed to every connected client. Connections share
+/// one, so an update is compressed at most once however many receive it.
+pub struct BroadcastUpdate {
+    pub entry: ClipboardEntry,
+    plain: Message,
+    compressed: OnceLock<Message>,
+}
+
+impl BroadcastUpdate {
+    pub fn new(entry: ClipboardEntry) -> Self {
+        let plain = Message::clipboard_update(
+            entry.content_type.as_str().to_string(),
+            entry.content.clone(),
+            entry.source.clone(),
+            entry.checksum.clone(),
+            entry.metadata.clone(),
+        );
+        Self {
+            entry,
+            plain,
+            compressed: OnceLock::new(),
+        }
+    }
+
+    /// The update for a connection that negotiated `compression`. One that
+    /// fails to compress is sent plain rather than dropping the connection.
+    pub fn message(&self, compression: Compression) -> &Message {
+        match compression {
+            Compression::None => &self.plain,
+            Compression::Zstd => self.compressed.get_or_init(|| {
+                self.plain.clone().encoded_for(compression).unwrap_or_else(|e| {
+                    warn!("Sending clipboard update uncompressed: {}", e);
+                    self.plain.clone()
+                })
+            }),
+        }
+    }
+}
+
+/// Writes received updates to this machine's clipboard
+#[derive(Clone, Default)]
+struct LocalClipboard {
+    applied: RecentlyApplied,
+    replace: ReplaceRules,
+    large: LargeApplyGuard,
+    pause: PauseFlag,
+}
+
+impl LocalClipboard {
+    /// Write an update to the clipboard, returning whether it changed anything
+    fn apply(&self, content: ClipboardContent) -> Result<bool> {
+        let content = self.replace.on_receive(content);
+        let mut clipboard = ClipboardManager::new()?;
+        // Nothing written means the monitor has no echo to suppress
+        let written = clipboard.set_content(&content)?;
+        if written {
+            self.applied.record_applied(&mut clipboard, &content);
+        }
+
+        Ok(written)
+    }
 }
 
 impl ClipboardServer {
-    pub async fn new(config: Config, storage: ClipboardStorage) -> Result<Self> {
+    pub async fn new(config: Config, storage: Arc<dyn Storage>) -> Result<Self> {
         let (clipboard_tx, _) = broadcast::channel(100);
+        let local = LocalClipboard {
+            large: LargeApplyGuard::from_config(&config.sync),
+            ..Default::default()
+        };
 
         Ok(Self {
             config: Arc::new(config),
-            storage: Arc::new(storage),
+            storage,
             clipboard_tx,
+            local,
         })
     }
 
-    pub fn get_clipboard_receiver(&self) -> broadcast::Receiver<ClipboardEntry> {
-        self.clipboard_tx.subscribe()
+    /// Share the record of applied updates with the local clipboard monitor,
+    /// so content received from a client isn't sent back out as a local copy
+    pub fn with_recently_applied(mut self, applied: RecentlyApplied) -> Self {
+        self.local.applied = applied;
+        self
+    }
+
+    /// Rewrite received text with `sync.replace_rules` before applying it
+    pub fn with_replace_rules(mut self, replace: ReplaceRules) -> Self {
+        self.local.replace = replace;
+        self
+    }
+
+    /// Store but don't apply received updates while `pause` is set
+    pub fn with_pause(mut self, pause: PauseFlag) -> Self {
+        self.local.pause = pause;
+        self
     }
 
-    pub async fn broadcast_clipboard_update(&self, entry: ClipboardEntry) {
-        let _ = self.clipboard_tx.send(entry);
+    pub fn get_clipboard_receiver(&self) -> broadcast::Receiver<Arc<BroadcastUpdate>> {
+        self.clipboard_tx.subscribe()
     }
 
     pub async fn run(&self) -> Result<()> {
+        if let Some(path) = &self.config.server.unix_socket {
+            return self.run_unix(path).await;
+        }
+
         let addr = format!(
             "{}:{}",
             self.config.server.host, self.config.server.port
@@ -42,22 +174,43 @@ impl ClipboardServer {
         let listener = TcpListener::bind(&addr).await?;
         info!("Clipboard server listening on {}", addr);
 
+        // Registered for as long as the server runs
+        #[cfg(feature = "discovery")]
+        let _advertisement = if self.config.server.advertise {
+            match crate::discovery::advertise(self.config.server.port, "tcp") {
+                Ok(advertisement) => {
+                    info!("Advertising {} over mDNS", crate::discovery::SERVICE_TYPE);
+                    Some(advertisement)
+                }
+                Err(e) => {
+                    warn!("mDNS advertisement failed: {:#}", e);
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
         loop {
             match listener.accept().await {
-                Ok((socket, addr)) => {
-                    info!("New connection from: {}", addr);
-                    let config = Arc::clone(&self.config);
-                    let storage = Arc::clone(&self.storage);
-                    let clipboard_rx = self.clipboard_tx.subscribe();
-
-                    tokio::spawn(async move {
-                        if let Err(e) =
-                            Self::handle_connection(socket, config, storage, clipboard_rx).await
-                        {
-                            error!("Error handling connection from {}: {}", addr, e);
-                        }
-                    });
+                Ok((socket, addr)) => self.spawn_connection(socket, addr.to_string()),
+                Err(e) => {
+                    error!("Error accepting connection: {}", e);
                 }
+            }
+        }
+    }
+
+    /// Serve on a Unix domain socket; the socket file is removed when this
+    /// returns or is cancelled
+    #[cfg(unix)]
+    async fn run_unix(&self, path: &std::path::Path) -> Result<()> {
+        let listener = crate::sync::transport::UnixSocketListener::bind(path)?;
+        info!("Clipboard server listening on {}", path.display());
+
+        loop {
+            match listener.accept().await {
+                Ok(socket) => self.spawn_connection(socket, path.display().to_string()),
                 Err(e) => {
                     error!("Error accepting connection: {}", e);
                 }
@@ -65,16 +218,49 @@ impl ClipboardServer {
         }
     }
 
+    #[cfg(not(unix))]
+    async fn run_unix(&self, _path: &std::path::Path) -> Result<()> {
+        anyhow::bail!("server.unix_socket is not supported on this platform")
+    }
+
+    fn spawn_connection(&self, socket: impl Stream + 'static, peer: String) {
+        info!("New connection from: {}", peer);
+        let config = Arc::clone(&self.config);
+        let storage = Arc::clone(&self.storage);
+        let clipboard_tx = self.clipboard_tx.clone();
+        let local = self.local.clone();
+
+        tokio::spawn(async move {
+            if let Err(e) =
+                Self::handle_connection(socket, &peer, config, storage, clipboard_tx, local).await
+            {
+                error!("Error handling connection from {}: {}", peer, e);
+            }
+        });
+    }
+
     async fn handle_connection(
-        mut socket: TcpStream,
+        mut socket: impl Stream,
+        peer: &str,
         config: Arc<Config>,
-        storage: Arc<ClipboardStorage>,
-        mut clipboard_rx: broadcast::Receiver<ClipboardEntry>,
+        storage: Arc<dyn Storage>,
+        clipboard_tx: broadcast::Sender<Arc<BroadcastUpdate>>,
+        local: LocalClipboard,
     ) -> Result<()> {
-        let mut authenticated = config.server.auth_token.is_none();
+        let mut clipboard_rx = clipboard_tx.subscribe();
+        let mut conn = ConnectionState {
+            authenticated: config.server.auth_token.is_none(),
+            ..Default::default()
+        };
         let mut buffer = vec![0u8; 8192];
         let mut pending_data = Vec::new();
 
+        // Clients ping every heartbeat interval; several missed in a row means
+        // the peer is gone even if TCP hasn't noticed yet
+        let idle_timeout = Duration::from_millis(config.sync.heartbeat_interval_ms * 3);
+        let idle = tokio::time::sleep(idle_timeout);
+        tokio::pin!(idle);
+
         loop {
             tokio::select! {
                 // Read from socket
@@ -85,6 +271,7 @@ impl ClipboardServer {
                             break;
                         }
                         Ok(n) => {
+                            idle.as_mut().reset(Instant::now() + idle_timeout);
                             pending_data.extend_from_slice(&buffer[..n]);
 
                             // Process complete messages
@@ -92,18 +279,22 @@ impl ClipboardServer {
                                 match Message::from_bytes(&pending_data) {
                                     Ok((message, size)) => {
                                         pending_data.drain(..size);
+                                        let request_id = message.request_id();
 
                                         match Self::handle_message(
                                             message,
                                             &mut socket,
                                             &config,
-                                            &storage,
-                                            &mut authenticated,
+                                            storage.as_ref(),
+                                            &clipboard_tx,
+                                            &local,
+                                            &mut conn,
                                         )
                                         .await
                                         {
                                             Ok(should_continue) => {
                                                 if !should_continue {
+                                                    info!("Client {} disconnected gracefully", peer);
                                                     return Ok(());
                                                 }
                                             }
@@ -111,8 +302,13 @@ impl ClipboardServer {
                                                 error!("Error handling message: {}", e);
                                                 let error_msg = Message::Error {
                                                     message: e.to_string(),
+                                                    request_id,
                                                 };
-                                                let _ = socket.write_all(&error_msg.to_bytes()?).await;
+                                                let _ = socket.write_all(&error_msg.to_bytes_as(conn.format)?).await;
+                                                if e.is::<RequestTimeout>() {
+                                                    warn!("Closing connection to {} after a storage timeout", peer);
+                                                    return Ok(());
+                                                }
                                             }
                                         }
                                     }
@@ -130,23 +326,21 @@ impl ClipboardServer {
                     }
                 }
 
+                _ = &mut idle => {
+                    warn!("No message from client in {:?}, closing dead connection", idle_timeout);
+                    break;
+                }
+
                 // Broadcast clipboard updates to connected clients
                 result = clipboard_rx.recv() => {
-                    if !authenticated {
+                    if !conn.authenticated {
                         continue;
                     }
 
                     match result {
-                        Ok(entry) => {
-                            let msg = Message::ClipboardUpdate {
-                                content_type: entry.content_type.as_str().to_string(),
-                                content: entry.content.clone(),
-                                timestamp: entry.timestamp,
-                                source: entry.source.clone(),
-                                checksum: entry.checksum.clone(),
-                            };
-
-                            if let Err(e) = socket.write_all(&msg.to_bytes()?).await {
+                        Ok(update) => {
+                            let msg = update.message(conn.compression);
+                            if let Err(e) = socket.write_all(&msg.to_bytes_as(conn.format)?).await {
                                 error!("Error sending clipboard update: {}", e);
                                 break;
                             }
@@ -162,13 +356,30 @@ impl ClipboardServer {
         Ok(())
     }
 
+    /// Handle one message; `Ok(false)` means the client said goodbye
     async fn handle_message(
         message: Message,
-        socket: &mut TcpStream,
+        socket: &mut impl Stream,
         config: &Config,
-        storage: &ClipboardStorage,
-        authenticated: &mut bool,
+        storage: &dyn Storage,
+        clipboard_tx: &broadcast::Sender<Arc<BroadcastUpdate>>,
+        local: &LocalClipboard,
+        conn: &mut ConnectionState,
     ) -> Result<bool> {
+        // Only authenticated peers get a payload inflated on their behalf
+        if let Message::ClipboardUpdateCompressed { source, checksum, .. } = &message {
+            if !conn.authenticated {
+                if let Some(log) = SyncEventLog::from_config(&config.sync) {
+                    log.record(
+                        SyncEvent::new(Direction::Inbound, source, checksum, "skipped")
+                            .with_reason("not authenticated"),
+                    );
+                }
+                return Ok(true);
+            }
+        }
+        let message = message.decompressed(config.storage.max_encoded_size_bytes())?;
+
         match message {
             Message::Auth { token } => {
                 let success = if let Some(expected_token) = &config.server.auth_token {
@@ -177,7 +388,7 @@ impl ClipboardServer {
                     true
                 };
 
-                *authenticated = success;
+                conn.authenticated = success;
 
                 let response = Message::AuthResponse {
                     success,
@@ -188,22 +399,63 @@ impl ClipboardServer {
                     },
                 };
 
-                socket.write_all(&response.to_bytes()?).await?;
+                socket.write_all(&response.to_bytes_as(conn.format)?).await?;
+            }
+
+            Message::Hello { compression, formats } => {
+                conn.compression = Compression::negotiate(&compression);
+                conn.format = WireFormat::negotiate(&formats);
+                let chosen = conn.compression.name();
+                info!(
+                    "Client supports compression {:?}; using {}, with {} frames",
+                    compression,
+                    chosen.unwrap_or("none"),
+                    conn.format.name()
+                );
+                let response = Message::HelloAck {
+                    compression: chosen.map(String::from),
+                    format: Some(conn.format.name().to_string()),
+                };
+                socket.write_all(&response.to_bytes_as(conn.format)?).await?;
             }
 
             Message::Ping => {
                 let response = Message::Pong;
-                socket.write_all(&response.to_bytes()?).await?;
+                socket.write_all(&response.to_bytes_as(conn.format)?).await?;
             }
 
+            Message::Bye => return Ok(false),
+
             Message::ClipboardUpdate {
                 content_type,
                 content,
                 timestamp,
                 source,
                 checksum,
+                metadata,
             } => {
-                if !*authenticated {
+                let event_log = SyncEventLog::from_config(&config.sync);
+                let record = |action, reason: Option<String>| {
+                    if let Some(log) = &event_log {
+                        let mut event = SyncEvent::new(Direction::Inbound, &source, &checksum, action);
+                        event.reason = reason;
+                        log.record(event);
+                    }
+                };
+
+                if !conn.authenticated {
+                    record("skipped", Some("not authenticated".to_string()));
+                    return Ok(true);
+                }
+
+                if !config.server.accepts_source(&source) {
+                    warn!("Rejecting clipboard update from {}: source not in allowed_sources", source);
+                    record("skipped", Some("source not allowed".to_string()));
+                    let response = Message::Error {
+                        message: format!("source {} is not allowed on this server", source),
+                        request_id: None,
+                    };
+                    socket.write_all(&response.to_bytes_as(conn.format)?).await?;
                     return Ok(true);
                 }
 
@@ -215,51 +467,144 @@ impl ClipboardServer {
                     checksum
                 );
 
-                let content_type_enum = crate::storage::models::ClipboardContentType::from_str(
+                // Validate before storing; a malformed update is skipped, not fatal
+                let clipboard_content = match ClipboardContent::from_base64(
                     &content_type,
-                )
-                .unwrap_or(crate::storage::models::ClipboardContentType::Text);
+                    &content,
+                    metadata.as_deref(),
+                    config.storage.max_content_size_bytes(),
+                ) {
+                    Ok(clipboard_content) => clipboard_content,
+                    Err(e) => {
+                        warn!("Skipping malformed clipboard update {} from {}: {}", checksum, source, e);
+                        record("skipped", Some(format!("decode: {}", e)));
+                        let response = Message::ClipboardAck {
+                            checksum,
+                            success: false,
+                            request_id: None,
+                        };
+                        socket.write_all(&response.to_bytes_as(conn.format)?).await?;
+                        return Ok(true);
+                    }
+                };
 
+                // The checksum covers the decoded content; a mismatch means it
+                // was corrupted or altered in transit
+                if !clipboard_content.matches_checksum(&checksum) {
+                    warn!("Rejecting clipboard update from {}: checksum mismatch", source);
+                    record("skipped", Some("checksum mismatch".to_string()));
+                    let response = Message::Error {
+                        message: format!("checksum mismatch for update from {}", source),
+                        request_id: None,
+                    };
+                    socket.write_all(&response.to_bytes_as(conn.format)?).await?;
+                    return Ok(true);
+                }
+
+                let content_type_enum =
+                    crate::storage::models::ClipboardContentType::from(content_type.as_str());
+
+                // The checksum was verified above, so the same content from two
+                // machines lands in one row (first sender wins)
                 let entry = ClipboardEntry {
                     id: None,
+                    size_bytes: ClipboardEntry::decoded_size(&content_type_enum, &content),
                     content_type: content_type_enum,
                     content: content.clone(),
-                    metadata: None,
+                    metadata: metadata.clone(),
                     source: source.clone(),
                     timestamp,
-                    checksum: checksum.clone(),
+                    created_at: timestamp,
+                    checksum: clipboard_content.checksum(),
+                    apply_count: 0,
                 };
 
-                match storage.insert(&entry).await {
-                    Ok(_) => {
+                match with_timeout(config, "storing the update", storage.insert(&entry)).await? {
+                    Ok(id) => {
                         info!("Stored clipboard entry in database");
 
                         // Apply to local clipboard
-                        if let Err(e) = Self::apply_clipboard_update(&content_type, &content) {
-                            error!("Failed to apply clipboard update locally: {}", e);
+                        if !config.sync.direction.receives() {
+                            info!("Send-only mode: stored but not applied to local clipboard");
+                            record("skipped", Some("send_only".to_string()));
+                        } else if local.pause.is_paused() {
+                            info!("Sync paused: stored but not applied to local clipboard");
+                            record("skipped", Some("paused".to_string()));
+                        } else if !local.large.allow(&clipboard_content).await {
+                            record("skipped", Some("too_large".to_string()));
                         } else {
-                            info!("✓ Applied clipboard update to local clipboard");
+                            match local.apply(clipboard_content) {
+                                Err(e) => {
+                                    error!("Failed to apply clipboard update locally: {}", e);
+                                    record("failed", Some(format!("apply: {}", e)));
+                                }
+                                Ok(false) => {
+                                    info!("Local clipboard already holds this update");
+                                    record("skipped", Some("unchanged".to_string()));
+                                }
+                                Ok(true) => {
+                                    info!("✓ Applied clipboard update to local clipboard");
+                                    record("applied", None);
+                                    if let Err(e) =
+                                        with_timeout(config, "counting the apply", storage.record_apply(id)).await?
+                                    {
+                                        warn!("Failed to count applied entry {}: {}", id, e);
+                                    }
+                                }
+                            }
                         }
 
                         let response = Message::ClipboardAck {
                             checksum,
                             success: true,
+                            request_id: None,
                         };
-                        socket.write_all(&response.to_bytes()?).await?;
+                        socket.write_all(&response.to_bytes_as(conn.format)?).await?;
                     }
                     Err(e) => {
                         error!("Error storing clipboard entry: {}", e);
+                        record("failed", Some(format!("store: {}", e)));
                         let response = Message::ClipboardAck {
                             checksum,
                             success: false,
+                            request_id: None,
                         };
-                        socket.write_all(&response.to_bytes()?).await?;
+                        socket.write_all(&response.to_bytes_as(conn.format)?).await?;
                     }
                 }
             }
 
-            Message::HistoryRequest { limit, offset } => {
-                if !*authenticated {
+            Message::Replay { id, request_id } => {
+                if !conn.authenticated {
+                    return Ok(true);
+                }
+
+                let response = match with_timeout(config, "loading the entry", storage.get_by_id(id)).await?? {
+                    Some(entry) => {
+                        info!("Replaying history entry {} to connected clients", id);
+                        let checksum = entry.checksum.clone();
+                        // No receivers just means nobody is connected right now
+                        let _ = clipboard_tx.send(Arc::new(BroadcastUpdate::new(entry)));
+                        Message::ClipboardAck {
+                            checksum,
+                            success: true,
+                            request_id,
+                        }
+                    }
+                    None => Message::Error {
+                        message: format!("history entry {} not found", id),
+                        request_id,
+                    },
+                };
+                socket.write_all(&response.to_bytes_as(conn.format)?).await?;
+            }
+
+            Message::HistoryRequest {
+                limit,
+                offset,
+                request_id,
+            } => {
+                if !conn.authenticated {
                     return Ok(true);
                 }
 
@@ -269,7 +614,7 @@ impl ClipboardServer {
                     ..Default::default()
                 };
 
-                let entries = storage.search(&query).await?;
+                let entries = with_timeout(config, "loading history", storage.search(&query)).await??;
 
                 let history_entries: Vec<crate::sync::protocol::HistoryEntry> = entries
                     .into_iter()
@@ -285,9 +630,31 @@ impl ClipboardServer {
 
                 let response = Message::HistoryResponse {
                     entries: history_entries,
+                    request_id,
+                };
+
+                socket.write_all(&response.to_bytes_as(conn.format)?).await?;
+            }
+
+            Message::GetStats { request_id } => {
+                if !conn.authenticated {
+                    return Ok(true);
+                }
+
+                let stats = with_timeout(config, "loading stats", storage.stats()).await??;
+                let dedup = storage.dedup_stats();
+                let response = Message::StatsResponse {
+                    total: stats.total,
+                    by_type: stats.by_type,
+                    by_source: stats.by_source,
+                    oldest: stats.oldest,
+                    newest: stats.newest,
+                    new_entries: dedup.new_entries,
+                    deduped: dedup.deduped,
+                    request_id,
                 };
 
-                socket.write_all(&response.to_bytes()?).await?;
+                socket.write_all(&response.to_bytes_as(conn.format)?).await?;
             }
 
             _ => {
@@ -297,14 +664,459 @@ impl ClipboardServer {
 
         Ok(true)
     }
+}
 
-    fn apply_clipboard_update(content_type: &str, content: &str) -> Result<()> {
-        use crate::clipboard::{ClipboardContent, ClipboardManager};
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::SyncDirection;
+    use crate::storage::{models::ClipboardSearchQuery, ClipboardStorage};
+    use tokio::net::TcpStream;
 
-        let mut clipboard = ClipboardManager::new()?;
-        let clipboard_content = ClipboardContent::from_base64(content_type, content)?;
-        clipboard.set_content(&clipboard_content)?;
+    #[test]
+    fn test_broadcast_is_compressed_once_for_every_connection() {
+        let entry = ClipboardEntry::new(
+            crate::storage::models::ClipboardContentType::Text,
+            "A".repeat(crate::sync::protocol::COMPRESSION_THRESHOLD + 1),
+            "macos".to_string(),
+        );
+        let update = BroadcastUpdate::new(entry);
 
-        Ok(())
+        assert!(matches!(update.message(Compression::None), Message::ClipboardUpdate { .. }));
+        let first = update.message(Compression::Zstd);
+        assert!(matches!(first, Message::ClipboardUpdateCompressed { .. }));
+        assert!(std::ptr::eq(first, update.message(Compression::Zstd)));
+    }
+
+    fn update(source: &str, checksum: &str) -> Message {
+        Message::ClipboardUpdate {
+            content_type: "text".to_string(),
+            content: "same content".to_string(),
+            timestamp: chrono::Utc::now(),
+            source: source.to_string(),
+            checksum: checksum.to_string(),
+            metadata: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_identical_content_from_two_clients_is_stored_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = Config::default();
+        config.storage.database_path = Some(dir.path().join("clipboard.db"));
+        // Keep the test away from the real clipboard
+        config.sync.direction = SyncDirection::SendOnly;
+        let storage = ClipboardStorage::from_config(&config).await.unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let _peer = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        let (mut socket, _) = listener.accept().await.unwrap();
+        let mut conn = ConnectionState {
+            authenticated: true,
+            ..Default::default()
+        };
+        let (clipboard_tx, _) = broadcast::channel(1);
+
+        let checksum = ClipboardEntry::calculate_checksum("same content");
+        for source in ["macos", "nixos"] {
+            ClipboardServer::handle_message(
+                update(source, &checksum),
+                &mut socket,
+                &config,
+                &storage,
+                &clipboard_tx,
+                &LocalClipboard::default(),
+                &mut conn,
+            )
+            .await
+            .unwrap();
+        }
+
+        let entries = storage.search(&ClipboardSearchQuery::default()).await.unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].source, "macos");
+    }
+
+    #[tokio::test]
+    async fn test_updates_from_unlisted_sources_are_not_stored() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = Config::default();
+        config.storage.database_path = Some(dir.path().join("clipboard.db"));
+        config.sync.direction = SyncDirection::SendOnly;
+        config.server.allowed_sources = Some(vec!["macos".to_string()]);
+        let storage = ClipboardStorage::from_config(&config).await.unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let _peer = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        let (mut socket, _) = listener.accept().await.unwrap();
+        let mut conn = ConnectionState {
+            authenticated: true,
+            ..Default::default()
+        };
+        let (clipboard_tx, _) = broadcast::channel(1);
+
+        // The unlisted source goes first; had it been stored, it would own the row
+        let checksum = ClipboardEntry::calculate_checksum("same content");
+        for source in ["nixos", "macos"] {
+            ClipboardServer::handle_message(
+                update(source, &checksum),
+                &mut socket,
+                &config,
+                &storage,
+                &clipboard_tx,
+                &LocalClipboard::default(),
+                &mut conn,
+            )
+            .await
+            .unwrap();
+        }
+
+        let entries = storage.search(&ClipboardSearchQuery::default()).await.unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].source, "macos");
+    }
+
+    #[tokio::test]
+    async fn test_hello_negotiates_compression() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = Config::default();
+        config.storage.database_path = Some(dir.path().join("clipboard.db"));
+        let storage = ClipboardStorage::from_config(&config).await.unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut peer = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        let (mut socket, _) = listener.accept().await.unwrap();
+        let (clipboard_tx, _) = broadcast::channel(1);
+
+        let cases: [(&[&str], Compression, Option<&str>); 3] = [
+            (&["zstd"], Compression::Zstd, Some("zstd")),
+            (&["brotli"], Compression::None, None),
+            (&[], Compression::None, None),
+        ];
+        for (offered, expected, answer) in cases {
+            let mut conn = ConnectionState::default();
+            let hello = Message::Hello {
+                compression: offered.iter().map(|name| name.to_string()).collect(),
+                formats: Vec::new(),
+            };
+            ClipboardServer::handle_message(
+                hello,
+                &mut socket,
+                &config,
+                &storage,
+                &clipboard_tx,
+                &LocalClipboard::default(),
+                &mut conn,
+            )
+            .await
+            .unwrap();
+            assert_eq!(conn.compression, expected);
+
+            let mut buffer = vec![0u8; 4096];
+            let n = peer.read(&mut buffer).await.unwrap();
+            match Message::from_bytes(&buffer[..n]).unwrap().0 {
+                Message::HelloAck { compression, format } => {
+                    assert_eq!(compression.as_deref(), answer);
+                    assert_eq!(format.as_deref(), Some("json"));
+                }
+                other => panic!("unexpected response {:?}", other),
+            }
+        }
+
+        // A client asking for MessagePack gets its answer in MessagePack
+        let mut conn = ConnectionState::default();
+        let hello = Message::Hello {
+            compression: Vec::new(),
+            formats: vec!["cbor".to_string(), "msgpack".to_string()],
+        };
+        ClipboardServer::handle_message(
+            hello,
+            &mut socket,
+            &config,
+            &storage,
+            &clipboard_tx,
+            &LocalClipboard::default(),
+            &mut conn,
+        )
+        .await
+        .unwrap();
+        assert_eq!(conn.format, WireFormat::MessagePack);
+
+        let mut buffer = vec![0u8; 4096];
+        let n = peer.read(&mut buffer).await.unwrap();
+        assert_ne!(buffer[4], b'{');
+        match Message::from_bytes(&buffer[..n]).unwrap().0 {
+            Message::HelloAck { format, .. } => assert_eq!(format.as_deref(), Some("msgpack")),
+            other => panic!("unexpected response {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_compressed_update_is_not_inflated_before_auth() {
+        let mut config = Config::default();
+        config.server.auth_token = Some("secret".to_string());
+        let storage = ClipboardStorage::in_memory(100, 1).await.unwrap();
+        let (mut socket, _peer) = tokio::io::duplex(4096);
+        let mut conn = ConnectionState::default();
+        let (clipboard_tx, _) = broadcast::channel(1);
+
+        // Not valid zstd, so decompressing it would fail the connection
+        let bogus = Message::ClipboardUpdateCompressed {
+            content_type: "text".to_string(),
+            content: "not zstd".to_string(),
+            original_len: 1024,
+            timestamp: chrono::Utc::now(),
+            source: "macos".to_string(),
+            checksum: "aaaaaaaaaaaa".to_string(),
+            metadata: None,
+        };
+        let keep_going = ClipboardServer::handle_message(
+            bogus,
+            &mut socket,
+            &config,
+            &storage,
+            &clipboard_tx,
+            &LocalClipboard::default(),
+            &mut conn,
+        )
+        .await
+        .unwrap();
+        assert!(keep_going);
+        assert_eq!(storage.get_count().await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_mismatched_checksum_is_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = Config::default();
+        config.storage.database_path = Some(dir.path().join("clipboard.db"));
+        config.sync.direction = SyncDirection::SendOnly;
+        let storage = ClipboardStorage::from_config(&config).await.unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut peer = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        let (mut socket, _) = listener.accept().await.unwrap();
+        let mut conn = ConnectionState {
+            authenticated: true,
+            ..Default::default()
+        };
+        let (clipboard_tx, mut clipboard_rx) = broadcast::channel(1);
+
+        let keep_going = ClipboardServer::handle_message(
+            update("macos", "aaaaaaaaaaaa"),
+            &mut socket,
+            &config,
+            &storage,
+            &clipboard_tx,
+            &LocalClipboard::default(),
+            &mut conn,
+        )
+        .await
+        .unwrap();
+        assert!(keep_going);
+
+        let entries = storage.search(&ClipboardSearchQuery::default()).await.unwrap();
+        assert!(entries.is_empty());
+        assert!(clipboard_rx.try_recv().is_err());
+
+        let mut buffer = vec![0u8; 4096];
+        let n = peer.read(&mut buffer).await.unwrap();
+        let (response, _) = Message::from_bytes(&buffer[..n]).unwrap();
+        assert!(matches!(response, Message::Error { .. }));
+    }
+
+    #[tokio::test]
+    async fn test_bye_closes_connection() {
+        let config = Config::default();
+        let storage = ClipboardStorage::in_memory(10, 1).await.unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let _peer = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        let (mut socket, _) = listener.accept().await.unwrap();
+        let (clipboard_tx, _) = broadcast::channel(1);
+
+        let keep_going = ClipboardServer::handle_message(
+            Message::Bye,
+            &mut socket,
+            &config,
+            &storage,
+            &clipboard_tx,
+            &LocalClipboard::default(),
+            &mut ConnectionState::default(),
+        )
+        .await
+        .unwrap();
+        assert!(!keep_going);
+    }
+
+    #[tokio::test]
+    async fn test_replay_broadcasts_stored_entry() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = Config::default();
+        config.storage.database_path = Some(dir.path().join("clipboard.db"));
+        let storage = ClipboardStorage::from_config(&config).await.unwrap();
+        let id = storage
+            .insert(&ClipboardEntry::new(
+                crate::storage::models::ClipboardContentType::Text,
+                "again".to_string(),
+                "macos".to_string(),
+            ))
+            .await
+            .unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut peer = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        let (mut socket, _) = listener.accept().await.unwrap();
+        let (clipboard_tx, mut clipboard_rx) = broadcast::channel(4);
+        let mut conn = ConnectionState {
+            authenticated: true,
+            ..Default::default()
+        };
+
+        for replay_id in [id, id + 100] {
+            ClipboardServer::handle_message(
+                Message::Replay {
+                    id: replay_id,
+                    request_id: Some(replay_id as u64),
+                },
+                &mut socket,
+                &config,
+                &storage,
+                &clipboard_tx,
+                &LocalClipboard::default(),
+                &mut conn,
+            )
+            .await
+            .unwrap();
+        }
+
+        assert_eq!(clipboard_rx.recv().await.unwrap().entry.content, "again");
+        assert!(clipboard_rx.try_recv().is_err());
+
+        let mut buffer = vec![0u8; 4096];
+        let mut received = Vec::new();
+        let mut responses = Vec::new();
+        while responses.len() < 2 {
+            match Message::from_bytes(&received) {
+                Ok((message, size)) => {
+                    received.drain(..size);
+                    responses.push(message);
+                }
+                Err(_) => {
+                    let n = peer.read(&mut buffer).await.unwrap();
+                    received.extend_from_slice(&buffer[..n]);
+                }
+            }
+        }
+        assert!(matches!(
+            responses[0],
+            Message::ClipboardAck { success: true, request_id: Some(r), .. } if r == id as u64
+        ));
+        assert!(matches!(
+            responses[1],
+            Message::Error { request_id: Some(r), .. } if r == id as u64 + 100
+        ));
+    }
+
+    /// A backend whose every call hangs, like a database stuck on a lock
+    struct StalledStorage;
+
+    #[async_trait::async_trait]
+    impl Storage for StalledStorage {
+        async fn insert(&self, _: &ClipboardEntry) -> Result<i64> {
+            std::future::pending().await
+        }
+        async fn get_latest(&self) -> Result<Option<ClipboardEntry>> {
+            std::future::pending().await
+        }
+        async fn get_by_id(&self, _: i64) -> Result<Option<ClipboardEntry>> {
+            std::future::pending().await
+        }
+        async fn search(&self, _: &ClipboardSearchQuery) -> Result<Vec<ClipboardEntry>> {
+            std::future::pending().await
+        }
+        async fn get_count(&self) -> Result<i64> {
+            std::future::pending().await
+        }
+        async fn stats(&self) -> Result<crate::storage::models::ClipboardStats> {
+            std::future::pending().await
+        }
+        async fn record_apply(&self, _: i64) -> Result<()> {
+            std::future::pending().await
+        }
+        async fn clear(&self) -> Result<()> {
+            std::future::pending().await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stalled_storage_times_out_request() {
+        let mut config = Config::default();
+        config.server.request_timeout_ms = 50;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut peer = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        let (socket, _) = listener.accept().await.unwrap();
+        let (clipboard_tx, _) = broadcast::channel(1);
+
+        let request = Message::HistoryRequest {
+            limit: 10,
+            offset: 0,
+            request_id: Some(7),
+        };
+        peer.write_all(&request.to_bytes().unwrap()).await.unwrap();
+
+        let connection = ClipboardServer::handle_connection(
+            socket,
+            "test",
+            Arc::new(config),
+            Arc::new(StalledStorage),
+            clipboard_tx,
+            LocalClipboard::default(),
+        );
+        tokio::time::timeout(Duration::from_secs(2), connection)
+            .await
+            .expect("stalled request was never abandoned")
+            .unwrap();
+
+        // The client hears why before the connection closes
+        let mut received = Vec::new();
+        peer.read_to_end(&mut received).await.unwrap();
+        let (response, _) = Message::from_bytes(&received).unwrap();
+        assert!(matches!(
+            response,
+            Message::Error { request_id: Some(7), ref message } if message.contains("timed out")
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_silent_client_is_disconnected() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = Config::default();
+        config.storage.database_path = Some(dir.path().join("clipboard.db"));
+        config.sync.heartbeat_interval_ms = 50;
+        let storage: Arc<dyn Storage> =
+            Arc::new(ClipboardStorage::from_config(&config).await.unwrap());
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut peer = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        let (socket, _) = listener.accept().await.unwrap();
+        let (clipboard_tx, _) = broadcast::channel(1);
+
+        let connection = ClipboardServer::handle_connection(
+            socket,
+            "test",
+            Arc::new(config),
+            storage,
+            clipboard_tx,
+            LocalClipboard::default(),
+        );
+        tokio::time::timeout(Duration::from_secs(2), connection)
+            .await
+            .expect("silent connection was never closed")
+            .unwrap();
+
+        let mut buffer = [0u8; 16];
+        assert_eq!(peer.read(&mut buffer).await.unwrap(), 0);
     }
 }
@@ -0,0 +1,141 @@
+use anyhow::{Context, Result};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// Metadata key marking an entry whose `content` column holds a blob checksum
+const BLOB_FLAG: &str = "blob";
+
+/// SQL condition selecting rows whose content lives in a blob
+pub const IS_BLOB_SQL: &str = "json_extract(coalesce(metadata, '{}'), '$.blob') IS 1";
+
+/// Content-addressed files for clipboard content too large to keep in SQLite.
+/// Each blob is named by the checksum of the content it holds.
+#[derive(Debug, Clone)]
+pub struct BlobStore {
+    dir: PathBuf,
+    threshold: usize,
+}
+
+impl BlobStore {
+    pub fn new(dir: PathBuf, threshold: usize) -> Self {
+        Self { dir, threshold }
+    }
+
+    /// Whether `content` is large enough to be stored outside the database
+    pub fn should_store(&self, content: &str) -> bool {
+        content.len() > self.threshold
+    }
+
+    fn path(&self, checksum: &str) -> Result<PathBuf> {
+        // Checksums are hex; anything else must not become a path
+        if checksum.is_empty() || !checksum.chars().all(|c| c.is_ascii_alphanumeric()) {
+            anyhow::bail!("invalid blob checksum {:?}", checksum);
+        }
+        Ok(self.dir.join(checksum))
+    }
+
+    /// Store `content` under `checksum`; existing blobs are left as they are
+    pub fn write(&self, checksum: &str, content: &str) -> Result<()> {
+        let path = self.path(checksum)?;
+        if path.exists() {
+            return Ok(());
+        }
+
+        std::fs::create_dir_all(&self.dir)
+            .with_context(|| format!("failed to create blob directory {}", self.dir.display()))?;
+        // Write then rename so a reader never sees a partial blob
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, content)
+            .and_then(|_| std::fs::rename(&tmp, &path))
+            .with_context(|| format!("failed to write blob {}", path.display()))
+    }
+
+    /// Delete the blob under `checksum`, if there is one
+    pub fn remove(&self, checksum: &str) -> Result<()> {
+        let path = self.path(checksum)?;
+        match std::fs::remove_file(&path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                Err(e).with_context(|| format!("failed to remove blob {}", path.display()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn read(&self, checksum: &str) -> Result<String> {
+        let path = self.path(checksum)?;
+        std::fs::read_to_string(&path)
+            .with_context(|| format!("failed to read blob {}", path.display()))
+    }
+
+    /// Delete blobs not in `referenced` that are older than `min_age`, so a
+    /// blob whose row is not committed yet survives. Returns how many were
+    /// removed.
+    pub fn collect_garbage(
+        &self,
+        referenced: &HashSet<String>,
+        min_age: Duration,
+    ) -> Result<usize> {
+        let entries = match std::fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut removed = 0;
+        for entry in entries {
+            let entry = entry?;
+            let name = entry.file_name();
+            if referenced.contains(name.to_string_lossy().as_ref())
+                || !is_older(&entry.path(), min_age)
+            {
+                continue;
+            }
+            std::fs::remove_file(entry.path())?;
+            removed += 1;
+        }
+        Ok(removed)
+    }
+}
+
+fn is_older(path: &Path, min_age: Duration) -> bool {
+    if min_age.is_zero() {
+        return true;
+    }
+    std::fs::metadata(path)
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+        .is_some_and(|age| age >= min_age)
+}
+
+/// Add the blob flag to JSON entry metadata, keeping existing keys
+pub fn mark_blob(metadata: Option<&str>) -> String {
+    let mut value = metadata
+        .and_then(|m| serde_json::from_str::<serde_json::Value>(m).ok())
+        .filter(|v| v.is_object())
+        .unwrap_or_else(|| serde_json::json!({}));
+    value[BLOB_FLAG] = serde_json::Value::Bool(true);
+    value.to_string()
+}
+
+/// Remove the blob flag from stored metadata, returning whether it was set
+/// and the metadata callers should see
+pub fn take_blob_flag(metadata: Option<String>) -> (bool, Option<String>) {
+    let Some(mut value) = metadata
+        .as_deref()
+        .and_then(|m| serde_json::from_str::<serde_json::Value>(m).ok())
+    else {
+        return (false, metadata);
+    };
+
+    let Some(object) = value.as_object_mut() else {
+        return (false, metadata);
+    };
+    if object.remove(BLOB_FLAG) != Some(serde_json::Value::Bool(true)) {
+        return (false, metadata);
+    }
+
+    let metadata = (!object.is_empty()).then(|| value.to_string());
+    (true, metadata)
+}
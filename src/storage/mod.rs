@@ -1,33 +1,378 @@
+mod blobs;
 pub mod models;
 
-use anyhow::Result;
+use crate::config::{Config, ConfigError};
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use blobs::BlobStore;
 use chrono::{TimeZone, Utc};
-use models::{ClipboardEntry, ClipboardSearchQuery};
-use sqlx::{sqlite::SqlitePool, Row};
-use std::path::PathBuf;
+use futures::{Stream, TryStreamExt};
+use lru::LruCache;
+use models::{
+    BatchResult, ClipboardContentType, ClipboardEntry, ClipboardSearchQuery, ClipboardStats,
+    DedupStats, HistoryCursor,
+};
+use sqlx::{
+    sqlite::{
+        SqliteConnectOptions, SqliteJournalMode, SqlitePool, SqlitePoolOptions,
+        SqliteSynchronous,
+    },
+    Row,
+};
+use std::collections::HashSet;
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::{debug, info, warn};
+
+/// Pool size used when no configuration is supplied
+#[cfg(test)]
+const DEFAULT_POOL_SIZE: u32 = 8;
+
+/// Number of recently inserted checksums remembered to skip dedup lookups
+const RECENT_CHECKSUMS: usize = 64;
+
+/// How long a connection waits on a lock held by another process
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Tries at a write that keeps failing with SQLITE_BUSY, and the wait
+/// before the first retry (doubled each time)
+const WRITE_ATTEMPTS: u32 = 4;
+const WRITE_RETRY_DELAY: Duration = Duration::from_millis(50);
+
+/// Longest a write can keep retrying: every attempt waiting out
+/// `BUSY_TIMEOUT`, plus the doubling delays between them
+pub const WRITE_RETRY_BUDGET: Duration = BUSY_TIMEOUT
+    .saturating_mul(WRITE_ATTEMPTS)
+    .saturating_add(WRITE_RETRY_DELAY.saturating_mul((1 << (WRITE_ATTEMPTS - 1)) - 1));
+
+/// Unreferenced blobs younger than this are kept by eviction, since their
+/// row may not have been inserted yet
+const BLOB_GRACE_PERIOD: Duration = Duration::from_secs(60);
+
+const MB: u64 = 1024 * 1024;
+
+/// What the startup size check does about the database
+#[derive(Debug, PartialEq, Eq)]
+enum SizeAction {
+    Nothing,
+    Warn,
+    Vacuum,
+}
+
+/// The action for a database of `size` bytes given the `warn_db_size_mb` and
+/// `auto_vacuum_threshold_mb` settings (0 disables either)
+fn size_action(size: u64, warn_mb: u64, vacuum_mb: u64) -> SizeAction {
+    let over = |mb: u64| mb > 0 && size > mb * MB;
+    if over(vacuum_mb) {
+        SizeAction::Vacuum
+    } else if over(warn_mb) {
+        SizeAction::Warn
+    } else {
+        SizeAction::Nothing
+    }
+}
+
+/// Bytes on disk for the database, including its write-ahead log
+fn database_size(db_path: &Path) -> u64 {
+    let mut wal = db_path.as_os_str().to_owned();
+    wal.push("-wal");
+    [db_path, Path::new(&wal)]
+        .iter()
+        .filter_map(|path| std::fs::metadata(path).ok())
+        .map(|metadata| metadata.len())
+        .sum()
+}
+
+fn megabytes(bytes: u64) -> String {
+    format!("{:.1} MB", bytes as f64 / MB as f64)
+}
+
+// SQLite primary result codes we give friendlier errors for
+const SQLITE_BUSY: i64 = 5;
+const SQLITE_LOCKED: i64 = 6;
+const SQLITE_CORRUPT: i64 = 11;
+const SQLITE_NOTADB: i64 = 26;
+
+/// A clipboard history backend. `ClipboardStorage` (SQLite) is the default;
+/// the daemon and server only depend on this trait.
+#[async_trait]
+pub trait Storage: Send + Sync {
+    /// Store an entry, or bump the timestamp of an existing one with the same
+    /// checksum. The first writer wins: a duplicate keeps the original row's
+    /// source, content and `created_at`.
+    async fn insert(&self, entry: &ClipboardEntry) -> Result<i64>;
+    async fn get_latest(&self) -> Result<Option<ClipboardEntry>>;
+    async fn get_by_id(&self, id: i64) -> Result<Option<ClipboardEntry>>;
+    async fn search(&self, query: &ClipboardSearchQuery) -> Result<Vec<ClipboardEntry>>;
+    async fn get_count(&self) -> Result<i64>;
+    async fn stats(&self) -> Result<ClipboardStats>;
+    /// Count one more copy of entry `id` back onto a clipboard
+    async fn record_apply(&self, id: i64) -> Result<()>;
+    async fn clear(&self) -> Result<()>;
+
+    /// Flush buffered writes to the main store; a no-op for backends without one
+    async fn checkpoint(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// How many inserts created rows vs matched existing content since this
+    /// storage was opened; zero for backends that don't count
+    fn dedup_stats(&self) -> DedupStats {
+        DedupStats::default()
+    }
+}
 
 #[derive(Clone)]
 pub struct ClipboardStorage {
     pool: SqlitePool,
     max_history: usize,
+    recent_checksums: Arc<Mutex<LruCache<String, i64>>>,
+    blobs: Option<BlobStore>, // Large content is kept in files when set
+    new_entries: Arc<AtomicU64>,
+    deduped: Arc<AtomicU64>,
+    binary_images: bool, // New images go in `content_blob` as raw bytes when set
 }
 
 impl ClipboardStorage {
+    /// Open `db_path` with default settings; the daemon uses `from_config`
+    #[cfg(test)]
     pub async fn new(db_path: PathBuf, max_history: usize) -> Result<Self> {
+        Self::connect(
+            db_path,
+            max_history,
+            SqliteSynchronous::Normal,
+            DEFAULT_POOL_SIZE,
+        )
+        .await
+    }
+
+    /// Open the database described by the storage section of `config`
+    pub async fn from_config(config: &Config) -> Result<Self> {
+        let synchronous = SqliteSynchronous::from_str(&config.storage.synchronous)
+            .map_err(|e| anyhow::anyhow!(e))
+            .context("invalid storage.synchronous setting")
+            .map_err(ConfigError)?;
+
+        if config.storage.is_in_memory() {
+            if config.storage.blob_threshold_bytes().is_some() {
+                warn!("storage.blob_threshold_kb is ignored for an in-memory database");
+            }
+            return Ok(
+                Self::in_memory(config.storage.max_history, config.storage.db_pool_size.max(1))
+                    .await?
+                    .with_binary_images(config.storage.binary_images),
+            );
+        }
+
+        let db_path = config.get_database_path();
+        let storage = Self::connect(
+            db_path.clone(),
+            config.storage.max_history,
+            synchronous,
+            config.storage.db_pool_size.max(1),
+        )
+        .await?;
+
+        let storage = match config.storage.blob_threshold_bytes() {
+            Some(threshold) => {
+                let dir = db_path.parent().unwrap_or(Path::new(".")).join("blobs");
+                storage.with_blob_store(dir, threshold)
+            }
+            None => storage,
+        }
+        .with_binary_images(config.storage.binary_images);
+        storage.backfill_blob_sizes().await?;
+        storage.rehash_legacy_checksums().await?;
+        Ok(storage)
+    }
+
+    /// Warn about, or vacuum, a database that has grown past
+    /// `storage.warn_db_size_mb` or `storage.auto_vacuum_threshold_mb`. Run
+    /// once when the daemon starts, not by every CLI command.
+    pub async fn check_size(&self, config: &Config) {
+        if config.storage.is_in_memory() {
+            return;
+        }
+        let db_path = config.get_database_path();
+        let settings = &config.storage;
+        let warn_mb = settings.warn_db_size_mb;
+        let mut size = database_size(&db_path);
+
+        if size_action(size, warn_mb, settings.auto_vacuum_threshold_mb) == SizeAction::Vacuum {
+            info!(
+                "Clipboard database is {}, over storage.auto_vacuum_threshold_mb ({} MB); vacuuming",
+                megabytes(size),
+                settings.auto_vacuum_threshold_mb
+            );
+            match self.vacuum().await {
+                Ok(()) => {
+                    let after = database_size(&db_path);
+                    info!("Vacuumed clipboard database: {} -> {}", megabytes(size), megabytes(after));
+                    size = after;
+                }
+                Err(e) => warn!("⚠️  Failed to vacuum clipboard database: {}", e),
+            }
+        }
+
+        if size_action(size, warn_mb, 0) == SizeAction::Warn {
+            warn!(
+                "⚠️  Clipboard database {} is {}, over storage.warn_db_size_mb ({} MB). \
+                 Lower storage.max_history or set storage.auto_vacuum_threshold_mb",
+                db_path.display(),
+                megabytes(size),
+                warn_mb
+            );
+        }
+    }
+
+    /// Rebuild the database file so space freed by deleted entries goes back
+    /// to the OS
+    pub async fn vacuum(&self) -> Result<()> {
+        sqlx::query("VACUUM").execute(&self.pool).await?;
+        // In WAL mode the rebuilt pages land in the log first
+        self.checkpoint().await
+    }
+
+    /// Cursor at the most recently used entry, for `ClipboardSearchQuery::after`
+    pub async fn latest_cursor(&self) -> Result<HistoryCursor> {
+        let latest: Option<(i64, i64)> = sqlx::query_as(
+            "SELECT timestamp, id FROM clipboard_history ORDER BY timestamp DESC, id DESC LIMIT 1",
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(latest.map_or_else(HistoryCursor::default, |(timestamp_millis, id)| HistoryCursor {
+            timestamp_millis,
+            id,
+        }))
+    }
+
+    /// Keep content larger than `threshold` bytes in files under `dir`
+    /// instead of the database
+    pub fn with_blob_store(mut self, dir: PathBuf, threshold: usize) -> Self {
+        self.blobs = Some(BlobStore::new(dir, threshold));
+        self
+    }
+
+    /// Store new images as raw PNG bytes in `content_blob` rather than as
+    /// base64 text, which is a third larger. Both forms are always readable.
+    pub fn with_binary_images(mut self, enabled: bool) -> Self {
+        self.binary_images = enabled;
+        self
+    }
+
+    async fn connect(
+        db_path: PathBuf,
+        max_history: usize,
+        synchronous: SqliteSynchronous,
+        pool_size: u32,
+    ) -> Result<Self> {
         // Ensure parent directory exists
         if let Some(parent) = db_path.parent() {
             tokio::fs::create_dir_all(parent).await?;
         }
 
         let db_url = format!("sqlite:{}?mode=rwc", db_path.display());
-        let pool = SqlitePool::connect(&db_url).await?;
+        // WAL lets history searches read while the monitor is writing
+        let options = SqliteConnectOptions::from_str(&db_url)?
+            .journal_mode(SqliteJournalMode::Wal)
+            .synchronous(synchronous)
+            .busy_timeout(BUSY_TIMEOUT);
+        let pool = SqlitePoolOptions::new()
+            .max_connections(pool_size)
+            .connect_with(options)
+            .await
+            .map_err(|e| Self::explain_open_error(e.into(), &db_path))?;
+
+        Self::with_pool(pool, max_history)
+            .await
+            .map_err(|e| Self::explain_open_error(e, &db_path))
+    }
 
-        let storage = Self { pool, max_history };
+    /// A database that lives only in memory and is gone when the process
+    /// exits, for tests, benchmarks and no-persistence daemons
+    pub async fn in_memory(max_history: usize, pool_size: u32) -> Result<Self> {
+        // sqlx opens :memory: as a uniquely named shared-cache database, so
+        // pooled connections see the same data. It is dropped when the last
+        // connection closes, so the pool must never let them all go.
+        let pool = SqlitePoolOptions::new()
+            .max_connections(pool_size)
+            .min_connections(1)
+            .idle_timeout(None)
+            .max_lifetime(None)
+            .connect_with(SqliteConnectOptions::from_str("sqlite::memory:")?)
+            .await?;
+
+        Self::with_pool(pool, max_history).await
+    }
+
+    async fn with_pool(pool: SqlitePool, max_history: usize) -> Result<Self> {
+        let storage = Self {
+            pool,
+            max_history,
+            recent_checksums: Arc::new(Mutex::new(LruCache::new(
+                NonZeroUsize::new(RECENT_CHECKSUMS).unwrap(),
+            ))),
+            blobs: None,
+            new_entries: Arc::default(),
+            deduped: Arc::default(),
+            binary_images: false,
+        };
         storage.init_schema().await?;
 
         Ok(storage)
     }
 
+    /// The primary SQLite result code behind `err`, if it came from SQLite
+    fn sqlite_code(err: &anyhow::Error) -> Option<i64> {
+        err.downcast_ref::<sqlx::Error>()
+            .and_then(|e| e.as_database_error())
+            .and_then(|e| e.code())
+            .and_then(|code| code.parse::<i64>().ok())
+            // Extended result codes carry the primary code in the low byte
+            .map(|code| code & 0xff)
+    }
+
+    /// Run a write, retrying with backoff while another connection or
+    /// process holds the lock past `BUSY_TIMEOUT`
+    async fn with_retry<T, F, Fut>(op: &str, mut write: F) -> Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: std::future::Future<Output = Result<T>>,
+    {
+        let mut delay = WRITE_RETRY_DELAY;
+        for _ in 1..WRITE_ATTEMPTS {
+            match write().await {
+                Err(e) if matches!(Self::sqlite_code(&e), Some(SQLITE_BUSY | SQLITE_LOCKED)) => {
+                    debug!("Database busy during {}, retrying in {:?}: {}", op, delay, e);
+                    tokio::time::sleep(delay).await;
+                    delay *= 2;
+                }
+                result => return result,
+            }
+        }
+        write().await
+    }
+
+    /// Turn lock and corruption failures into errors that tell the user what to do
+    fn explain_open_error(err: anyhow::Error, db_path: &Path) -> anyhow::Error {
+        match Self::sqlite_code(&err) {
+            Some(SQLITE_BUSY) | Some(SQLITE_LOCKED) => err.context(format!(
+                "database {} is locked — is another clippy running?",
+                db_path.display()
+            )),
+            Some(SQLITE_CORRUPT) | Some(SQLITE_NOTADB) => err.context(format!(
+                "database {} is corrupt — restore it from a backup or move it aside to start fresh",
+                db_path.display()
+            )),
+            _ => err.context(format!("failed to open database {}", db_path.display())),
+        }
+    }
+
     async fn init_schema(&self) -> Result<()> {
         sqlx::query(
             r#"
@@ -38,7 +383,11 @@ impl ClipboardStorage {
                 metadata TEXT,
                 source TEXT NOT NULL,
                 timestamp INTEGER NOT NULL,
+                created_at INTEGER,
                 checksum TEXT NOT NULL,
+                size_bytes INTEGER,
+                apply_count INTEGER NOT NULL DEFAULT 0,
+                content_blob BLOB,
                 UNIQUE(checksum)
             );
 
@@ -51,10 +400,284 @@ impl ClipboardStorage {
         .execute(&self.pool)
         .await?;
 
+        self.migrate_created_at().await?;
+        self.migrate_size_bytes().await?;
+        self.migrate_timestamp_millis().await?;
+        self.migrate_apply_count().await?;
+        self.migrate_content_blob().await?;
+
+        Ok(())
+    }
+
+    /// Add the `created_at` column to databases created before it existed,
+    /// backfilling it from the last-used timestamp
+    async fn migrate_created_at(&self) -> Result<()> {
+        let has_column: bool = sqlx::query_scalar(
+            "SELECT COUNT(*) > 0 FROM pragma_table_info('clipboard_history') WHERE name = 'created_at'",
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        if !has_column {
+            sqlx::query("ALTER TABLE clipboard_history ADD COLUMN created_at INTEGER")
+                .execute(&self.pool)
+                .await?;
+        }
+
+        sqlx::query("UPDATE clipboard_history SET created_at = timestamp WHERE created_at IS NULL")
+            .execute(&self.pool)
+            .await?;
+
+        sqlx::query(
+            "CREATE INDEX IF NOT EXISTS idx_created_at ON clipboard_history(created_at DESC)",
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Add the `size_bytes` column to databases created before it existed.
+    /// Inline rows are backfilled here, in the same transaction; rows kept as
+    /// blobs are filled in by `backfill_blob_sizes` once the blob store is set.
+    async fn migrate_size_bytes(&self) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        let has_column: bool = sqlx::query_scalar(
+            "SELECT COUNT(*) > 0 FROM pragma_table_info('clipboard_history') WHERE name = 'size_bytes'",
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        if has_column {
+            return Ok(());
+        }
+
+        sqlx::query("ALTER TABLE clipboard_history ADD COLUMN size_bytes INTEGER")
+            .execute(&mut *tx)
+            .await?;
+
+        sqlx::query(&format!(
+            r#"
+            UPDATE clipboard_history SET size_bytes = CASE
+                WHEN content_type IN ('image', 'other') THEN
+                    length(content) / 4 * 3
+                    - (CASE WHEN content LIKE '%==' THEN 2 WHEN content LIKE '%=' THEN 1 ELSE 0 END)
+                ELSE length(CAST(content AS BLOB))
+            END
+            WHERE NOT ({})
+            "#,
+            blobs::IS_BLOB_SQL
+        ))
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Measure blob rows left without a `size_bytes` by the migration, which
+    /// can't see the blob files. A no-op once every row has a size.
+    async fn backfill_blob_sizes(&self) -> Result<()> {
+        let Some(store) = &self.blobs else {
+            return Ok(());
+        };
+        let rows: Vec<(i64, String, String)> = sqlx::query_as(&format!(
+            "SELECT id, content_type, content FROM clipboard_history WHERE size_bytes IS NULL AND {}",
+            blobs::IS_BLOB_SQL
+        ))
+        .fetch_all(&self.pool)
+        .await?;
+
+        for (id, content_type, checksum) in rows {
+            let content = match store.read(&checksum) {
+                Ok(content) => content,
+                Err(e) => {
+                    warn!("Entry {} content is unavailable: {:#}", id, e);
+                    continue;
+                }
+            };
+            let content_type = ClipboardContentType::from(content_type.as_str());
+            let size = ClipboardEntry::decoded_size(&content_type, &content);
+            sqlx::query("UPDATE clipboard_history SET size_bytes = ? WHERE id = ?")
+                .bind(size as i64)
+                .bind(id)
+                .execute(&self.pool)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Recompute checksums stored by releases that used `DefaultHasher`, so
+    /// content copied again still dedups against its existing row. Blob rows
+    /// keep their file name in `content`, so only the checksum column changes.
+    /// A legacy row whose content was stored again since is dropped.
+    async fn rehash_legacy_checksums(&self) -> Result<()> {
+        // `DefaultHasher` checksums are 16 hex digits at most
+        let ids: Vec<i64> =
+            sqlx::query_scalar("SELECT id FROM clipboard_history WHERE length(checksum) < 64")
+                .fetch_all(&self.pool)
+                .await?;
+        if ids.is_empty() {
+            return Ok(());
+        }
+
+        info!("Updating {} history checksum(s) to SHA-256", ids.len());
+        for id in ids {
+            let Some(entry) = self.get_by_id(id).await? else {
+                continue;
+            };
+            // An unreadable blob comes back empty; leave its checksum alone
+            if entry.content.is_empty() && entry.size_bytes > 0 {
+                continue;
+            }
+            let checksum = ClipboardEntry::content_checksum(&entry.content_type, &entry.content);
+            let result = sqlx::query("UPDATE OR IGNORE clipboard_history SET checksum = ? WHERE id = ?")
+                .bind(checksum)
+                .bind(id)
+                .execute(&self.pool)
+                .await?;
+            if result.rows_affected() == 0 {
+                sqlx::query("DELETE FROM clipboard_history WHERE id = ?")
+                    .bind(id)
+                    .execute(&self.pool)
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Convert timestamps stored in whole seconds by older releases to
+    /// milliseconds. No millisecond value since 1973 is below the cutoff,
+    /// so rows already converted are left alone.
+    async fn migrate_timestamp_millis(&self) -> Result<()> {
+        let result = sqlx::query(
+            r#"
+            UPDATE clipboard_history SET
+                timestamp = CASE WHEN timestamp < 100000000000 THEN timestamp * 1000 ELSE timestamp END,
+                created_at = CASE WHEN created_at < 100000000000 THEN created_at * 1000 ELSE created_at END
+            WHERE timestamp < 100000000000 OR created_at < 100000000000
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        if result.rows_affected() > 0 {
+            info!(
+                "Converted {} history timestamps to millisecond precision",
+                result.rows_affected()
+            );
+        }
+
         Ok(())
     }
 
-    pub async fn insert(&self, entry: &ClipboardEntry) -> Result<i64> {
+    /// Add the `apply_count` column to databases created before it existed
+    async fn migrate_apply_count(&self) -> Result<()> {
+        let has_column: bool = sqlx::query_scalar(
+            "SELECT COUNT(*) > 0 FROM pragma_table_info('clipboard_history') WHERE name = 'apply_count'",
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        if !has_column {
+            sqlx::query(
+                "ALTER TABLE clipboard_history ADD COLUMN apply_count INTEGER NOT NULL DEFAULT 0",
+            )
+            .execute(&self.pool)
+            .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Add the `content_blob` column to databases created before it existed
+    async fn migrate_content_blob(&self) -> Result<()> {
+        let has_column: bool = sqlx::query_scalar(
+            "SELECT COUNT(*) > 0 FROM pragma_table_info('clipboard_history') WHERE name = 'content_blob'",
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        if !has_column {
+            sqlx::query("ALTER TABLE clipboard_history ADD COLUMN content_blob BLOB")
+                .execute(&self.pool)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// With `binary_images` on, move images stored as base64 text into
+    /// `content_blob`, one row at a time so memory stays flat. Rows that
+    /// aren't valid base64 are left as they are. Run once when the daemon
+    /// starts, not by every CLI command.
+    pub async fn convert_base64_images(&self) -> Result<()> {
+        if !self.binary_images {
+            return Ok(());
+        }
+        let ids: Vec<i64> = sqlx::query_scalar(&format!(
+            "SELECT id FROM clipboard_history \
+             WHERE content_type = 'image' AND content_blob IS NULL AND content != '' AND NOT ({})",
+            blobs::IS_BLOB_SQL
+        ))
+        .fetch_all(&self.pool)
+        .await?;
+        if ids.is_empty() {
+            return Ok(());
+        }
+
+        info!("Converting {} stored image(s) from base64 to binary", ids.len());
+        for id in ids {
+            let content: String = sqlx::query_scalar("SELECT content FROM clipboard_history WHERE id = ?")
+                .bind(id)
+                .fetch_one(&self.pool)
+                .await?;
+            let Ok(bytes) = BASE64.decode(&content) else {
+                warn!("Entry {} is not valid base64; leaving it as text", id);
+                continue;
+            };
+            sqlx::query("UPDATE clipboard_history SET content = '', content_blob = ? WHERE id = ?")
+                .bind(bytes)
+                .bind(id)
+                .execute(&self.pool)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Update the last-used timestamp of an existing entry, returning whether it still exists
+    async fn touch(&self, id: i64, entry: &ClipboardEntry) -> Result<bool> {
+        let result = sqlx::query("UPDATE clipboard_history SET timestamp = ? WHERE id = ?")
+            .bind(entry.timestamp.timestamp_millis())
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    fn remember_checksum(&self, checksum: &str, id: i64) {
+        self.recent_checksums
+            .lock()
+            .unwrap()
+            .put(checksum.to_string(), id);
+    }
+
+    /// One attempt at `insert`, returning the entry's id and whether a row was
+    /// added. Counters are only bumped once the write has succeeded, so a
+    /// retried attempt is never counted twice.
+    async fn insert_once(&self, entry: &ClipboardEntry) -> Result<(i64, bool)> {
+        // Recently seen content only needs its timestamp bumped. The cached id
+        // may be stale if the row was evicted, in which case nothing is updated
+        // and we fall through to the normal lookup.
+        let cached = self.recent_checksums.lock().unwrap().get(&entry.checksum).copied();
+        if let Some(id) = cached {
+            if self.touch(id, entry).await? {
+                self.deduped.fetch_add(1, Ordering::Relaxed);
+                return Ok((id, false));
+            }
+            self.recent_checksums.lock().unwrap().pop(&entry.checksum);
+        }
+
         // Check if entry with same checksum exists
         let existing: Option<i64> = sqlx::query_scalar(
             "SELECT id FROM clipboard_history WHERE checksum = ? LIMIT 1",
@@ -64,46 +687,175 @@ impl ClipboardStorage {
         .await?;
 
         if let Some(id) = existing {
-            // Update timestamp of existing entry
-            sqlx::query(
-                "UPDATE clipboard_history SET timestamp = ? WHERE id = ?",
-            )
-            .bind(entry.timestamp.timestamp())
-            .bind(id)
-            .execute(&self.pool)
-            .await?;
-            return Ok(id);
+            self.touch(id, entry).await?;
+            self.remember_checksum(&entry.checksum, id);
+            self.deduped.fetch_add(1, Ordering::Relaxed);
+            return Ok((id, false));
         }
 
-        // Insert new entry
-        let result = sqlx::query(
+        let (content, metadata, content_blob) = self.row_content(entry)?;
+
+        // Insert new entry. Another connection may have stored the same content
+        // since the lookup above, so resolve that race in SQLite rather than
+        // failing on the UNIQUE constraint.
+        let mut tx = self.pool.begin().await?;
+        let (id, stored_metadata): (i64, Option<String>) = sqlx::query_as(
             r#"
-            INSERT INTO clipboard_history (content_type, content, metadata, source, timestamp, checksum)
-            VALUES (?, ?, ?, ?, ?, ?)
+            INSERT INTO clipboard_history (content_type, content, metadata, source, timestamp, created_at, checksum, size_bytes, content_blob)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
+            ON CONFLICT(checksum) DO UPDATE SET timestamp = excluded.timestamp
+            RETURNING id, metadata
             "#,
         )
         .bind(entry.content_type.as_str())
-        .bind(&entry.content)
-        .bind(&entry.metadata)
+        .bind(&content)
+        .bind(&metadata)
         .bind(&entry.source)
-        .bind(entry.timestamp.timestamp())
+        .bind(entry.timestamp.timestamp_millis())
+        .bind(entry.created_at.timestamp_millis())
         .bind(&entry.checksum)
-        .execute(&self.pool)
+        .bind(ClipboardEntry::decoded_size(&entry.content_type, &entry.content) as i64)
+        .bind(content_blob)
+        .fetch_one(&mut *tx)
         .await?;
+        // The blob is written once its row is in; if that fails the row is
+        // rolled back with the transaction
+        self.write_blob(entry, &stored_metadata)?;
+        tx.commit().await?;
+
+        self.remember_checksum(&entry.checksum, id);
+        self.new_entries.fetch_add(1, Ordering::Relaxed);
+        Ok((id, true))
+    }
+
+    /// Store many entries in one transaction, e.g. for an import. Unlike
+    /// `insert`, content that is already stored is skipped untouched rather
+    /// than having its timestamp bumped.
+    pub async fn insert_batch(&self, entries: &[ClipboardEntry]) -> Result<BatchResult> {
+        let result = Self::with_retry("batch insert", || self.insert_batch_once(entries)).await?;
+
+        self.new_entries.fetch_add(result.inserted as u64, Ordering::Relaxed);
+        self.deduped.fetch_add(result.skipped as u64, Ordering::Relaxed);
+        if result.inserted > 0 {
+            Self::with_retry("cleanup", || self.cleanup_old_entries()).await?;
+        }
+        Ok(result)
+    }
+
+    async fn insert_batch_once(&self, entries: &[ClipboardEntry]) -> Result<BatchResult> {
+        let mut written = Vec::new();
+        let result = self.insert_batch_rows(entries, &mut written).await;
+        if result.is_err() {
+            // Their rows were rolled back, so nothing references these blobs
+            if let Some(blobs) = &self.blobs {
+                for checksum in &written {
+                    if let Err(e) = blobs.remove(checksum) {
+                        warn!("Failed to remove blob of a rolled back import: {:#}", e);
+                    }
+                }
+            }
+        }
+        result
+    }
+
+    /// Insert `entries` in one transaction, noting the checksum of each blob
+    /// written in `written`
+    async fn insert_batch_rows(
+        &self,
+        entries: &[ClipboardEntry],
+        written: &mut Vec<String>,
+    ) -> Result<BatchResult> {
+        let mut result = BatchResult::default();
+        let mut tx = self.pool.begin().await?;
 
-        // Cleanup old entries if exceeding max_history
-        self.cleanup_old_entries().await?;
+        for entry in entries {
+            let (content, metadata, content_blob) = self.row_content(entry)?;
+            let inserted = sqlx::query(
+                r#"
+                INSERT OR IGNORE INTO clipboard_history (content_type, content, metadata, source, timestamp, created_at, checksum, size_bytes, content_blob)
+                VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
+                "#,
+            )
+            .bind(entry.content_type.as_str())
+            .bind(&content)
+            .bind(&metadata)
+            .bind(&entry.source)
+            .bind(entry.timestamp.timestamp_millis())
+            .bind(entry.created_at.timestamp_millis())
+            .bind(&entry.checksum)
+            .bind(ClipboardEntry::decoded_size(&entry.content_type, &entry.content) as i64)
+            .bind(content_blob)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+
+            if inserted > 0 {
+                if self.write_blob(entry, &metadata)? {
+                    written.push(entry.checksum.clone());
+                }
+                result.inserted += 1;
+            } else {
+                result.skipped += 1;
+            }
+        }
 
-        Ok(result.last_insert_rowid())
+        // Dropping the transaction on an error above rolls the whole batch back
+        tx.commit().await?;
+        Ok(result)
+    }
+
+    /// The `content`, `metadata` and `content_blob` columns of a new row
+    fn row_content(&self, entry: &ClipboardEntry) -> Result<(String, Option<String>, Option<Vec<u8>>)> {
+        // Large content goes to a blob named by its checksum, written by
+        // `write_blob` once the row is in; the row keeps only the checksum
+        // and a metadata flag
+        let (content, metadata) = match &self.blobs {
+            Some(blobs) if blobs.should_store(&entry.content) => {
+                (
+                    entry.checksum.clone(),
+                    Some(blobs::mark_blob(entry.metadata.as_deref())),
+                )
+            }
+            _ => (entry.content.clone(), entry.metadata.clone()),
+        };
+        // Images kept in the row go in as raw bytes, leaving `content` empty
+        Ok(match self.image_bytes(entry, &metadata) {
+            Some(bytes) => (String::new(), metadata, Some(bytes)),
+            None => (content, metadata, None),
+        })
+    }
+
+    /// Write the blob file of a stored row whose `metadata` flags it as one,
+    /// returning whether it did
+    fn write_blob(&self, entry: &ClipboardEntry, metadata: &Option<String>) -> Result<bool> {
+        match &self.blobs {
+            Some(blobs) if blobs::take_blob_flag(metadata.clone()).0 => {
+                blobs.write(&entry.checksum, &entry.content)?;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    /// The decoded bytes of an image entry to store in `content_blob`, when
+    /// binary images are on and the content isn't already in a blob file
+    fn image_bytes(&self, entry: &ClipboardEntry, metadata: &Option<String>) -> Option<Vec<u8>> {
+        if !self.binary_images || !matches!(entry.content_type, ClipboardContentType::Image) {
+            return None;
+        }
+        if blobs::take_blob_flag(metadata.clone()).0 {
+            return None;
+        }
+        BASE64.decode(&entry.content).ok()
     }
 
     async fn cleanup_old_entries(&self) -> Result<()> {
-        sqlx::query(
+        let result = sqlx::query(
             r#"
             DELETE FROM clipboard_history
             WHERE id NOT IN (
                 SELECT id FROM clipboard_history
-                ORDER BY timestamp DESC
+                ORDER BY timestamp DESC, id DESC
                 LIMIT ?
             )
             "#,
@@ -112,15 +864,120 @@ impl ClipboardStorage {
         .execute(&self.pool)
         .await?;
 
+        if result.rows_affected() > 0 {
+            self.collect_blob_garbage(BLOB_GRACE_PERIOD).await?;
+        }
+
         Ok(())
     }
 
-    pub async fn get_latest(&self) -> Result<Option<ClipboardEntry>> {
+    /// Delete blob files no longer referenced by any row
+    async fn collect_blob_garbage(&self, min_age: Duration) -> Result<()> {
+        let Some(blobs) = &self.blobs else {
+            return Ok(());
+        };
+
+        let referenced: HashSet<String> = sqlx::query_scalar(&format!(
+            "SELECT content FROM clipboard_history WHERE {}",
+            blobs::IS_BLOB_SQL
+        ))
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .collect();
+
+        let removed = blobs.collect_garbage(&referenced, min_age)?;
+        if removed > 0 {
+            debug!("Removed {} unreferenced blob(s)", removed);
+        }
+        Ok(())
+    }
+
+    /// Every entry, including sensitive ones, most recently used first.
+    /// Rows are fetched lazily, so memory stays flat however long the history.
+    pub fn stream_all(&self) -> impl Stream<Item = Result<ClipboardEntry>> + '_ {
+        sqlx::query(
+            r#"
+            SELECT id, content_type, content, metadata, source, timestamp, created_at, checksum, size_bytes, apply_count, content_blob
+            FROM clipboard_history
+            ORDER BY timestamp DESC, id DESC
+            "#,
+        )
+        .fetch(&self.pool)
+        .map_ok(|row| self.row_to_entry(row))
+        .map_err(Into::into)
+    }
+
+    fn row_to_entry(&self, row: sqlx::sqlite::SqliteRow) -> ClipboardEntry {
+        let id: i64 = row.get("id");
+        let content_type_str: String = row.get("content_type");
+        let content: String = row.get("content");
+        let metadata: Option<String> = row.get("metadata");
+        let source: String = row.get("source");
+        let timestamp: i64 = row.get("timestamp");
+        let created_at: Option<i64> = row.get("created_at");
+        let checksum: String = row.get("checksum");
+        let size_bytes: Option<i64> = row.get("size_bytes");
+        let apply_count: i64 = row.get("apply_count");
+        let content_blob: Option<Vec<u8>> = row.get("content_blob");
+
+        let (is_blob, metadata) = blobs::take_blob_flag(metadata);
+        let content = match (&self.blobs, is_blob) {
+            (Some(blobs), true) => blobs.read(&content).unwrap_or_else(|e| {
+                warn!("Entry {} content is unavailable: {:#}", id, e);
+                String::new()
+            }),
+            (None, true) => {
+                warn!("Entry {} is stored as a blob, but blob storage is off", id);
+                String::new()
+            }
+            // Entries keep base64 content in memory; only storage differs
+            (_, false) => match content_blob {
+                Some(bytes) => BASE64.encode(bytes),
+                None => content,
+            },
+        };
+
+        let content_type = ClipboardContentType::from(content_type_str.as_str());
+        let size_bytes = match size_bytes {
+            Some(size) => size as usize,
+            None => ClipboardEntry::decoded_size(&content_type, &content),
+        };
+
+        ClipboardEntry {
+            id: Some(id),
+            content_type,
+            content,
+            metadata,
+            source,
+            timestamp: Utc.timestamp_millis_opt(timestamp).unwrap(),
+            created_at: Utc.timestamp_millis_opt(created_at.unwrap_or(timestamp)).unwrap(),
+            checksum,
+            size_bytes,
+            apply_count,
+        }
+    }
+}
+
+#[async_trait]
+impl Storage for ClipboardStorage {
+    async fn insert(&self, entry: &ClipboardEntry) -> Result<i64> {
+        let (id, inserted) = Self::with_retry("insert", || self.insert_once(entry)).await?;
+
+        // Cleanup old entries if exceeding max_history. Retried on its own so a
+        // busy database doesn't redo the insert above.
+        if inserted {
+            Self::with_retry("cleanup", || self.cleanup_old_entries()).await?;
+        }
+        Ok(id)
+    }
+
+    async fn get_latest(&self) -> Result<Option<ClipboardEntry>> {
         let row = sqlx::query(
             r#"
-            SELECT id, content_type, content, metadata, source, timestamp, checksum
+            SELECT id, content_type, content, metadata, source, timestamp, created_at, checksum, size_bytes, apply_count, content_blob
             FROM clipboard_history
-            ORDER BY timestamp DESC
+            ORDER BY timestamp DESC, id DESC
             LIMIT 1
             "#,
         )
@@ -130,9 +987,24 @@ impl ClipboardStorage {
         Ok(row.map(|r| self.row_to_entry(r)))
     }
 
-    pub async fn search(&self, query: &ClipboardSearchQuery) -> Result<Vec<ClipboardEntry>> {
+    async fn get_by_id(&self, id: i64) -> Result<Option<ClipboardEntry>> {
+        let row = sqlx::query(
+            r#"
+            SELECT id, content_type, content, metadata, source, timestamp, created_at, checksum, size_bytes, apply_count, content_blob
+            FROM clipboard_history
+            WHERE id = ?
+            "#,
+        )
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.map(|r| self.row_to_entry(r)))
+    }
+
+    async fn search(&self, query: &ClipboardSearchQuery) -> Result<Vec<ClipboardEntry>> {
         let mut sql = String::from(
-            "SELECT id, content_type, content, metadata, source, timestamp, checksum FROM clipboard_history WHERE 1=1",
+            "SELECT id, content_type, content, metadata, source, timestamp, created_at, checksum, size_bytes, apply_count, content_blob FROM clipboard_history WHERE 1=1",
         );
         let mut bindings = Vec::new();
 
@@ -147,17 +1019,33 @@ impl ClipboardStorage {
         }
 
         if let Some(ref search_text) = query.search_text {
-            sql.push_str(" AND content LIKE ?");
-            bindings.push(format!("%{}%", search_text));
+            sql.push_str(" AND content LIKE ? ESCAPE '\\'");
+            bindings.push(query.match_mode.like_pattern(search_text));
+        }
+
+        if query.after.is_some() {
+            sql.push_str(" AND (timestamp, id) > (?, ?)");
+        }
+
+        if !query.include_sensitive {
+            sql.push_str(" AND json_extract(coalesce(metadata, '{}'), '$.sensitive') IS NOT 1");
         }
 
-        sql.push_str(" ORDER BY timestamp DESC LIMIT ? OFFSET ?");
+        sql.push_str(&format!(
+            " ORDER BY {} DESC, id DESC LIMIT ? OFFSET ?",
+            query.order.column()
+        ));
 
         let mut query_builder = sqlx::query(&sql);
         for binding in bindings {
             query_builder = query_builder.bind(binding);
         }
-        query_builder = query_builder.bind(query.limit as i64);
+        if let Some(after) = query.after {
+            query_builder = query_builder.bind(after.timestamp_millis).bind(after.id);
+        }
+        // A negative LIMIT is unbounded in SQLite
+        let limit = if query.limit == 0 { -1 } else { query.limit as i64 };
+        query_builder = query_builder.bind(limit);
         query_builder = query_builder.bind(query.offset as i64);
 
         let rows = query_builder.fetch_all(&self.pool).await?;
@@ -165,40 +1053,643 @@ impl ClipboardStorage {
         Ok(rows.into_iter().map(|r| self.row_to_entry(r)).collect())
     }
 
-    pub async fn get_count(&self) -> Result<i64> {
+    async fn get_count(&self) -> Result<i64> {
         let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM clipboard_history")
             .fetch_one(&self.pool)
             .await?;
         Ok(count)
     }
 
-    pub async fn clear(&self) -> Result<()> {
-        sqlx::query("DELETE FROM clipboard_history")
-            .execute(&self.pool)
-            .await?;
+    async fn stats(&self) -> Result<ClipboardStats> {
+        let total = self.get_count().await?;
+
+        let by_type: Vec<(String, i64)> = sqlx::query_as(
+            "SELECT content_type, COUNT(*) FROM clipboard_history GROUP BY content_type",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        let by_source: Vec<(String, i64)> = sqlx::query_as(
+            "SELECT source, COUNT(*) FROM clipboard_history GROUP BY source",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        let (oldest, newest, total_bytes): (Option<i64>, Option<i64>, i64) = sqlx::query_as(
+            "SELECT MIN(created_at), MAX(timestamp), COALESCE(SUM(size_bytes), 0) FROM clipboard_history",
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(ClipboardStats {
+            total,
+            by_type: by_type.into_iter().collect(),
+            by_source: by_source.into_iter().collect(),
+            oldest: oldest.and_then(|t| Utc.timestamp_millis_opt(t).single()),
+            newest: newest.and_then(|t| Utc.timestamp_millis_opt(t).single()),
+            total_bytes,
+        })
+    }
+
+    fn dedup_stats(&self) -> DedupStats {
+        DedupStats {
+            new_entries: self.new_entries.load(Ordering::Relaxed),
+            deduped: self.deduped.load(Ordering::Relaxed),
+        }
+    }
+
+    async fn record_apply(&self, id: i64) -> Result<()> {
+        Self::with_retry("record_apply", || async {
+            sqlx::query("UPDATE clipboard_history SET apply_count = apply_count + 1 WHERE id = ?")
+                .bind(id)
+                .execute(&self.pool)
+                .await?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn clear(&self) -> Result<()> {
+        Self::with_retry("clear", || async {
+            sqlx::query("DELETE FROM clipboard_history")
+                .execute(&self.pool)
+                .await?;
+            Ok(())
+        })
+        .await?;
+        self.recent_checksums.lock().unwrap().clear();
+        self.collect_blob_garbage(Duration::ZERO).await?;
         Ok(())
     }
 
-    fn row_to_entry(&self, row: sqlx::sqlite::SqliteRow) -> ClipboardEntry {
-        use models::ClipboardContentType;
+    async fn checkpoint(&self) -> Result<()> {
+        let (busy, log_frames, checkpointed): (i64, i64, i64) =
+            sqlx::query_as("PRAGMA wal_checkpoint(TRUNCATE)")
+                .fetch_one(&self.pool)
+                .await?;
 
-        let id: i64 = row.get("id");
-        let content_type_str: String = row.get("content_type");
-        let content: String = row.get("content");
-        let metadata: Option<String> = row.get("metadata");
-        let source: String = row.get("source");
-        let timestamp: i64 = row.get("timestamp");
-        let checksum: String = row.get("checksum");
+        if busy != 0 {
+            warn!(
+                "WAL checkpoint was blocked by a reader or writer ({} of {} frames checkpointed)",
+                checkpointed, log_frames
+            );
+        } else {
+            debug!("WAL checkpoint complete ({} frames)", checkpointed);
+        }
+        Ok(())
+    }
+}
 
-        ClipboardEntry {
-            id: Some(id),
-            content_type: ClipboardContentType::from_str(&content_type_str)
-                .unwrap_or(ClipboardContentType::Text),
-            content,
-            metadata,
-            source,
-            timestamp: Utc.timestamp_opt(timestamp, 0).unwrap(),
-            checksum,
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+    use models::{ClipboardContentType, HistoryOrder, MatchMode};
+
+    async fn test_storage(dir: &tempfile::TempDir) -> ClipboardStorage {
+        ClipboardStorage::new(dir.path().join("clipboard.db"), 100)
+            .await
+            .unwrap()
+    }
+
+    fn text_entry(content: &str, age_secs: i64) -> ClipboardEntry {
+        let mut entry = ClipboardEntry::new(
+            ClipboardContentType::Text,
+            content.to_string(),
+            "test".to_string(),
+        );
+        entry.timestamp = Utc::now() - Duration::seconds(age_secs);
+        entry.created_at = entry.timestamp;
+        entry
+    }
+
+    #[tokio::test]
+    async fn test_corrupt_database_error_is_actionable() {
+        let dir = tempfile::tempdir().unwrap();
+        let db_path = dir.path().join("clipboard.db");
+        std::fs::write(&db_path, vec![0xAB; 4096]).unwrap();
+
+        let err = ClipboardStorage::new(db_path, 100).await.err().unwrap();
+        assert!(err.to_string().contains("is corrupt"), "{:#}", err);
+    }
+
+    #[tokio::test]
+    async fn test_checksum_cache_is_reset_by_clear() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = test_storage(&dir).await;
+
+        let first = storage.insert(&text_entry("same", 10)).await.unwrap();
+        assert_eq!(storage.insert(&text_entry("same", 5)).await.unwrap(), first);
+        assert_eq!(storage.get_count().await.unwrap(), 1);
+        assert_eq!(storage.dedup_stats(), DedupStats { new_entries: 1, deduped: 1 });
+
+        // The cached id is gone after a clear, so the content is stored again
+        storage.clear().await.unwrap();
+        assert!(storage.recent_checksums.lock().unwrap().is_empty());
+        storage.insert(&text_entry("same", 1)).await.unwrap();
+        assert_eq!(storage.get_count().await.unwrap(), 1);
+        assert_eq!(storage.dedup_stats(), DedupStats { new_entries: 2, deduped: 1 });
+    }
+
+    #[tokio::test]
+    async fn test_insert_retries_while_another_pool_holds_the_lock() {
+        let dir = tempfile::tempdir().unwrap();
+        let db_path = dir.path().join("clipboard.db");
+        let holder = ClipboardStorage::new(db_path.clone(), 100).await.unwrap();
+
+        // A second pool that gives up on a lock at once, so only the retry helps
+        let options = SqliteConnectOptions::from_str(&format!("sqlite:{}", db_path.display()))
+            .unwrap()
+            .busy_timeout(std::time::Duration::ZERO);
+        let pool = SqlitePoolOptions::new().connect_with(options).await.unwrap();
+        let storage = ClipboardStorage::with_pool(pool, 100).await.unwrap();
+
+        let mut conn = holder.pool.acquire().await.unwrap();
+        sqlx::query("BEGIN IMMEDIATE").execute(&mut *conn).await.unwrap();
+        let release = tokio::spawn(async move {
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+            sqlx::query("COMMIT").execute(&mut *conn).await.unwrap();
+        });
+
+        storage.insert(&text_entry("contended", 1)).await.unwrap();
+        release.await.unwrap();
+        assert_eq!(holder.get_count().await.unwrap(), 1);
+        assert_eq!(storage.dedup_stats(), DedupStats { new_entries: 1, deduped: 0 });
+    }
+
+    #[tokio::test]
+    async fn test_in_memory_database_is_shared_by_the_pool() {
+        let mut config = Config::default();
+        config.storage.database_path = Some(PathBuf::from(":memory:"));
+        config.storage.max_history = 2;
+        let storage = ClipboardStorage::from_config(&config).await.unwrap();
+
+        for (content, age) in [("one", 30), ("two", 20), ("three", 10)] {
+            storage.insert(&text_entry(content, age)).await.unwrap();
+        }
+
+        // Eviction ran, and concurrent queries on other connections see the rows
+        let (count, latest) = tokio::join!(storage.get_count(), storage.get_latest());
+        assert_eq!(count.unwrap(), 2);
+        assert_eq!(latest.unwrap().unwrap().content, "three");
+    }
+
+    #[tokio::test]
+    async fn test_stream_all_matches_search() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = test_storage(&dir).await;
+
+        storage.insert(&text_entry("one", 30)).await.unwrap();
+        storage.insert(&text_entry("two", 20).mark_sensitive()).await.unwrap();
+        storage.insert(&text_entry("three", 10)).await.unwrap();
+
+        let streamed: Vec<ClipboardEntry> = storage.stream_all().try_collect().await.unwrap();
+        let searched = storage
+            .search(&ClipboardSearchQuery {
+                include_sensitive: true,
+                limit: 10_000,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+
+        let ids = |entries: &[ClipboardEntry]| entries.iter().map(|e| e.id).collect::<Vec<_>>();
+        assert_eq!(streamed.len(), 3);
+        assert_eq!(ids(&streamed), ids(&searched));
+    }
+
+    #[tokio::test]
+    async fn test_batch_insert_skips_duplicate_checksums() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = test_storage(&dir).await;
+        storage.insert(&text_entry("already stored", 50)).await.unwrap();
+
+        let batch = [
+            text_entry("first", 40),
+            text_entry("already stored", 30),
+            text_entry("second", 20),
+            text_entry("first", 10),
+        ];
+        let result = storage.insert_batch(&batch).await.unwrap();
+        assert_eq!(result, BatchResult { inserted: 2, skipped: 2 });
+
+        // The first copy in the batch wins, and existing rows are left alone
+        let entries = storage.search(&ClipboardSearchQuery::default()).await.unwrap();
+        let contents: Vec<_> = entries.iter().map(|e| e.content.as_str()).collect();
+        assert_eq!(contents, ["second", "first", "already stored"]);
+        assert_eq!(storage.dedup_stats(), DedupStats { new_entries: 3, deduped: 2 });
+    }
+
+    #[tokio::test]
+    async fn test_search_after_cursor_returns_newer_and_recopied_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = test_storage(&dir).await;
+        storage.insert(&text_entry("recopied", 60)).await.unwrap();
+        storage.insert(&text_entry("old", 30)).await.unwrap();
+        let cursor = storage.latest_cursor().await.unwrap();
+
+        // A re-copy keeps its id but bumps the timestamp past the cursor
+        storage.insert(&text_entry("new", 10)).await.unwrap();
+        storage.insert(&text_entry("recopied", 5)).await.unwrap();
+        storage.insert(&text_entry("backdated", 90)).await.unwrap();
+
+        let query = ClipboardSearchQuery {
+            after: Some(cursor),
+            ..Default::default()
+        };
+        let contents: Vec<_> =
+            storage.search(&query).await.unwrap().into_iter().map(|e| e.content).collect();
+        assert_eq!(contents, ["recopied", "new"]);
+        assert!(storage.latest_cursor().await.unwrap() > cursor);
+    }
+
+    #[tokio::test]
+    async fn test_search_match_modes() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = test_storage(&dir).await;
+        for (content, age) in [("api_key", 30), ("api_key_old", 20), ("my api_key", 10), ("apixkey", 5)] {
+            storage.insert(&text_entry(content, age)).await.unwrap();
         }
+
+        let search = |match_mode| {
+            let storage = storage.clone();
+            async move {
+                let query = ClipboardSearchQuery {
+                    search_text: Some("API_KEY".to_string()),
+                    match_mode,
+                    ..Default::default()
+                };
+                let entries = storage.search(&query).await.unwrap();
+                entries.into_iter().map(|e| e.content).collect::<Vec<_>>()
+            }
+        };
+
+        // `_` is literal, so "apixkey" never matches
+        assert_eq!(search(MatchMode::Substring).await, ["my api_key", "api_key_old", "api_key"]);
+        assert_eq!(search(MatchMode::Exact).await, ["api_key"]);
+        assert_eq!(search(MatchMode::Prefix).await, ["api_key_old", "api_key"]);
+    }
+
+    #[tokio::test]
+    async fn test_equal_timestamps_list_latest_insert_first() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = ClipboardStorage::connect(
+            dir.path().join("clipboard.db"),
+            2,
+            SqliteSynchronous::Normal,
+            4,
+        )
+        .await
+        .unwrap();
+
+        let at = text_entry("a", 0).timestamp;
+        let mut ids = Vec::new();
+        for content in ["a", "b", "c"] {
+            let mut entry = text_entry(content, 0);
+            entry.timestamp = at;
+            entry.created_at = at;
+            ids.push(storage.insert(&entry).await.unwrap());
+        }
+
+        // Cleanup kept the two newest rows, which search lists newest first
+        let listed: Vec<_> = storage
+            .search(&ClipboardSearchQuery::default())
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|e| e.id.unwrap())
+            .collect();
+        assert_eq!(listed, vec![ids[2], ids[1]]);
+    }
+
+    #[tokio::test]
+    async fn test_zero_limit_returns_everything() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = test_storage(&dir).await;
+        for (i, content) in ["a", "b", "c"].iter().enumerate() {
+            storage.insert(&text_entry(content, i as i64)).await.unwrap();
+        }
+
+        let query = |limit, offset| ClipboardSearchQuery {
+            limit,
+            offset,
+            ..Default::default()
+        };
+        assert_eq!(storage.search(&query(0, 0)).await.unwrap().len(), 3);
+        assert_eq!(storage.search(&query(0, 1)).await.unwrap().len(), 2);
+        assert_eq!(storage.search(&query(1, 0)).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_size_bytes_migration_measures_inline_and_blob_rows() {
+        let dir = tempfile::tempdir().unwrap();
+        let db_path = dir.path().join("clipboard.db");
+        let blob_dir = dir.path().join("blobs");
+
+        // A database from before size_bytes, with one row kept in a blob file
+        let options = SqliteConnectOptions::from_str(&format!("sqlite:{}?mode=rwc", db_path.display())).unwrap();
+        let pool = SqlitePool::connect_with(options).await.unwrap();
+        sqlx::query(
+            "CREATE TABLE clipboard_history (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                content_type TEXT NOT NULL,
+                content TEXT NOT NULL,
+                metadata TEXT,
+                source TEXT NOT NULL,
+                timestamp INTEGER NOT NULL,
+                checksum TEXT NOT NULL,
+                UNIQUE(checksum)
+            );
+            INSERT INTO clipboard_history (content_type, content, metadata, source, timestamp, checksum)
+            VALUES ('text', 'hello', NULL, 'test', 1700000000, 'inline'),
+                   ('text', 'abc123', '{\"blob\":1}', 'test', 1700000001, 'abc123');",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        pool.close().await;
+        std::fs::create_dir_all(&blob_dir).unwrap();
+        std::fs::write(blob_dir.join("abc123"), "sixteen bytes!!!").unwrap();
+
+        let storage = ClipboardStorage::new(db_path, 100).await.unwrap().with_blob_store(blob_dir, 4);
+        storage.backfill_blob_sizes().await.unwrap();
+
+        let unmeasured: i64 =
+            sqlx::query_scalar("SELECT COUNT(*) FROM clipboard_history WHERE size_bytes IS NULL")
+                .fetch_one(&storage.pool)
+                .await
+                .unwrap();
+        assert_eq!(unmeasured, 0);
+        assert_eq!(storage.stats().await.unwrap().total_bytes, 5 + 16);
+    }
+
+    #[tokio::test]
+    async fn test_second_precision_timestamps_are_migrated() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = test_storage(&dir).await;
+
+        sqlx::query(
+            "INSERT INTO clipboard_history (content_type, content, source, timestamp, created_at, checksum)
+             VALUES ('text', 'old', 'test', 1700000000, 1600000000, 'x')",
+        )
+        .execute(&storage.pool)
+        .await
+        .unwrap();
+        storage.migrate_timestamp_millis().await.unwrap();
+        storage.migrate_timestamp_millis().await.unwrap();
+
+        let entry = storage.search(&ClipboardSearchQuery::default()).await.unwrap().remove(0);
+        assert_eq!(entry.timestamp.timestamp(), 1_700_000_000);
+        assert_eq!(entry.created_at.timestamp(), 1_600_000_000);
+    }
+
+    #[tokio::test]
+    async fn test_legacy_checksums_are_rehashed() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = test_storage(&dir).await;
+        storage.insert(&text_entry("copied again", 0)).await.unwrap();
+        sqlx::query(
+            "INSERT INTO clipboard_history (content_type, content, source, timestamp, checksum)
+             VALUES ('text', 'hello', 'test', 1700000000000, '1a2b3c'),
+                    ('text', 'copied again', 'test', 1700000000000, '4d5e6f')",
+        )
+        .execute(&storage.pool)
+        .await
+        .unwrap();
+
+        storage.rehash_legacy_checksums().await.unwrap();
+        assert_eq!(storage.get_count().await.unwrap(), 2);
+        storage.insert(&text_entry("hello", 0)).await.unwrap();
+        assert_eq!(storage.get_count().await.unwrap(), 2);
+        assert_eq!(storage.dedup_stats().deduped, 1);
+    }
+
+    #[tokio::test]
+    async fn test_stats_groups_by_type_and_source() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = test_storage(&dir).await;
+
+        storage.insert(&text_entry("one", 20)).await.unwrap();
+        storage.insert(&text_entry("two", 10)).await.unwrap();
+
+        let stats = storage.stats().await.unwrap();
+        assert_eq!(stats.total, 2);
+        assert_eq!(stats.by_type.get("text"), Some(&2));
+        assert_eq!(stats.by_source.get("test"), Some(&2));
+        assert!(stats.oldest.unwrap() < stats.newest.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_duplicate_bumps_recent_but_not_created_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = test_storage(&dir).await;
+
+        storage.insert(&text_entry("first", 30)).await.unwrap();
+        storage.insert(&text_entry("second", 20)).await.unwrap();
+        // Copy "first" again; it becomes the most recently used
+        storage.insert(&text_entry("first", 10)).await.unwrap();
+
+        let recent = storage
+            .search(&ClipboardSearchQuery::default())
+            .await
+            .unwrap();
+        assert_eq!(recent[0].content, "first");
+
+        let created = storage
+            .search(&ClipboardSearchQuery {
+                order: HistoryOrder::Created,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(created[0].content, "second");
+        assert!(created[1].created_at < created[1].timestamp);
+    }
+
+    #[tokio::test]
+    async fn test_frequency_order_counts_applies() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = test_storage(&dir).await;
+
+        let snippet = storage.insert(&text_entry("snippet", 30)).await.unwrap();
+        storage.insert(&text_entry("once", 20)).await.unwrap();
+        storage.insert(&text_entry("never", 10)).await.unwrap();
+        storage.record_apply(snippet).await.unwrap();
+        storage.record_apply(snippet).await.unwrap();
+
+        let frequent = storage
+            .search(&ClipboardSearchQuery {
+                order: HistoryOrder::Frequency,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(frequent[0].content, "snippet");
+        assert_eq!(frequent[0].apply_count, 2);
+        // Ties fall back to the newest entry
+        assert_eq!(frequent[1].content, "never");
+    }
+
+    #[tokio::test]
+    async fn test_sensitive_entries_are_hidden_by_default() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = test_storage(&dir).await;
+
+        storage.insert(&text_entry("public", 10)).await.unwrap();
+        storage
+            .insert(&text_entry("hunter2", 5).mark_sensitive())
+            .await
+            .unwrap();
+
+        let visible = storage.search(&ClipboardSearchQuery::default()).await.unwrap();
+        assert_eq!(visible.len(), 1);
+        assert_eq!(visible[0].content, "public");
+
+        let all = storage
+            .search(&ClipboardSearchQuery {
+                include_sensitive: true,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(all.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_checkpoint_truncates_wal() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = test_storage(&dir).await;
+        storage.insert(&text_entry("hello", 1)).await.unwrap();
+
+        storage.checkpoint().await.unwrap();
+
+        let wal = dir.path().join("clipboard.db-wal");
+        assert_eq!(std::fs::metadata(wal).map(|m| m.len()).unwrap_or(0), 0);
+    }
+
+    #[test]
+    fn test_size_thresholds() {
+        assert_eq!(size_action(600 * MB, 500, 0), SizeAction::Warn);
+        assert_eq!(size_action(400 * MB, 500, 0), SizeAction::Nothing);
+        assert_eq!(size_action(600 * MB, 500, 550), SizeAction::Vacuum);
+        assert_eq!(size_action(520 * MB, 500, 550), SizeAction::Warn);
+        // 0 disables either check
+        assert_eq!(size_action(600 * MB, 0, 0), SizeAction::Nothing);
+        assert_eq!(size_action(600 * MB, 0, 100), SizeAction::Vacuum);
+    }
+
+    #[tokio::test]
+    async fn test_vacuum_shrinks_database_after_clear() {
+        let dir = tempfile::tempdir().unwrap();
+        let db_path = dir.path().join("clipboard.db");
+        let storage = test_storage(&dir).await;
+        for i in 0..100 {
+            let content = format!("{}{}", i, "x".repeat(16 * 1024));
+            storage.insert(&text_entry(&content, i)).await.unwrap();
+        }
+        storage.clear().await.unwrap();
+        storage.checkpoint().await.unwrap();
+        let before = database_size(&db_path);
+
+        storage.vacuum().await.unwrap();
+        assert!(database_size(&db_path) < before / 2);
+    }
+
+    #[tokio::test]
+    async fn test_images_round_trip_through_binary_column() {
+        let dir = tempfile::tempdir().unwrap();
+        let png = |marker: u8| BASE64.encode([0x89, b'P', b'N', b'G', marker]);
+        let image = |content: String| {
+            ClipboardEntry::new(ClipboardContentType::Image, content, "test".to_string())
+        };
+
+        // Stored as base64 text before the option was turned on
+        let storage = test_storage(&dir).await;
+        let old_id = storage.insert(&image(png(1))).await.unwrap();
+
+        let storage = storage.with_binary_images(true);
+        storage.convert_base64_images().await.unwrap();
+        let new_id = storage.insert(&image(png(2))).await.unwrap();
+
+        for (id, marker) in [(old_id, 1), (new_id, 2)] {
+            let (content, blob): (String, Option<Vec<u8>>) = sqlx::query_as(
+                "SELECT content, content_blob FROM clipboard_history WHERE id = ?",
+            )
+            .bind(id)
+            .fetch_one(&storage.pool)
+            .await
+            .unwrap();
+            assert_eq!(content, "");
+            assert_eq!(blob, Some(vec![0x89, b'P', b'N', b'G', marker]));
+
+            let entry = storage.get_by_id(id).await.unwrap().unwrap();
+            assert_eq!(entry.content, png(marker));
+            assert_eq!(entry.size_bytes, 5);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_large_content_is_stored_as_blob() {
+        let dir = tempfile::tempdir().unwrap();
+        let blob_dir = dir.path().join("blobs");
+        let storage = test_storage(&dir).await.with_blob_store(blob_dir.clone(), 16);
+
+        let small = storage.insert(&text_entry("tiny", 2)).await.unwrap();
+        let large_entry = text_entry(&"x".repeat(64), 1).mark_sensitive();
+        let large = storage.insert(&large_entry).await.unwrap();
+
+        let stored: String = sqlx::query_scalar("SELECT content FROM clipboard_history WHERE id = ?")
+            .bind(large)
+            .fetch_one(&storage.pool)
+            .await
+            .unwrap();
+        assert_eq!(stored, large_entry.checksum);
+        assert!(blob_dir.join(&large_entry.checksum).exists());
+
+        let loaded = storage.get_by_id(large).await.unwrap().unwrap();
+        assert_eq!(loaded.content, large_entry.content);
+        assert_eq!(loaded.metadata, large_entry.metadata);
+        assert_eq!(storage.get_by_id(small).await.unwrap().unwrap().content, "tiny");
+
+        storage.clear().await.unwrap();
+        assert!(!blob_dir.join(&large_entry.checksum).exists());
+    }
+
+    #[tokio::test]
+    async fn test_batch_writes_blobs_only_for_committed_rows() {
+        let dir = tempfile::tempdir().unwrap();
+        let blob_dir = dir.path().join("blobs");
+        let storage = test_storage(&dir).await.with_blob_store(blob_dir.clone(), 16);
+
+        // A skipped duplicate doesn't write its blob again
+        let stored = text_entry(&"s".repeat(64), 3);
+        storage.insert(&stored).await.unwrap();
+        std::fs::remove_file(blob_dir.join(&stored.checksum)).unwrap();
+        let result = storage.insert_batch(std::slice::from_ref(&stored)).await.unwrap();
+        assert_eq!(result.skipped, 1);
+        assert!(!blob_dir.join(&stored.checksum).exists());
+
+        // A failed batch takes back the blobs of the rows it rolled back
+        let first = text_entry(&"a".repeat(64), 2);
+        let mut bad = text_entry(&"b".repeat(64), 1);
+        bad.checksum = "not/a/checksum".to_string();
+        assert!(storage.insert_batch(&[first.clone(), bad]).await.is_err());
+        assert_eq!(storage.get_count().await.unwrap(), 1);
+        assert!(!blob_dir.join(&first.checksum).exists());
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_duplicate_inserts_share_a_row() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = test_storage(&dir).await;
+
+        let mut first = text_entry("race", 2);
+        first.source = "macos".to_string();
+        let mut second = text_entry("race", 1);
+        second.source = "nixos".to_string();
+
+        let (a, b) = tokio::join!(storage.insert(&first), storage.insert(&second));
+        assert_eq!(a.unwrap(), b.unwrap());
+        assert_eq!(storage.get_count().await.unwrap(), 1);
     }
 }
@@ -1,13 +1,19 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 
+/// Serialized as its `as_str` name, the same string stored in the database
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(into = "String", try_from = "String")]
 pub enum ClipboardContentType {
     Text,
     Image,
     Html,
     Rtf,
     Files,
+    /// Any other type string, kept as stored so it round-trips unchanged.
+    /// `"other"` itself marks content whose MIME type is kept in metadata.
+    Other(String),
 }
 
 impl ClipboardContentType {
@@ -18,17 +24,40 @@ impl ClipboardContentType {
             ClipboardContentType::Html => "html",
             ClipboardContentType::Rtf => "rtf",
             ClipboardContentType::Files => "files",
+            ClipboardContentType::Other(kind) => kind,
         }
     }
+}
 
-    pub fn from_str(s: &str) -> Option<Self> {
+impl From<&str> for ClipboardContentType {
+    fn from(s: &str) -> Self {
         match s {
-            "text" => Some(ClipboardContentType::Text),
-            "image" => Some(ClipboardContentType::Image),
-            "html" => Some(ClipboardContentType::Html),
-            "rtf" => Some(ClipboardContentType::Rtf),
-            "files" => Some(ClipboardContentType::Files),
-            _ => None,
+            "text" => ClipboardContentType::Text,
+            "image" => ClipboardContentType::Image,
+            "html" => ClipboardContentType::Html,
+            "rtf" => ClipboardContentType::Rtf,
+            "files" => ClipboardContentType::Files,
+            other => ClipboardContentType::Other(other.to_string()),
+        }
+    }
+}
+
+impl From<String> for ClipboardContentType {
+    fn from(s: String) -> Self {
+        // Exports written before the type serialized as its name used the
+        // variant names
+        match s.as_str() {
+            "Text" | "Image" | "Html" | "Rtf" | "Files" => Self::from(s.to_lowercase().as_str()),
+            _ => Self::from(s.as_str()),
+        }
+    }
+}
+
+impl From<ClipboardContentType> for String {
+    fn from(content_type: ClipboardContentType) -> Self {
+        match content_type {
+            ClipboardContentType::Other(kind) => kind,
+            known => known.as_str().to_string(),
         }
     }
 }
@@ -40,8 +69,13 @@ pub struct ClipboardEntry {
     pub content: String, // Base64 encoded for binary content
     pub metadata: Option<String>, // JSON encoded metadata
     pub source: String, // "macos" or "nixos"
-    pub timestamp: DateTime<Utc>,
+    pub timestamp: DateTime<Utc>, // Last used; bumped when duplicate content is inserted
+    pub created_at: DateTime<Utc>, // First copied; preserved across duplicates
     pub checksum: String, // SHA256 hash for deduplication
+    #[serde(default)]
+    pub size_bytes: usize, // Decoded content length: raw bytes for images, not base64
+    #[serde(default)]
+    pub apply_count: i64, // Times copied back to a clipboard by `recent --copy` or a sync
 }
 
 impl ClipboardEntry {
@@ -50,15 +84,20 @@ impl ClipboardEntry {
         content: String,
         source: String,
     ) -> Self {
-        let checksum = Self::calculate_checksum(&content);
+        let checksum = Self::content_checksum(&content_type, &content);
+        let size_bytes = Self::decoded_size(&content_type, &content);
+        let now = Utc::now();
         Self {
             id: None,
             content_type,
             content,
             metadata: None,
             source,
-            timestamp: Utc::now(),
+            timestamp: now,
+            created_at: now,
             checksum,
+            size_bytes,
+            apply_count: 0,
         }
     }
 
@@ -67,13 +106,168 @@ impl ClipboardEntry {
         self
     }
 
-    fn calculate_checksum(content: &str) -> String {
-        use std::collections::hash_map::DefaultHasher;
-        use std::hash::{Hash, Hasher};
+    /// Flag the entry as secret so history and search hide it by default
+    pub fn mark_sensitive(mut self) -> Self {
+        self.metadata = Some(mark_sensitive(self.metadata.as_deref()));
+        self
+    }
+
+    /// Whether the entry was flagged as secret by `mark_sensitive`
+    pub fn is_sensitive(&self) -> bool {
+        self.metadata
+            .as_deref()
+            .and_then(|m| serde_json::from_str::<serde_json::Value>(m).ok())
+            .is_some_and(|v| v["sensitive"] == serde_json::Value::Bool(true))
+    }
+
+    /// Searchable text of the entry: text as stored, HTML with tags stripped
+    /// and entities decoded. Images and other binary content have none.
+    pub fn decoded_text(&self) -> Option<String> {
+        match self.content_type {
+            ClipboardContentType::Text | ClipboardContentType::Rtf | ClipboardContentType::Files => {
+                Some(self.content.clone())
+            }
+            ClipboardContentType::Html => Some(strip_html(&self.content)),
+            ClipboardContentType::Image | ClipboardContentType::Other(_) => None,
+        }
+    }
+
+    /// Case-insensitive substring match against `decoded_text`
+    pub fn grep_matches(&self, pattern: &str) -> bool {
+        self.decoded_text()
+            .is_some_and(|text| text.to_lowercase().contains(&pattern.to_lowercase()))
+    }
+
+    /// Length of `content` once decoded. Images and "other" content are
+    /// base64, so this is measured from the encoding without decoding it.
+    pub fn decoded_size(content_type: &ClipboardContentType, content: &str) -> usize {
+        match content_type {
+            ClipboardContentType::Image => base64_decoded_len(content),
+            ClipboardContentType::Other(kind) if kind == "other" => base64_decoded_len(content),
+            _ => content.len(),
+        }
+    }
 
-        let mut hasher = DefaultHasher::new();
-        content.hash(&mut hasher);
-        format!("{:x}", hasher.finish())
+    /// Checksum of `content` as text, without decoding it
+    pub fn calculate_checksum(content: &str) -> String {
+        Self::checksum_bytes(content.as_bytes())
+    }
+
+    /// Checksum of stored content. Base64 content is decoded first, so this
+    /// matches `ClipboardContent::checksum` of what was copied.
+    pub fn content_checksum(content_type: &ClipboardContentType, content: &str) -> String {
+        use base64::{engine::general_purpose::STANDARD, Engine};
+
+        let is_base64 = match content_type {
+            ClipboardContentType::Image => true,
+            ClipboardContentType::Other(kind) => kind == "other",
+            _ => false,
+        };
+        match STANDARD.decode(content) {
+            Ok(bytes) if is_base64 => Self::checksum_bytes(&bytes),
+            _ => Self::calculate_checksum(content),
+        }
+    }
+
+    /// The one checksum used for deduplication, echo suppression and on the
+    /// wire, always over decoded content. SHA-256, so peers built with
+    /// different toolchains agree on it.
+    pub fn checksum_bytes(bytes: &[u8]) -> String {
+        crate::content_hash::content_hash(bytes)
+    }
+}
+
+fn base64_decoded_len(encoded: &str) -> usize {
+    let padding = encoded.bytes().rev().take(2).filter(|&b| b == b'=').count();
+    (encoded.len() / 4 * 3).saturating_sub(padding)
+}
+
+/// Term for the SQL `LIKE` prefilter of a grep: the longest word of the
+/// pattern, so phrases split by HTML tags still reach the decoded match
+pub fn grep_prefilter(pattern: &str) -> &str {
+    pattern
+        .split_whitespace()
+        .max_by_key(|word| word.len())
+        .unwrap_or(pattern)
+}
+
+/// Add `"sensitive": true` to JSON entry metadata, keeping existing keys
+pub fn mark_sensitive(metadata: Option<&str>) -> String {
+    let mut value = metadata
+        .and_then(|m| serde_json::from_str::<serde_json::Value>(m).ok())
+        .filter(|v| v.is_object())
+        .unwrap_or_else(|| serde_json::json!({}));
+    value["sensitive"] = serde_json::Value::Bool(true);
+    value.to_string()
+}
+
+/// Plain-text rendering of HTML: tags dropped, common entities decoded
+pub fn strip_html(html: &str) -> String {
+    let mut text = String::with_capacity(html.len());
+    let mut in_tag = false;
+    for ch in html.chars() {
+        match ch {
+            '<' => in_tag = true,
+            '>' if in_tag => in_tag = false,
+            _ if !in_tag => text.push(ch),
+            _ => {}
+        }
+    }
+
+    text.replace("&nbsp;", " ")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&")
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+pub enum HistoryOrder {
+    /// Most recently used first
+    #[default]
+    Recent,
+    /// Most recently created first, ignoring later reuse
+    Created,
+    /// Most often copied back to a clipboard first
+    Frequency,
+}
+
+impl HistoryOrder {
+    pub(crate) fn column(&self) -> &'static str {
+        match self {
+            HistoryOrder::Recent => "timestamp",
+            HistoryOrder::Created => "created_at",
+            HistoryOrder::Frequency => "apply_count",
+        }
+    }
+}
+
+/// How `search_text` must match an entry's content (case-insensitively)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MatchMode {
+    /// Anywhere in the content
+    #[default]
+    Substring,
+    /// The whole content
+    Exact,
+    /// The start of the content
+    Prefix,
+}
+
+impl MatchMode {
+    /// The `LIKE ... ESCAPE '\'` pattern for `text`, with its own `%`, `_`
+    /// and `\` matched literally
+    pub fn like_pattern(self, text: &str) -> String {
+        let escaped = text
+            .replace('\\', "\\\\")
+            .replace('%', "\\%")
+            .replace('_', "\\_");
+        match self {
+            MatchMode::Substring => format!("%{}%", escaped),
+            MatchMode::Exact => escaped,
+            MatchMode::Prefix => format!("{}%", escaped),
+        }
     }
 }
 
@@ -82,8 +276,15 @@ pub struct ClipboardSearchQuery {
     pub content_type: Option<ClipboardContentType>,
     pub source: Option<String>,
     pub search_text: Option<String>,
-    pub limit: usize,
+    #[serde(default)]
+    pub match_mode: MatchMode,
+    pub order: HistoryOrder,
+    pub include_sensitive: bool,
+    pub limit: usize, // 0 means no limit
     pub offset: usize,
+    /// Only entries last used after this point, for following new captures
+    #[serde(default)]
+    pub after: Option<HistoryCursor>,
 }
 
 impl Default for ClipboardSearchQuery {
@@ -92,8 +293,123 @@ impl Default for ClipboardSearchQuery {
             content_type: None,
             source: None,
             search_text: None,
+            match_mode: MatchMode::default(),
+            order: HistoryOrder::default(),
+            include_sensitive: false,
             limit: 100,
             offset: 0,
+            after: None,
+        }
+    }
+}
+
+/// Position in history ordered by last use. Re-copying an entry bumps its
+/// timestamp but keeps its id, so both are needed to see it again.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct HistoryCursor {
+    pub timestamp_millis: i64,
+    pub id: i64,
+}
+
+impl HistoryCursor {
+    pub fn of(entry: &ClipboardEntry) -> Self {
+        Self {
+            timestamp_millis: entry.timestamp.timestamp_millis(),
+            id: entry.id.unwrap_or(0),
         }
     }
 }
+
+/// Aggregate statistics over the stored history
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ClipboardStats {
+    pub total: i64,
+    pub by_type: BTreeMap<String, i64>,
+    pub by_source: BTreeMap<String, i64>,
+    pub oldest: Option<DateTime<Utc>>, // Earliest created_at
+    pub newest: Option<DateTime<Utc>>, // Latest timestamp (last used)
+    #[serde(default)]
+    pub total_bytes: i64, // Sum of decoded entry sizes
+}
+
+/// How inserts were resolved since the storage was opened; not persisted
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct DedupStats {
+    pub new_entries: u64, // Inserts that created a row
+    pub deduped: u64,     // Inserts that matched an existing checksum
+}
+
+/// What `ClipboardStorage::insert_batch` did with its entries
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BatchResult {
+    pub inserted: usize,
+    pub skipped: usize, // Checksum already stored, or repeated earlier in the batch
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_content_type_serializes_as_its_name() {
+        let json = serde_json::to_string(&ClipboardContentType::Text).unwrap();
+        assert_eq!(json, r#""text""#);
+        let json = serde_json::to_string(&ClipboardContentType::from("text/x-custom")).unwrap();
+        assert_eq!(json, r#""text/x-custom""#);
+
+        let parsed: ClipboardContentType = serde_json::from_str(r#""text/x-custom""#).unwrap();
+        assert_eq!(parsed.as_str(), "text/x-custom");
+        let legacy: ClipboardContentType = serde_json::from_str(r#""Html""#).unwrap();
+        assert!(matches!(legacy, ClipboardContentType::Html));
+    }
+
+    #[test]
+    fn test_grep_matches_decoded_html_and_skips_images() {
+        let html = ClipboardEntry::new(
+            ClipboardContentType::Html,
+            "<p>Hello <b>big</b> &amp; bold</p>".to_string(),
+            "macos".to_string(),
+        );
+        assert!(html.grep_matches("hello big & BOLD"));
+        assert!(!html.grep_matches("<b>"));
+
+        let image = ClipboardEntry::new(
+            ClipboardContentType::Image,
+            "aGVsbG8=".to_string(),
+            "macos".to_string(),
+        );
+        assert!(!image.grep_matches("aGVs"));
+
+        assert_eq!(grep_prefilter("hello big & bold"), "hello");
+    }
+
+    #[test]
+    fn test_unknown_content_type_round_trips() {
+        assert!(matches!(ClipboardContentType::from("html"), ClipboardContentType::Html));
+
+        let unknown = ClipboardContentType::from("application/x-future");
+        assert_eq!(unknown.as_str(), "application/x-future");
+
+        // Unknown content is opaque, never treated as text
+        let entry = ClipboardEntry::new(unknown, "secret".to_string(), "macos".to_string());
+        assert!(!entry.grep_matches("secret"));
+    }
+
+    #[test]
+    fn test_size_is_decoded_length() {
+        // "hello" is 5 bytes, 8 once base64 encoded
+        let image = ClipboardEntry::new(
+            ClipboardContentType::Image,
+            "aGVsbG8=".to_string(),
+            "macos".to_string(),
+        );
+        assert_eq!(image.size_bytes, 5);
+
+        let text = ClipboardEntry::new(
+            ClipboardContentType::Text,
+            "héllo".to_string(),
+            "macos".to_string(),
+        );
+        assert_eq!(text.size_bytes, 6);
+    }
+}
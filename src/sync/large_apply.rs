@@ -0,0 +1,153 @@
+use crate::clipboard::ClipboardContent;
+use crate::config::{LargeApplyAction, LargeApplyDefault, SyncConfig};
+use std::io::{BufRead, IsTerminal};
+use std::sync::OnceLock;
+use std::time::Duration;
+use tokio::sync::{mpsc, Mutex};
+use tracing::warn;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Check {
+    Apply,
+    Skip,
+    Ask,
+}
+
+/// `sync.confirm_large_apply_bytes`, checked before a received update is
+/// written to the local clipboard
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LargeApplyGuard {
+    threshold: Option<usize>,
+    action: LargeApplyAction,
+    fallback: LargeApplyDefault,
+    prompt_timeout: Duration,
+}
+
+impl LargeApplyGuard {
+    pub fn from_config(sync: &SyncConfig) -> Self {
+        Self {
+            threshold: sync.confirm_large_apply_bytes,
+            action: sync.large_apply,
+            fallback: sync.large_apply_default,
+            prompt_timeout: Duration::from_secs(sync.large_apply_prompt_timeout_secs),
+        }
+    }
+
+    /// Whether `content` may be applied. Oversized updates are logged and,
+    /// depending on `sync.large_apply`, confirmed on the terminal or dropped.
+    pub async fn allow(&self, content: &ClipboardContent) -> bool {
+        let size = content.size_bytes();
+        if self.threshold.is_none_or(|limit| size <= limit) {
+            return true;
+        }
+
+        let check = self.check(size, std::io::stdin().is_terminal());
+        let description = content.preview(0);
+        warn!(
+            "⚠️  Received {} is over sync.confirm_large_apply_bytes ({} bytes)",
+            description,
+            self.threshold.unwrap_or_default()
+        );
+        match check {
+            Check::Apply => true,
+            Check::Skip => {
+                warn!("⚠️  Skipped applying {} to the clipboard", description);
+                false
+            }
+            Check::Ask => {
+                let accepted = match confirm(&description, self.prompt_timeout).await {
+                    Some(accepted) => accepted,
+                    None => {
+                        warn!(
+                            "⚠️  No answer within {}s, using sync.large_apply_default",
+                            self.prompt_timeout.as_secs()
+                        );
+                        self.fallback == LargeApplyDefault::Apply
+                    }
+                };
+                if !accepted {
+                    warn!("⚠️  Skipped applying {} to the clipboard", description);
+                }
+                accepted
+            }
+        }
+    }
+
+    fn check(&self, size: usize, interactive: bool) -> Check {
+        match self.threshold {
+            Some(limit) if size > limit => match self.action {
+                LargeApplyAction::Apply => Check::Apply,
+                LargeApplyAction::Skip => Check::Skip,
+                LargeApplyAction::Prompt if interactive => Check::Ask,
+                LargeApplyAction::Prompt => match self.fallback {
+                    LargeApplyDefault::Apply => Check::Apply,
+                    LargeApplyDefault::Skip => Check::Skip,
+                },
+            },
+            _ => Check::Apply,
+        }
+    }
+}
+
+/// Lines typed on stdin. One thread reads them for the life of the process,
+/// so a prompt that timed out doesn't leave a read behind that swallows the
+/// next answer; the lock also keeps concurrent prompts from interleaving.
+fn answers() -> &'static Mutex<mpsc::UnboundedReceiver<String>> {
+    static ANSWERS: OnceLock<Mutex<mpsc::UnboundedReceiver<String>>> = OnceLock::new();
+    ANSWERS.get_or_init(|| {
+        let (tx, rx) = mpsc::unbounded_channel();
+        std::thread::spawn(move || {
+            for line in std::io::stdin().lock().lines() {
+                let Ok(line) = line else { break };
+                if tx.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+        Mutex::new(rx)
+    })
+}
+
+/// Ask on the terminal; anything but "y" declines. `None` when nobody
+/// answered within `wait` or stdin closed.
+async fn confirm(description: &str, wait: Duration) -> Option<bool> {
+    let mut answers = answers().lock().await;
+    // Lines typed while no prompt was showing don't answer this one
+    while answers.try_recv().is_ok() {}
+    eprintln!(
+        "Apply received {} to the clipboard? (y/N, {}s)",
+        description,
+        wait.as_secs()
+    );
+    match tokio::time::timeout(wait, answers.recv()).await {
+        Ok(Some(input)) => Some(input.trim().eq_ignore_ascii_case("y")),
+        Ok(None) | Err(_) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_only_updates_over_the_threshold_are_checked() {
+        let guard = |action| LargeApplyGuard {
+            threshold: Some(1024),
+            action,
+            ..LargeApplyGuard::default()
+        };
+
+        assert_eq!(LargeApplyGuard::default().check(usize::MAX, true), Check::Apply);
+        assert_eq!(guard(LargeApplyAction::Skip).check(1024, true), Check::Apply);
+        assert_eq!(guard(LargeApplyAction::Skip).check(1025, true), Check::Skip);
+        assert_eq!(guard(LargeApplyAction::Apply).check(1025, true), Check::Apply);
+        assert_eq!(guard(LargeApplyAction::Prompt).check(1025, true), Check::Ask);
+        // Nobody to ask, e.g. under systemd
+        assert_eq!(guard(LargeApplyAction::Prompt).check(1025, false), Check::Skip);
+        let apply_unattended = LargeApplyGuard {
+            fallback: LargeApplyDefault::Apply,
+            ..guard(LargeApplyAction::Prompt)
+        };
+        assert_eq!(apply_unattended.check(1025, false), Check::Apply);
+    }
+}
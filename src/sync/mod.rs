@@ -1 +1,112 @@
+pub mod large_apply;
+pub mod outbound;
+pub mod pause;
 pub mod protocol;
+pub mod replace;
+pub mod transport;
+
+use crate::clipboard::{ClipboardContent, ClipboardManager};
+use crate::config::FrameFormat;
+use crate::storage::models::strip_html;
+use protocol::WireFormat;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// protocol.rs is also built on its own (fuzz targets), so it can't see config
+impl From<FrameFormat> for WireFormat {
+    fn from(format: FrameFormat) -> Self {
+        match format {
+            FrameFormat::Json => WireFormat::Json,
+            FrameFormat::Msgpack => WireFormat::MessagePack,
+        }
+    }
+}
+
+/// How long after applying a received update the same content is treated as
+/// an echo rather than a local copy
+const SUPPRESSION_WINDOW: Duration = Duration::from_secs(5);
+
+/// Checksums of content recently written to the local clipboard by a received
+/// update. Shared by the client, the server and the clipboard monitor so a
+/// daemon in both modes doesn't send what it just applied straight back out.
+#[derive(Clone, Default)]
+pub struct RecentlyApplied(Arc<Mutex<HashMap<String, Instant>>>);
+
+impl RecentlyApplied {
+    pub fn record(&self, content: &ClipboardContent) {
+        let checksum = content.checksum();
+        let mut applied = self.0.lock().unwrap();
+        applied.retain(|_, at| at.elapsed() < SUPPRESSION_WINDOW);
+        applied.insert(checksum, Instant::now());
+    }
+
+    /// Record `content` after writing it to `clipboard`. Images are
+    /// re-encoded on read, so remember what the monitor will actually see.
+    /// HTML is also remembered as its plain-text alternate, which is what
+    /// the monitor reads back.
+    pub fn record_applied(&self, clipboard: &mut ClipboardManager, content: &ClipboardContent) {
+        match content {
+            ClipboardContent::Image(_) => match clipboard.get_content() {
+                Ok(Some(read_back)) => self.record(&read_back),
+                _ => self.record(content),
+            },
+            ClipboardContent::Html(html) => {
+                self.record(content);
+                self.record(&ClipboardContent::Text(strip_html(html)));
+            }
+            _ => self.record(content),
+        }
+    }
+
+    /// Whether `content` is something we just applied; a match is consumed
+    pub fn is_echo(&self, content: &ClipboardContent) -> bool {
+        let checksum = content.checksum();
+        self.0
+            .lock()
+            .unwrap()
+            .remove(&checksum)
+            .is_some_and(|at| at.elapsed() < SUPPRESSION_WINDOW)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_applied_content_is_suppressed_once() {
+        let applied = RecentlyApplied::default();
+        let received = ClipboardContent::Text("from the server".to_string());
+        let local = ClipboardContent::Text("copied here".to_string());
+
+        applied.record(&received);
+        assert!(!applied.is_echo(&local));
+        assert!(applied.is_echo(&received));
+        // Copying the same text again later is a real change
+        assert!(!applied.is_echo(&received));
+    }
+
+    #[test]
+    fn test_updates_applied_by_client_and_server_are_both_suppressed() {
+        // In both mode the server applies one peer's update and the client
+        // another's before the monitor polls; neither may be re-sent
+        let applied = RecentlyApplied::default();
+        let from_peer = ClipboardContent::Text("from a connected client".to_string());
+        let from_upstream = ClipboardContent::Text("from the upstream server".to_string());
+
+        applied.clone().record(&from_peer);
+        applied.clone().record(&from_upstream);
+
+        assert!(applied.is_echo(&from_peer));
+        assert!(applied.is_echo(&from_upstream));
+    }
+
+    #[test]
+    fn test_configured_frame_formats_match_protocol_names() {
+        for format in [FrameFormat::Json, FrameFormat::Msgpack] {
+            let configured = serde_json::to_value(format).unwrap();
+            assert_eq!(configured, WireFormat::from(format).name());
+        }
+    }
+}
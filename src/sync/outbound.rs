@@ -0,0 +1,81 @@
+use crate::sync::protocol::Message;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::Notify;
+use tracing::debug;
+
+/// The clipboard update waiting for the client to send it. A single
+/// latest-value slot: a newer update replaces one not yet sent, so after a
+/// disconnect only the current clipboard goes out, and the monitor never
+/// blocks on a stalled connection.
+#[derive(Clone, Default)]
+pub struct LatestUpdate {
+    shared: Arc<Shared>,
+}
+
+#[derive(Default)]
+struct Shared {
+    slot: Mutex<Option<Message>>,
+    ready: Notify,
+    coalesced: AtomicU64,
+}
+
+impl LatestUpdate {
+    /// Set the update to send without waiting, returning the unsent one it
+    /// replaced
+    pub fn push(&self, message: Message) -> Option<Message> {
+        let replaced = self.shared.slot.lock().unwrap().replace(message);
+        self.shared.ready.notify_one();
+
+        if replaced.is_some() {
+            let total = self.shared.coalesced.fetch_add(1, Ordering::Relaxed) + 1;
+            debug!("Replaced an unsent clipboard update with a newer one ({} so far)", total);
+        }
+        replaced
+    }
+
+    /// Wait for the next update. Cancel-safe: the slot is only emptied when
+    /// its update is returned.
+    pub async fn pop(&self) -> Message {
+        loop {
+            if let Some(message) = self.shared.slot.lock().unwrap().take() {
+                return message;
+            }
+            self.shared.ready.notified().await;
+        }
+    }
+
+    #[cfg(test)]
+    pub fn is_empty(&self) -> bool {
+        self.shared.slot.lock().unwrap().is_none()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn update(text: &str) -> Message {
+        Message::clipboard_update("text".into(), text.into(), "test".into(), text.into(), None)
+    }
+
+    fn content(message: &Message) -> &str {
+        match message {
+            Message::ClipboardUpdate { content, .. } => content,
+            _ => panic!("not an update"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_newer_update_replaces_unsent_one() {
+        let latest = LatestUpdate::default();
+        assert!(latest.push(update("one")).is_none());
+
+        // Doesn't block; the unsent update makes way for the newest
+        let replaced = latest.push(update("two")).unwrap();
+        assert_eq!(content(&replaced), "one");
+
+        assert_eq!(content(&latest.pop().await), "two");
+        assert!(latest.is_empty());
+    }
+}
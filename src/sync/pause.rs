@@ -0,0 +1,43 @@
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::info;
+
+/// How often a running daemon looks for the flag file `clippy pause` creates
+const CHECK_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Whether sync is paused. Shared by the monitors, which stop reading and
+/// sending, and the client and server, which stop applying received updates.
+#[derive(Clone, Default)]
+pub struct PauseFlag(Arc<AtomicBool>);
+
+impl PauseFlag {
+    pub fn is_paused(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Set the state, returning whether it changed
+    pub fn set(&self, paused: bool) -> bool {
+        self.0.swap(paused, Ordering::Relaxed) != paused
+    }
+
+    /// Follow the flag file at `path`: paused while it exists
+    pub fn watch(&self, path: PathBuf) -> tokio::task::JoinHandle<()> {
+        let flag = self.clone();
+        tokio::spawn(async move {
+            loop {
+                let paused = path.exists();
+                if flag.set(paused) {
+                    if paused {
+                        info!("⏸ Sync paused; run `clippy resume` to continue");
+                    } else {
+                        info!("▶ Sync resumed");
+                    }
+                }
+                tokio::time::sleep(CHECK_INTERVAL).await;
+            }
+        })
+    }
+}
+
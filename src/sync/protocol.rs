@@ -1,46 +1,213 @@
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 
+/// Version of the wire format; bumped on incompatible changes to `Message`
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Clipboard payloads larger than this (base64 bytes) are sent zstd-compressed
+pub const COMPRESSION_THRESHOLD: usize = 256 * 1024;
+const COMPRESSION_LEVEL: i32 = 3;
+
+/// Compression of clipboard updates, agreed per connection: the client
+/// lists what it can decode in `Hello` and the server picks one in
+/// `HelloAck`. Peers that haven't negotiated are sent plain updates.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Compression {
+    #[default]
+    None,
+    Zstd,
+}
+
+impl Compression {
+    /// Algorithms this build can decode, most preferred first
+    const SUPPORTED: &'static [Compression] = &[Compression::Zstd];
+
+    pub fn name(self) -> Option<&'static str> {
+        match self {
+            Compression::None => None,
+            Compression::Zstd => Some("zstd"),
+        }
+    }
+
+    /// Names to list in `Hello`
+    pub fn advertised() -> Vec<String> {
+        Self::SUPPORTED
+            .iter()
+            .filter_map(|c| c.name().map(String::from))
+            .collect()
+    }
+
+    /// Our most preferred algorithm among the peer's; `None` without overlap
+    pub fn negotiate(offered: &[String]) -> Self {
+        Self::SUPPORTED
+            .iter()
+            .copied()
+            .find(|c| c.name().is_some_and(|name| offered.iter().any(|o| o == name)))
+            .unwrap_or_default()
+    }
+}
+
+/// First byte of a MessagePack frame body. JSON bodies always start with
+/// `{` or `"`, so a frame's format is known from its first byte.
+const MSGPACK_MARKER: u8 = 0x01;
+
+/// Serialization of frames, agreed in the same handshake as `Compression`.
+/// JSON is the default and what un-negotiated peers get; MessagePack is
+/// smaller and carries base64 content as raw bytes. Receivers read either.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WireFormat {
+    #[default]
+    #[serde(rename = "json")]
+    Json,
+    #[serde(rename = "msgpack")]
+    MessagePack,
+}
+
+impl WireFormat {
+    pub fn name(self) -> &'static str {
+        match self {
+            WireFormat::Json => "json",
+            WireFormat::MessagePack => "msgpack",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        [WireFormat::Json, WireFormat::MessagePack]
+            .into_iter()
+            .find(|format| format.name() == name)
+    }
+
+    /// The first of the peer's formats, in its order of preference, that
+    /// this build can write; JSON without overlap
+    pub fn negotiate(offered: &[String]) -> Self {
+        offered
+            .iter()
+            .find_map(|name| Self::from_name(name))
+            .unwrap_or_default()
+    }
+}
+
+/// Requests may carry an optional `request_id`, which the server echoes on
+/// the matching response (`ClipboardAck`, `HistoryResponse`, `StatsResponse`
+/// or `Error`) so a client can correlate several requests in flight.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Message {
     // Authentication
     Auth { token: String },
     AuthResponse { success: bool, message: String },
 
+    // Handshake, sent by the client once connected (and authenticated)
+    Hello {
+        /// Compression algorithms the client can decode, by name
+        compression: Vec<String>,
+        /// Wire formats the client would like frames in, most preferred first
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        formats: Vec<String>,
+    },
+    HelloAck {
+        /// The algorithm both sides use for this connection; none if unset
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        compression: Option<String>,
+        /// The wire format both sides use for this connection; JSON if unset
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        format: Option<String>,
+    },
+
     // Clipboard sync
     ClipboardUpdate {
         content_type: String,
+        #[serde(with = "base64_content")]
         content: String, // Base64 encoded
         timestamp: DateTime<Utc>,
         source: String,
         checksum: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        metadata: Option<String>, // JSON, e.g. the MIME type of "other" content
+    },
+    /// Same as `ClipboardUpdate`, with `content` zstd-compressed and then
+    /// base64 encoded. `original_len` is the length of the uncompressed
+    /// base64 content and `checksum` is that of the uncompressed content.
+    ClipboardUpdateCompressed {
+        content_type: String,
+        #[serde(with = "base64_content")]
+        content: String,
+        original_len: usize,
+        timestamp: DateTime<Utc>,
+        source: String,
+        checksum: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        metadata: Option<String>,
     },
     ClipboardAck {
         checksum: String,
         success: bool,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        request_id: Option<u64>,
     },
 
     // History requests
     HistoryRequest {
         limit: usize,
         offset: usize,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        request_id: Option<u64>,
     },
     HistoryResponse {
         entries: Vec<HistoryEntry>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        request_id: Option<u64>,
+    },
+
+    // Re-broadcast a stored history entry to connected clients
+    Replay {
+        id: i64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        request_id: Option<u64>,
+    },
+
+    // Statistics requests
+    GetStats {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        request_id: Option<u64>,
+    },
+    StatsResponse {
+        total: i64,
+        by_type: BTreeMap<String, i64>,
+        by_source: BTreeMap<String, i64>,
+        oldest: Option<DateTime<Utc>>,
+        newest: Option<DateTime<Utc>>,
+        /// Inserts since the server started that created a row, and that
+        /// matched an existing checksum
+        #[serde(default)]
+        new_entries: u64,
+        #[serde(default)]
+        deduped: u64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        request_id: Option<u64>,
     },
 
     // Heartbeat
     Ping,
     Pong,
 
+    /// Sent by a client just before it closes the connection on shutdown
+    Bye,
+
     // Error
-    Error { message: String },
+    Error {
+        message: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        request_id: Option<u64>,
+    },
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct HistoryEntry {
     pub id: i64,
     pub content_type: String,
+    #[serde(with = "base64_content")]
     pub content: String,
     pub source: String,
     pub timestamp: DateTime<Utc>,
@@ -56,13 +223,131 @@ impl Message {
         Ok(serde_json::from_str(json)?)
     }
 
+    /// The correlation ID of a request, to echo on its response
+    pub fn request_id(&self) -> Option<u64> {
+        match self {
+            Message::HistoryRequest { request_id, .. }
+            | Message::Replay { request_id, .. }
+            | Message::GetStats { request_id } => *request_id,
+            _ => None,
+        }
+    }
+
+    /// Build a plain clipboard update; `encoded_for` compresses it for a
+    /// connection that negotiated zstd
+    pub fn clipboard_update(
+        content_type: String,
+        content: String,
+        source: String,
+        checksum: String,
+        metadata: Option<String>,
+    ) -> Self {
+        Message::ClipboardUpdate {
+            content_type,
+            content,
+            timestamp: Utc::now(),
+            source,
+            checksum,
+            metadata,
+        }
+    }
+
+    /// Encode a clipboard update for a connection that negotiated
+    /// `compression`: zstd above `COMPRESSION_THRESHOLD`, otherwise plain.
+    /// Other messages are returned unchanged.
+    pub fn encoded_for(self, compression: Compression) -> anyhow::Result<Self> {
+        match (compression, self) {
+            (Compression::None, message) => message.decompressed(usize::MAX),
+            (
+                Compression::Zstd,
+                Message::ClipboardUpdate {
+                    content_type,
+                    content,
+                    timestamp,
+                    source,
+                    checksum,
+                    metadata,
+                },
+            ) if content.len() > COMPRESSION_THRESHOLD => {
+                let compressed = zstd::bulk::compress(content.as_bytes(), COMPRESSION_LEVEL)?;
+                Ok(Message::ClipboardUpdateCompressed {
+                    content_type,
+                    content: BASE64.encode(compressed),
+                    original_len: content.len(),
+                    timestamp,
+                    source,
+                    checksum,
+                    metadata,
+                })
+            }
+            (Compression::Zstd, message) => Ok(message),
+        }
+    }
+
+    /// Turn a `ClipboardUpdateCompressed` into a plain `ClipboardUpdate`;
+    /// other messages are returned unchanged. Payloads claiming more than
+    /// `max_len` bytes are rejected before decompressing.
+    pub fn decompressed(self, max_len: usize) -> anyhow::Result<Self> {
+        match self {
+            Message::ClipboardUpdateCompressed {
+                content_type,
+                content,
+                original_len,
+                timestamp,
+                source,
+                checksum,
+                metadata,
+            } => {
+                if original_len > max_len {
+                    anyhow::bail!(
+                        "compressed clipboard update too large ({} bytes, max {})",
+                        original_len,
+                        max_len
+                    );
+                }
+
+                let compressed = BASE64.decode(content)?;
+                let bytes = zstd::bulk::decompress(&compressed, original_len)?;
+                if bytes.len() != original_len {
+                    anyhow::bail!(
+                        "compressed clipboard update length mismatch ({} != {})",
+                        bytes.len(),
+                        original_len
+                    );
+                }
+
+                Ok(Message::ClipboardUpdate {
+                    content_type,
+                    content: String::from_utf8(bytes)?,
+                    timestamp,
+                    source,
+                    checksum,
+                    metadata,
+                })
+            }
+            other => Ok(other),
+        }
+    }
+
     /// Serialize message with length prefix for TCP streaming
     pub fn to_bytes(&self) -> anyhow::Result<Vec<u8>> {
-        let json = self.to_json()?;
-        let len = json.len() as u32;
-        let mut bytes = Vec::with_capacity(4 + json.len());
+        self.to_bytes_as(WireFormat::Json)
+    }
+
+    /// Serialize to length-prefixed bytes in `format`
+    pub fn to_bytes_as(&self, format: WireFormat) -> anyhow::Result<Vec<u8>> {
+        let body = match format {
+            WireFormat::Json => self.to_json()?.into_bytes(),
+            WireFormat::MessagePack => {
+                let mut body = vec![MSGPACK_MARKER];
+                rmp_serde::encode::write_named(&mut body, self)?;
+                body
+            }
+        };
+        let len = body.len() as u32;
+        let mut bytes = Vec::with_capacity(4 + body.len());
         bytes.extend_from_slice(&len.to_be_bytes());
-        bytes.extend_from_slice(json.as_bytes());
+        bytes.extend_from_slice(&body);
         Ok(bytes)
     }
 
@@ -73,15 +358,65 @@ impl Message {
         }
 
         let len = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize;
+        // Checked so a huge prefix can't overflow on 32-bit targets
+        let end = len
+            .checked_add(4)
+            .filter(|&end| end <= bytes.len())
+            .ok_or_else(|| anyhow::anyhow!("Insufficient bytes for message body"))?;
 
-        if bytes.len() < 4 + len {
-            return Err(anyhow::anyhow!("Insufficient bytes for message body"));
+        let body = &bytes[4..end];
+        let message = match body.split_first() {
+            Some((&MSGPACK_MARKER, msgpack)) => rmp_serde::from_slice(msgpack)?,
+            _ => Self::from_json(std::str::from_utf8(body)?)?,
+        };
+
+        Ok((message, end))
+    }
+}
+
+/// Base64 content fields: a string in JSON, the decoded bytes in binary
+/// formats so they aren't base64 inside the frame
+mod base64_content {
+    use super::{Engine, BASE64};
+    use serde::de::{self, Visitor};
+    use serde::{Deserializer, Serializer};
+    use std::fmt;
+
+    pub fn serialize<S: Serializer>(content: &str, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            return serializer.serialize_str(content);
         }
+        // Content that isn't valid base64 is passed on as sent
+        match BASE64.decode(content) {
+            Ok(bytes) => serializer.serialize_bytes(&bytes),
+            Err(_) => serializer.serialize_str(content),
+        }
+    }
 
-        let json = std::str::from_utf8(&bytes[4..4 + len])?;
-        let message = Self::from_json(json)?;
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+        struct ContentVisitor;
 
-        Ok((message, 4 + len))
+        impl Visitor<'_> for ContentVisitor {
+            type Value = String;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("base64 text or raw bytes")
+            }
+
+            fn visit_str<E: de::Error>(self, s: &str) -> Result<String, E> {
+                Ok(s.to_string())
+            }
+
+            fn visit_bytes<E: de::Error>(self, bytes: &[u8]) -> Result<String, E> {
+                Ok(BASE64.encode(bytes))
+            }
+        }
+
+        if deserializer.is_human_readable() {
+            deserializer.deserialize_str(ContentVisitor)
+        } else {
+            deserializer.deserialize_any(ContentVisitor)
+        }
     }
 }
 
@@ -99,6 +434,14 @@ mod tests {
         matches!(decoded, Message::Ping);
     }
 
+    #[test]
+    fn test_bye_round_trip() {
+        let bytes = Message::Bye.to_bytes().unwrap();
+        let (decoded, size) = Message::from_bytes(&bytes).unwrap();
+        assert_eq!(size, bytes.len());
+        assert!(matches!(decoded, Message::Bye));
+    }
+
     #[test]
     fn test_clipboard_update_message() {
         let msg = Message::ClipboardUpdate {
@@ -107,6 +450,7 @@ mod tests {
             timestamp: Utc::now(),
             source: "macos".to_string(),
             checksum: "abc123".to_string(),
+            metadata: None,
         };
 
         let bytes = msg.to_bytes().unwrap();
@@ -119,4 +463,207 @@ mod tests {
             _ => panic!("Wrong message type"),
         }
     }
+
+    #[test]
+    fn test_stats_response_round_trip() {
+        let mut by_type = BTreeMap::new();
+        by_type.insert("text".to_string(), 3);
+        by_type.insert("image".to_string(), 1);
+
+        let msg = Message::StatsResponse {
+            total: 4,
+            by_type,
+            by_source: BTreeMap::new(),
+            oldest: Some(Utc::now()),
+            newest: None,
+            new_entries: 5,
+            deduped: 2,
+            request_id: Some(7),
+        };
+
+        let bytes = msg.to_bytes().unwrap();
+        let (decoded, _) = Message::from_bytes(&bytes).unwrap();
+
+        match decoded {
+            Message::StatsResponse {
+                total,
+                by_type,
+                oldest,
+                newest,
+                deduped,
+                request_id,
+                ..
+            } => {
+                assert_eq!(total, 4);
+                assert_eq!(deduped, 2);
+                assert_eq!(request_id, Some(7));
+                assert_eq!(by_type.get("image"), Some(&1));
+                assert!(oldest.is_some());
+                assert!(newest.is_none());
+            }
+            _ => panic!("Wrong message type"),
+        }
+    }
+
+    #[test]
+    fn test_requests_without_request_id_still_parse() {
+        let legacy = [
+            r#"{"HistoryRequest":{"limit":10,"offset":0}}"#,
+            r#"{"Replay":{"id":42}}"#,
+        ];
+        for json in legacy {
+            assert_eq!(Message::from_json(json).unwrap().request_id(), None);
+        }
+
+        let json = Message::GetStats { request_id: None }.to_json().unwrap();
+        assert!(!json.contains("request_id"));
+    }
+
+    #[test]
+    fn test_malformed_frames_are_errors_not_panics() {
+        let frames: [&[u8]; 5] = [
+            b"",
+            &[0xff, 0xff, 0xff, 0xff, b'{'],    // Length far past the end
+            &[0, 0, 0, 2, 0xc3, 0x28],          // Invalid UTF-8
+            &[0, 0, 0, 4, b'n', b'u', b'l', b'l'], // Valid JSON, not a message
+            &[0, 0, 0, 1, b'{', b'"'],          // Truncated JSON
+        ];
+        for frame in frames {
+            assert!(Message::from_bytes(frame).is_err());
+        }
+    }
+
+    #[test]
+    fn test_large_update_is_compressed_and_round_trips() {
+        let content = "A".repeat(COMPRESSION_THRESHOLD + 1);
+        let msg = Message::clipboard_update(
+            "image".to_string(),
+            content.clone(),
+            "macos".to_string(),
+            "abc123".to_string(),
+            None,
+        )
+        .encoded_for(Compression::Zstd)
+        .unwrap();
+
+        let bytes = msg.to_bytes().unwrap();
+        assert!(bytes.len() < content.len());
+
+        let (decoded, _) = Message::from_bytes(&bytes).unwrap();
+        assert!(matches!(decoded, Message::ClipboardUpdateCompressed { .. }));
+        assert!(decoded.clone().decompressed(COMPRESSION_THRESHOLD).is_err());
+
+        match decoded.decompressed(usize::MAX).unwrap() {
+            Message::ClipboardUpdate { content: c, checksum, .. } => {
+                assert_eq!(c, content);
+                assert_eq!(checksum, "abc123");
+            }
+            _ => panic!("Wrong message type"),
+        }
+    }
+
+    #[test]
+    fn test_compression_negotiation() {
+        let offer = |names: &[&str]| names.iter().map(|n| n.to_string()).collect::<Vec<_>>();
+        assert_eq!(Compression::negotiate(&Compression::advertised()), Compression::Zstd);
+        assert_eq!(Compression::negotiate(&offer(&["brotli", "zstd"])), Compression::Zstd);
+        // No overlap, or a peer predating negotiation: plain updates
+        assert_eq!(Compression::negotiate(&offer(&["brotli"])), Compression::None);
+        assert_eq!(Compression::negotiate(&[]), Compression::None);
+
+        let large = Message::clipboard_update(
+            "text".to_string(),
+            "A".repeat(COMPRESSION_THRESHOLD + 1),
+            "macos".to_string(),
+            "abc123".to_string(),
+            None,
+        );
+        assert!(matches!(large, Message::ClipboardUpdate { .. }));
+        let compressed = large.encoded_for(Compression::Zstd).unwrap();
+        assert!(matches!(compressed, Message::ClipboardUpdateCompressed { .. }));
+        let plain = compressed.encoded_for(Compression::None).unwrap();
+        assert!(matches!(plain, Message::ClipboardUpdate { .. }));
+    }
+
+    #[test]
+    fn test_wire_formats_round_trip() {
+        let image = BASE64.encode([0u8, 159, 146, 150, 255]);
+        let messages = [
+            Message::ClipboardUpdate {
+                content_type: "image".to_string(),
+                content: image.clone(),
+                timestamp: Utc::now(),
+                source: "macos".to_string(),
+                checksum: "abc123".to_string(),
+                metadata: None,
+            },
+            Message::GetStats { request_id: Some(3) },
+            Message::Ping,
+        ];
+
+        for format in [WireFormat::Json, WireFormat::MessagePack] {
+            // Two frames back to back, read without being told the format
+            let mut bytes = messages[0].to_bytes_as(format).unwrap();
+            let first_len = bytes.len();
+            bytes.extend(messages[1].to_bytes_as(format).unwrap());
+            bytes.extend(messages[2].to_bytes_as(format).unwrap());
+            assert_eq!(bytes[4] == MSGPACK_MARKER, format == WireFormat::MessagePack);
+
+            let (decoded, size) = Message::from_bytes(&bytes).unwrap();
+            assert_eq!(size, first_len);
+            match decoded {
+                Message::ClipboardUpdate { content, metadata, .. } => {
+                    assert_eq!(content, image);
+                    assert_eq!(metadata, None);
+                }
+                other => panic!("unexpected message {:?}", other),
+            }
+            let (decoded, second_len) = Message::from_bytes(&bytes[size..]).unwrap();
+            assert!(matches!(decoded, Message::GetStats { request_id: Some(3) }));
+            let (decoded, _) = Message::from_bytes(&bytes[size + second_len..]).unwrap();
+            assert!(matches!(decoded, Message::Ping));
+        }
+
+        // Content that isn't base64 survives the binary format unchanged
+        let odd = Message::Error {
+            message: "x".to_string(),
+            request_id: None,
+        };
+        assert!(Message::from_bytes(&odd.to_bytes_as(WireFormat::MessagePack).unwrap()).is_ok());
+        let entry = HistoryEntry {
+            id: 1,
+            content_type: "text".to_string(),
+            content: "not base64!".to_string(),
+            source: "nixos".to_string(),
+            timestamp: Utc::now(),
+            checksum: "abc".to_string(),
+        };
+        let response = Message::HistoryResponse {
+            entries: vec![entry],
+            request_id: None,
+        };
+        match Message::from_bytes(&response.to_bytes_as(WireFormat::MessagePack).unwrap()).unwrap().0 {
+            Message::HistoryResponse { entries, .. } => assert_eq!(entries[0].content, "not base64!"),
+            other => panic!("unexpected message {:?}", other),
+        }
+
+        let offer = |names: &[&str]| names.iter().map(|n| n.to_string()).collect::<Vec<_>>();
+        assert_eq!(WireFormat::negotiate(&offer(&["cbor", "msgpack"])), WireFormat::MessagePack);
+        assert_eq!(WireFormat::negotiate(&offer(&["cbor"])), WireFormat::Json);
+        assert_eq!(WireFormat::negotiate(&[]), WireFormat::Json);
+    }
+
+    #[test]
+    fn test_small_update_is_not_compressed() {
+        let msg = Message::clipboard_update(
+            "text".to_string(),
+            "hi".to_string(),
+            "macos".to_string(),
+            "abc123".to_string(),
+            None,
+        );
+
+        let msg = msg.encoded_for(Compression::Zstd).unwrap();
+        assert!(matches!(msg, Message::ClipboardUpdate { .. }));
+    }
 }
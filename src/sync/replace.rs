@@ -0,0 +1,128 @@
+use crate::clipboard::ClipboardContent;
+use crate::config::{ReplaceRule, RuleDirection};
+use anyhow::Result;
+use regex::Regex;
+use std::sync::Arc;
+use tracing::info;
+
+#[derive(Debug)]
+struct CompiledRule {
+    name: String,
+    regex: Regex,
+    replacement: String,
+    direction: RuleDirection,
+}
+
+/// `sync.replace_rules`, compiled once at startup (reusing the patterns
+/// compiled when the config was validated). Cheap to clone.
+#[derive(Debug, Clone, Default)]
+pub struct ReplaceRules(Arc<Vec<CompiledRule>>);
+
+impl ReplaceRules {
+    pub fn compile(rules: &[ReplaceRule]) -> Result<Self> {
+        let compiled = rules
+            .iter()
+            .enumerate()
+            .map(|(i, rule)| {
+                let regex = rule.regex(i)?.clone();
+                Ok(CompiledRule {
+                    name: rule.name.clone().unwrap_or_else(|| rule.pattern.clone()),
+                    regex,
+                    replacement: rule.replacement.clone(),
+                    direction: rule.direction,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self(Arc::new(compiled)))
+    }
+
+    /// Rewrite content about to be sent
+    pub fn on_send(&self, content: ClipboardContent) -> ClipboardContent {
+        self.apply(content, RuleDirection::Send)
+    }
+
+    /// Rewrite received content before it is applied
+    pub fn on_receive(&self, content: ClipboardContent) -> ClipboardContent {
+        self.apply(content, RuleDirection::Receive)
+    }
+
+    /// Run the matching rules in order over text and HTML; binary content
+    /// is never touched
+    fn apply(&self, content: ClipboardContent, direction: RuleDirection) -> ClipboardContent {
+        if self.0.is_empty() {
+            return content;
+        }
+        match content {
+            ClipboardContent::Text(text) => ClipboardContent::Text(self.rewrite(text, direction)),
+            ClipboardContent::Html(html) => ClipboardContent::Html(self.rewrite(html, direction)),
+            other => other,
+        }
+    }
+
+    fn rewrite(&self, mut text: String, direction: RuleDirection) -> String {
+        for rule in self.0.iter() {
+            if rule.direction != RuleDirection::Both && rule.direction != direction {
+                continue;
+            }
+            if rule.regex.is_match(&text) {
+                info!("Replace rule {:?} fired", rule.name);
+                text = rule.regex.replace_all(&text, rule.replacement.as_str()).into_owned();
+            }
+        }
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(pattern: &str, replacement: &str, direction: RuleDirection) -> ReplaceRule {
+        serde_json::from_value(serde_json::json!({
+            "pattern": pattern,
+            "replacement": replacement,
+            "direction": direction,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_rules_apply_in_order_for_their_direction() {
+        let rules = ReplaceRules::compile(&[
+            rule(r"/Users/(\w+)", "/home/$1", RuleDirection::Send),
+            rule(r"ghp_\w+", "[redacted]", RuleDirection::Both),
+            rule(r"/home", "/mnt/home", RuleDirection::Receive),
+        ])
+        .unwrap();
+
+        let text = ClipboardContent::Text("cd /Users/jo ghp_abc123".to_string());
+        match rules.on_send(text.clone()) {
+            ClipboardContent::Text(t) => assert_eq!(t, "cd /home/jo [redacted]"),
+            other => panic!("expected text, got {:?}", other),
+        }
+        match rules.on_receive(text) {
+            ClipboardContent::Text(t) => assert_eq!(t, "cd /Users/jo [redacted]"),
+            other => panic!("expected text, got {:?}", other),
+        }
+
+        let image = ClipboardContent::Image(b"ghp_abc123".to_vec());
+        match rules.on_send(image) {
+            ClipboardContent::Image(data) => assert_eq!(data, b"ghp_abc123"),
+            other => panic!("expected image, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_invalid_pattern_is_rejected() {
+        let err = ReplaceRules::compile(&[rule("(", "", RuleDirection::Both)]).unwrap_err();
+        assert!(err.to_string().contains("replace_rules[0]"));
+    }
+
+    #[test]
+    fn test_validated_patterns_are_not_compiled_again() {
+        let rules = [rule("a+", "b", RuleDirection::Both)];
+        let validated: *const Regex = rules[0].regex(0).unwrap();
+        assert!(std::ptr::eq(validated, rules[0].regex(0).unwrap()));
+        assert!(ReplaceRules::compile(&rules).is_ok());
+    }
+}
@@ -0,0 +1,138 @@
+//! Byte streams the sync protocol runs over: TCP, or a Unix domain socket
+//! when `server.unix_socket` / `client.unix_socket` is set.
+
+use anyhow::{Context, Result};
+use std::path::Path;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+
+/// A connected stream carrying framed `Message`s
+pub trait Stream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Stream for T {}
+
+/// Connect to the Unix socket at `unix_socket` if given, otherwise to TCP `addr`
+pub async fn connect(unix_socket: Option<&Path>, addr: &str) -> Result<Box<dyn Stream>> {
+    match unix_socket {
+        #[cfg(unix)]
+        Some(path) => {
+            let stream = tokio::net::UnixStream::connect(path)
+                .await
+                .with_context(|| format!("Failed to connect to {}", path.display()))?;
+            Ok(Box::new(stream))
+        }
+        #[cfg(not(unix))]
+        Some(_) => anyhow::bail!("Unix domain sockets are not supported on this platform"),
+        None => Ok(Box::new(TcpStream::connect(addr).await?)),
+    }
+}
+
+#[cfg(unix)]
+pub use unix::UnixSocketListener;
+
+#[cfg(unix)]
+mod unix {
+    use anyhow::{Context, Result};
+    use std::os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt};
+    use std::path::{Path, PathBuf};
+    use tokio::net::{UnixListener, UnixStream};
+
+    /// A listening Unix socket that removes its file when dropped
+    pub struct UnixSocketListener {
+        listener: UnixListener,
+        path: PathBuf,
+    }
+
+    impl UnixSocketListener {
+        /// Bind `path`, replacing a socket file left behind by an unclean
+        /// exit but not one a running server still answers on. Only the
+        /// owner may connect.
+        pub fn bind(path: &Path) -> Result<Self> {
+            if let Ok(metadata) = std::fs::symlink_metadata(path) {
+                if !metadata.file_type().is_socket() {
+                    anyhow::bail!("{} exists and is not a socket", path.display());
+                }
+                if std::os::unix::net::UnixStream::connect(path).is_ok() {
+                    anyhow::bail!("another server is already listening on {}", path.display());
+                }
+                std::fs::remove_file(path)
+                    .with_context(|| format!("Failed to remove stale socket {}", path.display()))?;
+            }
+
+            // Bound inside a directory only the owner can enter, so the
+            // socket is never reachable before its mode is tightened; then
+            // moved into place
+            let file_name = path
+                .file_name()
+                .with_context(|| format!("{} is not a file path", path.display()))?;
+            let staging = path.with_file_name(format!(
+                ".{}.{}",
+                file_name.to_string_lossy(),
+                std::process::id()
+            ));
+            std::fs::DirBuilder::new()
+                .mode(0o700)
+                .create(&staging)
+                .with_context(|| format!("Failed to create {}", staging.display()))?;
+            let staged = staging.join(file_name);
+            let bound = UnixListener::bind(&staged)
+                .with_context(|| format!("Failed to bind {}", path.display()))
+                .and_then(|listener| {
+                    std::fs::set_permissions(&staged, std::fs::Permissions::from_mode(0o600))?;
+                    std::fs::rename(&staged, path)?;
+                    Ok(listener)
+                });
+            let _ = std::fs::remove_file(&staged);
+            let _ = std::fs::remove_dir(&staging);
+
+            Ok(Self {
+                listener: bound?,
+                path: path.to_path_buf(),
+            })
+        }
+
+        pub async fn accept(&self) -> std::io::Result<UnixStream> {
+            self.listener.accept().await.map(|(stream, _)| stream)
+        }
+    }
+
+    impl Drop for UnixSocketListener {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[tokio::test]
+    async fn test_unix_socket_replaces_stale_file_and_cleans_up() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("clippy.sock");
+
+        // A socket left behind by a crashed server
+        drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+        assert!(path.exists());
+
+        let listener = UnixSocketListener::bind(&path).unwrap();
+        let (client, server) = tokio::join!(connect(Some(&path), "unused"), listener.accept());
+        assert!(client.is_ok() && server.is_ok());
+
+        // Only the owner may connect
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        // A live server's socket is left alone
+        assert!(UnixSocketListener::bind(&path).is_err());
+        assert!(path.exists());
+
+        drop(listener);
+        assert!(!path.exists());
+
+        std::fs::write(&path, "not a socket").unwrap();
+        assert!(UnixSocketListener::bind(&path).is_err());
+    }
+}
@@ -0,0 +1,98 @@
+use crate::clipboard::ClipboardContent;
+use crate::config::Config;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::time::Duration;
+use tracing::{debug, warn};
+
+/// Summary of a clipboard change POSTed to the configured webhook
+#[derive(Debug, Serialize)]
+struct ChangeNotification {
+    source: String,
+    content_type: String,
+    size: usize,
+    checksum: String,
+    timestamp: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    content: Option<String>, // Base64 encoded for binary content
+}
+
+/// Fire-and-forget notifier for `sync.on_change_webhook`
+#[derive(Clone)]
+pub struct ChangeNotifier {
+    url: String,
+    include_content: bool,
+    source: String,
+    client: reqwest::Client,
+}
+
+impl ChangeNotifier {
+    /// Returns `None` when no webhook is configured
+    pub fn from_config(config: &Config) -> Result<Option<Self>> {
+        let Some(url) = config.sync.on_change_webhook.clone() else {
+            return Ok(None);
+        };
+        reqwest::Url::parse(&url).with_context(|| format!("Invalid webhook URL {}", url))?;
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(3))
+            .build()
+            .context("Failed to build webhook HTTP client")?;
+
+        Ok(Some(Self {
+            url,
+            include_content: config.sync.include_content,
+            source: config.source_name(),
+            client,
+        }))
+    }
+
+    /// Send a change summary in the background; failures are only logged
+    pub fn notify(&self, content: &ClipboardContent, checksum: &str) {
+        let size = match content {
+            ClipboardContent::Text(text) | ClipboardContent::Html(text) => text.len(),
+            ClipboardContent::Image(data) | ClipboardContent::Other { data, .. } => data.len(),
+        };
+        let notification = ChangeNotification {
+            source: self.source.clone(),
+            content_type: content.content_type_str().to_string(),
+            size,
+            checksum: checksum.to_string(),
+            timestamp: Utc::now(),
+            content: self.include_content.then(|| content.to_base64()),
+        };
+
+        let client = self.client.clone();
+        let url = self.url.clone();
+        tokio::spawn(async move {
+            match client.post(&url).json(&notification).send().await {
+                Ok(response) if response.status().is_success() => {
+                    debug!("Notified webhook {}", url);
+                }
+                Ok(response) => {
+                    warn!("Webhook {} returned {}", url, response.status());
+                }
+                Err(e) => {
+                    warn!("Failed to notify webhook {}: {}", url, e);
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_notifier_is_only_built_for_a_valid_webhook() {
+        let mut config = Config::default();
+        assert!(ChangeNotifier::from_config(&config).unwrap().is_none());
+
+        config.sync.on_change_webhook = Some("http://127.0.0.1:9/changes".to_string());
+        assert!(ChangeNotifier::from_config(&config).unwrap().is_some());
+
+        config.sync.on_change_webhook = Some("not a url".to_string());
+        assert!(ChangeNotifier::from_config(&config).is_err());
+    }
+}